@@ -11,7 +11,11 @@ CREATE TABLE IF NOT EXISTS hop (
   query INTEGER NOT NULL,
   query_result TEXT,
   addr TEXT,
-  rtt TEXT,
+  rtt_us INTEGER,
+  reply_ttl INTEGER,
+  quoted_ttl INTEGER,
+  reverse_hops INTEGER,
+  hidden_hops INTEGER,
   trace INTEGER NOT NULL REFERENCES trace(id)
 );
 CREATE INDEX IF NOT EXISTS idx_hop_trace ON hop (trace);
@@ -19,18 +23,41 @@ CREATE INDEX IF NOT EXISTS idx_hop_trace ON hop (trace);
 CREATE TABLE IF NOT EXISTS trace (
   id INTEGER PRIMARY KEY AUTOINCREMENT,
   trace TEXT NOT NULL,
-  route INTEGER NOT NULL REFERENCES route(id)
+  route INTEGER NOT NULL REFERENCES route(id),
+  protocol TEXT NOT NULL DEFAULT 'udp',
+  mode TEXT NOT NULL DEFAULT 'classic',
+  stop_reason TEXT
 );
 CREATE INDEX IF NOT EXISTS idx_trace ON trace (trace);
 
 CREATE TABLE IF NOT EXISTS hop_stats (
   id INTEGER PRIMARY KEY AUTOINCREMENT,
   hop INTEGER NOT NULL REFERENCES hop(id),
-  mean_ms TEXT,
-  median_ms TEXT
+  mean_us INTEGER,
+  median_us INTEGER
 );
 CREATE INDEX IF NOT EXISTS idx_hop_stats ON hop_stats (hop);
 
+CREATE TABLE IF NOT EXISTS hop_mpls (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  hop INTEGER NOT NULL REFERENCES hop(id),
+  position INTEGER NOT NULL,
+  label INTEGER NOT NULL,
+  tc INTEGER NOT NULL,
+  s INTEGER NOT NULL,
+  ttl INTEGER NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_hop_mpls ON hop_mpls (hop, position);
+
+CREATE TABLE IF NOT EXISTS hop_modification (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  hop INTEGER NOT NULL REFERENCES hop(id),
+  field TEXT NOT NULL,
+  sent TEXT NOT NULL,
+  quoted TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_hop_modification ON hop_modification (hop);
+
 CREATE TABLE IF NOT EXISTS hop_geo (
   id INTEGER PRIMARY KEY AUTOINCREMENT,
   hop INTEGER NOT NULL REFERENCES hop(id),
@@ -48,3 +75,70 @@ CREATE TABLE IF NOT EXISTS hop_geo (
   asn TEXT,
   org TEXT
 );
+
+CREATE TABLE IF NOT EXISTS vertex (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  trace INTEGER NOT NULL REFERENCES trace(id),
+  ttl INTEGER NOT NULL,
+  addr TEXT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_vertex ON vertex (trace, ttl, addr);
+
+CREATE TABLE IF NOT EXISTS edge (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  trace INTEGER NOT NULL REFERENCES trace(id),
+  source INTEGER NOT NULL REFERENCES vertex(id),
+  destination INTEGER NOT NULL REFERENCES vertex(id)
+);
+CREATE INDEX IF NOT EXISTS idx_edge_trace ON edge (trace);
+
+CREATE TABLE IF NOT EXISTS hop_mtu (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  trace INTEGER NOT NULL REFERENCES trace(id),
+  ttl INTEGER NOT NULL,
+  mtu INTEGER NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_hop_mtu ON hop_mtu (trace, ttl);
+
+CREATE TABLE IF NOT EXISTS path_mtu (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  trace INTEGER NOT NULL REFERENCES trace(id),
+  mtu INTEGER NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_path_mtu ON path_mtu (trace);
+
+CREATE TABLE IF NOT EXISTS trace_config (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  trace INTEGER NOT NULL REFERENCES trace(id),
+  port INTEGER NOT NULL,
+  first_ttl INTEGER NOT NULL,
+  max_hops INTEGER NOT NULL,
+  tries INTEGER NOT NULL,
+  timeout_ms INTEGER NOT NULL,
+  wait_ms INTEGER NOT NULL,
+  size INTEGER,
+  tos INTEGER NOT NULL,
+  confidence REAL NOT NULL,
+  window_size INTEGER,
+  gap_limit INTEGER,
+  interface TEXT
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_trace_config ON trace_config (trace);
+
+CREATE TABLE IF NOT EXISTS probe_budget (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  day TEXT NOT NULL,
+  budget INTEGER NOT NULL DEFAULT 0,
+  used INTEGER NOT NULL DEFAULT 0
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_probe_budget ON probe_budget (day);
+
+CREATE TABLE IF NOT EXISTS trace_origin (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  trace INTEGER NOT NULL REFERENCES trace(id),
+  platform TEXT NOT NULL,
+  vantage_point TEXT NOT NULL,
+  measurement TEXT,
+  started_at INTEGER
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_trace_origin ON trace_origin (trace);
@@ -83,6 +83,6 @@ trace() {
 while IFS="," read -r -a ADDR; do
     for i in "${ADDR[@]}"; do
         msg_info "Scraping $i"
-        trace "$i" || echo "$i" >> ERROR.txt
+        trace "$i" || echo "$i,$?" >> ERROR.txt
     done
 done <<< $(tr '\n' ',' < "${args[0]}")
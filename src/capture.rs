@@ -0,0 +1,195 @@
+use pnet::packet::{ip::IpNextHeaderProtocols, ipv6::MutableIpv6Packet};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    net::Ipv6Addr,
+    path::Path,
+    sync::Mutex,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{Error, ReplyKind, Result};
+
+/// Block types of pcapng.
+pub(crate) const SECTION_HEADER: u32 = 0x0a0d_0d0a;
+pub(crate) const INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+pub(crate) const ENHANCED_PACKET: u32 = 0x0000_0006;
+
+/// Packets start with their IPv4 or IPv6 header.
+pub(crate) const LINKTYPE_RAW: u16 = 101;
+
+/// Option codes of pcapng.
+pub(crate) const OPT_END: u16 = 0;
+pub(crate) const OPT_COMMENT: u16 = 1;
+const SHB_USERAPPL: u16 = 4;
+pub(crate) const IF_TSRESOL: u16 = 9;
+const EPB_FLAGS: u16 = 2;
+
+/// Direction flags of packets.
+const INBOUND: u32 = 1;
+const OUTBOUND: u32 = 2;
+
+/// Writes the probes and replies of traces into a pcapng file, each with a
+/// comment that tells which trace, TTL and query it belongs to. Traces may
+/// share a capture.
+pub struct Capture {
+    state: Mutex<State>,
+}
+
+struct State {
+    writer: BufWriter<File>,
+    /// The first write that failed, reported when the capture is flushed.
+    error: Option<io::Error>,
+}
+
+impl Capture {
+    /// Create a capture file at `path`, replacing any file there.
+    pub fn create(path: &Path) -> Result<Self> {
+        let error = |e: io::Error| Error::Storage(format!("{}: {}", path.display(), e));
+        let mut writer = BufWriter::new(File::create(path).map_err(error)?);
+
+        let mut options = vec![];
+        option(&mut options, SHB_USERAPPL, b"tracer");
+        let mut body = vec![];
+        body.extend_from_slice(&0x1a2b_3c4d_u32.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        // The length of the section isn't known in advance.
+        body.extend_from_slice(&(-1i64).to_le_bytes());
+        write_block(&mut writer, SECTION_HEADER, &body, &options).map_err(error)?;
+
+        // Timestamps are in nanoseconds.
+        let mut options = vec![];
+        option(&mut options, IF_TSRESOL, &[9]);
+        let mut body = vec![];
+        body.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        write_block(&mut writer, INTERFACE_DESCRIPTION, &body, &options).map_err(error)?;
+
+        Ok(Capture {
+            state: Mutex::new(State {
+                writer,
+                error: None,
+            }),
+        })
+    }
+
+    /// Write a probe that was sent at `sent`.
+    pub(crate) fn probe(&self, packet: &[u8], sent: Instant, comment: &str) {
+        self.write(packet, sent, OUTBOUND, comment);
+    }
+
+    /// Write a reply that was received at `received`.
+    pub(crate) fn reply(&self, packet: &[u8], received: Instant, comment: &str) {
+        self.write(packet, received, INBOUND, comment);
+    }
+
+    /// Write every packet to the file. Returns the first error of any write.
+    pub fn flush(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let flushed = state.writer.flush();
+
+        match state.error.take().map_or(flushed, Err) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(Error::Storage(format!(
+                "unable to write the capture: {}",
+                e
+            ))),
+        }
+    }
+
+    fn write(&self, packet: &[u8], time: Instant, direction: u32, comment: &str) {
+        let nanos = wall_clock(time)
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |stamp| stamp.as_nanos() as u64);
+
+        let mut options = vec![];
+        option(&mut options, OPT_COMMENT, comment.as_bytes());
+        option(&mut options, EPB_FLAGS, &direction.to_le_bytes());
+        let mut body = vec![];
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&((nanos >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(nanos as u32).to_le_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        body.extend_from_slice(packet);
+        pad(&mut body);
+
+        let mut state = self.state.lock().unwrap();
+        if state.error.is_none() {
+            if let Err(e) = write_block(&mut state.writer, ENHANCED_PACKET, &body, &options) {
+                state.error = Some(e);
+            }
+        }
+    }
+}
+
+/// Prepend the IPv6 header to a reply from `source` to `destination`, since
+/// IPv6 raw sockets deliver replies without it.
+pub(crate) fn ipv6_reply(
+    source: Ipv6Addr,
+    destination: Ipv6Addr,
+    reply: ReplyKind,
+    hop_limit: Option<u8>,
+    payload: &[u8],
+) -> Vec<u8> {
+    let next_header = match reply {
+        ReplyKind::SynAck | ReplyKind::Reset => IpNextHeaderProtocols::Tcp,
+        _ => IpNextHeaderProtocols::Icmpv6,
+    };
+    let mut buf = vec![0; MutableIpv6Packet::minimum_packet_size() + payload.len()];
+
+    let mut packet = MutableIpv6Packet::new(&mut buf[..]).unwrap();
+    packet.set_version(6);
+    packet.set_payload_length(payload.len() as u16);
+    packet.set_next_header(next_header);
+    packet.set_hop_limit(hop_limit.unwrap_or(0));
+    packet.set_source(source);
+    packet.set_destination(destination);
+    packet.set_payload(payload);
+
+    buf
+}
+
+/// The time on the realtime clock of an instant on the monotonic one.
+fn wall_clock(time: Instant) -> SystemTime {
+    let now = Instant::now();
+    match now.checked_duration_since(time) {
+        Some(age) => SystemTime::now() - age,
+        None => SystemTime::now() + time.duration_since(now),
+    }
+}
+
+/// Append an option with `code` and `value` to `options`.
+fn option(options: &mut Vec<u8>, code: u16, value: &[u8]) {
+    options.extend_from_slice(&code.to_le_bytes());
+    options.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    options.extend_from_slice(value);
+    pad(options);
+}
+
+/// Pad `buf` to a multiple of 32 bits.
+fn pad(buf: &mut Vec<u8>) {
+    buf.resize(buf.len().div_ceil(4) * 4, 0);
+}
+
+/// Write a block of `block_type` with `body` and `options`, which end with
+/// the end of options.
+fn write_block<W: Write>(
+    writer: &mut W,
+    block_type: u32,
+    body: &[u8],
+    options: &[u8],
+) -> io::Result<()> {
+    // The type, both lengths and the end of options.
+    let len = (body.len() + options.len() + 16) as u32;
+
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(options)?;
+    writer.write_all(&OPT_END.to_le_bytes())?;
+    writer.write_all(&0u16.to_le_bytes())?;
+    writer.write_all(&len.to_le_bytes())
+}
@@ -1,12 +1,20 @@
 use anyhow::{Context, Error, Result};
 use crossbeam_channel::bounded;
-use std::{net::IpAddr, sync::Arc};
+use pnet::datalink::NetworkInterface;
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr},
+    path::Path,
+    sync::{Arc, Once},
+};
 
 use tracer::{
+    address_interface,
     data::{migrate_db, DbHandle},
-    interface_ip,
+    find_interface, interface_ip, interface_mtu,
     tasks::{self, Task},
-    Route, {Config, TraceRoute},
+    Capture, Hop, Mode, Pacer, Protocol, Receiver, Route, ScanConfig, Scanner, Simulation,
+    TraceQuery, {Config, TraceRoute},
 };
 
 use crate::AppConfig;
@@ -21,86 +29,653 @@ pub(crate) fn trace(cfg: AppConfig) -> Result<()> {
     let destination = cfg
         .destination
         .ok_or_else(|| Error::msg("destination address is missing"))?;
+
+    let db = Arc::new(DbHandle::new(cfg.db.clone()).context("Failed to start database actor.")?);
+
+    let (source_ip, interface) = source(&cfg, &destination)?;
+    let pacer = pacer(&cfg, &db)?;
+
+    // All traces receive their replies on the same sockets, and are recorded
+    // in the same capture.
+    let receiver = match cfg.simulate {
+        Some(_) => None,
+        None => receiver()?,
+    };
+    let capture = match &cfg.pcap {
+        Some(path) => Some(Arc::new(Capture::create(path)?)),
+        None => None,
+    };
+    let route = Route {
+        source: source_ip,
+        destination,
+    };
+    for idx in 0..cfg.count {
+        if pacer.is_exhausted() {
+            println!("probe budget of {} probes per day used up", pacer.budget());
+            break;
+        }
+        if cfg.count > 1 {
+            if idx > 0 {
+                println!();
+            }
+            println!("trace {} of {}", idx + 1, cfg.count);
+        }
+        let traced = trace_once(
+            &cfg,
+            &db,
+            receiver.as_ref(),
+            capture.as_ref(),
+            &pacer,
+            &route,
+            interface.as_ref(),
+        );
+        let updated = db.update_budget(pacer.budget(), pacer.take_sent());
+        traced?;
+        updated?;
+    }
+    if let Some(capture) = capture {
+        capture.flush()?;
+    }
+
+    db.shutdown();
+
+    Ok(())
+}
+
+/// Run a single trace on `route` and store it with its own id.
+fn trace_once(
+    cfg: &AppConfig,
+    db: &Arc<DbHandle>,
+    receiver: Option<&Arc<Receiver>>,
+    capture: Option<&Arc<Capture>>,
+    pacer: &Arc<Pacer>,
+    route: &Route,
+    interface: Option<&NetworkInterface>,
+) -> Result<()> {
+    let (source_ip, destination) = (route.source, route.destination);
+
+    // Yarrp traces are scans of a single target.
+    if cfg.mode == Mode::Yarrp {
+        if cfg.simulate.is_some() {
+            return Err(unsimulated().into());
+        }
+        if capture.is_some() {
+            return Err(
+                tracer::Error::Unsupported("yarrp traces can't be captured".to_string()).into(),
+            );
+        }
+        let target = match destination {
+            IpAddr::V4(target) => target,
+            IpAddr::V6(_) => return Err(Error::msg("yarrp traces only support IPv4")),
+        };
+        let config = scan_config(cfg, interface);
+        let scanner = match receiver {
+            Some(receiver) => Scanner::with_receiver(source_ip, config, Arc::clone(receiver)),
+            None => Scanner::new(source_ip, config),
+        };
+        let mut scanner = scanner?.with_pacer(Arc::clone(pacer));
+        scanner.scan(&[target], |trace, hops| {
+            db.insert_route(trace.route.clone())?;
+            db.insert_trace(trace)?;
+            for hop in hops {
+                tasks::hop_log(hop.clone())?;
+                db.insert_hop(hop)?;
+            }
+
+            Ok(())
+        })?;
+
+        return Ok(());
+    }
+
+    let config = trace_config(cfg, interface)?;
+    let mut traceroute =
+        traceroute(cfg, receiver, source_ip, destination, config)?.with_pacer(Arc::clone(pacer));
+    if let Some(capture) = capture {
+        traceroute = traceroute.with_capture(Arc::clone(capture));
+    }
+
+    db.insert_route(traceroute.trace.route.clone())?;
+    db.insert_trace(traceroute.trace.clone())?;
+    db.insert_trace_config(traceroute.trace.id, traceroute.config().clone())?;
+
+    // MDA traces yield a graph of all paths instead of a sequence of hops.
+    if cfg.mode == Mode::Mda {
+        let multipath = traceroute.multipath();
+        tasks::multipath_log(&multipath)?;
+        db.update_stop_reason(multipath.trace, multipath.stop_reason)?;
+        db.insert_multipath(multipath)?;
+
+        return Ok(());
+    }
+
+    let id = traceroute.trace.id;
+    run_tasks(db, |push| {
+        let mut hops = traceroute.iter();
+        while let Some(hop) = hops.next() {
+            db.insert_hop(hop.clone())?;
+
+            for late in hops.take_late() {
+                tasks::late_log(&late)?;
+                db.update_late(late)?;
+            }
+
+            // The workers only hang up once they failed.
+            if !push(hop) {
+                return Ok(());
+            }
+        }
+
+        if let Some(reason) = hops.stop_reason() {
+            db.update_stop_reason(id, reason)?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Run the tasks of every hop that `produce` pushes on worker threads, like
+/// logging, statistics and geolocation, while `produce` stores the hops.
+/// Pushing a hop fails once the workers failed.
+fn run_tasks<F>(db: &Arc<DbHandle>, produce: F) -> Result<()>
+where
+    F: FnOnce(&mut dyn FnMut(Hop) -> bool) -> tracer::Result<()> + Send,
+{
     let (snd1, rcv1) = bounded(1);
     let (snd2, _rcv2) = bounded::<tasks::Task>(1);
     let cpus = num_cpus::get();
     let n_workers = if cpus > 2 { cpus / 2 } else { 1 };
 
-    let db = Arc::new(DbHandle::new(cfg.db).context("Failed to start database actor.")?);
+    let geo_warning = Once::new();
+    crossbeam::scope(|s| {
+        // The producer pushes hops into consumers for further processing.
+        let producer = s.spawn(move |_| -> tracer::Result<()> {
+            let mut push = |hop: Hop| {
+                for task in [
+                    Task::HopLog(hop.clone()),
+                    Task::HopStats(hop.clone()),
+                    Task::HopGeoIp(hop),
+                ] {
+                    if snd1.send(task).is_err() {
+                        return false;
+                    }
+                }
+                true
+            };
+
+            // The channel is closed once the producer returns - this is
+            // necessary to exit the for-loop in the worker
+            produce(&mut push)
+        });
 
-    let source_ip = interface_ip(None)?;
-    let destination_ip = match destination {
-        IpAddr::V4(ip) => ip,
-        IpAddr::V6(ip) => ip.to_ipv4().unwrap(),
+        // Each worker listens to incoming tasks and runs them as they come in.
+        let workers = (0..n_workers)
+            .map(|_| {
+                let (_sendr, recvr) = (snd2.clone(), rcv1.clone());
+                let local_db = Arc::clone(db);
+                let geo_warning = &geo_warning;
+
+                s.spawn(move |_| -> tracer::Result<()> {
+                    for task in recvr.iter() {
+                        match task {
+                            Task::HopLog(hop) => tasks::hop_log(hop)?,
+                            Task::HopStats(hop) => tasks::hop_stats(&local_db, hop)?,
+                            // Geolocation is best effort and doesn't stop the trace.
+                            Task::HopGeoIp(hop) => match tasks::hop_geoip(&local_db, hop) {
+                                Err(tracer::Error::Geo(e)) => geo_warning
+                                    .call_once(|| eprintln!("geolocation skipped: {}", e)),
+                                result => result?,
+                            },
+                        };
+                    }
+
+                    Ok(())
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // Close the channels, otherwise the producer blocks once all workers
+        // failed and sink will never exit the for-loop
+        drop(rcv1);
+        drop(snd2);
+
+        // The error of a worker explains why the producer stopped early.
+        let mut results = workers
+            .into_iter()
+            .map(|worker| worker.join())
+            .collect::<Vec<_>>();
+        results.push(producer.join());
+        for result in results {
+            result.map_err(|_| Error::msg("a trace thread panicked"))??;
+        }
+
+        Ok(())
+    })
+    .map_err(|_| Error::msg("a trace thread panicked"))?
+}
+
+pub(crate) fn pmtu(cfg: AppConfig) -> Result<()> {
+    let destination = cfg
+        .destination
+        .ok_or_else(|| Error::msg("destination address is missing"))?;
+    if cfg.mode == Mode::Yarrp {
+        return Err(Error::msg(
+            "path MTU discovery doesn't support yarrp traces",
+        ));
+    }
+
+    let db = DbHandle::new(cfg.db.clone()).context("Failed to start database actor.")?;
+
+    let (source_ip, interface) = source(&cfg, &destination)?;
+    // Probes from a source address leave through the interface that has it.
+    let mtu = interface_mtu(
+        interface.clone().or_else(|| address_interface(&source_ip)),
+        &destination,
+    );
+    let pacer = pacer(&cfg, &db)?;
+    let config = trace_config(&cfg, interface.as_ref())?;
+    let receiver = match cfg.simulate {
+        Some(_) => None,
+        None => receiver()?,
     };
-    let config = Config::default();
-    let mut traceroute = TraceRoute::new(source_ip, destination_ip, config);
+    let mut traceroute = traceroute(&cfg, receiver.as_ref(), source_ip, destination, config)?
+        .with_pacer(Arc::clone(&pacer));
 
-    db.insert_route(traceroute.trace.route.clone());
-    db.insert_trace(traceroute.trace.clone());
+    db.insert_route(traceroute.trace.route.clone())?;
+    db.insert_trace(traceroute.trace.clone())?;
+    db.insert_trace_config(traceroute.trace.id, traceroute.config().clone())?;
 
-    crossbeam::scope(|s| {
-        // The producer does the traces and pushes routes, traces and hops into
-        // consumers for further processing.
-        s.spawn(|_| {
-            for hop in traceroute.iter() {
-                db.insert_hop(hop.clone());
-
-                snd1.send(Task::HopLog(hop.clone())).unwrap();
-                snd1.send(Task::HopStats(hop.clone())).unwrap();
-                snd1.send(Task::HopGeoIp(hop)).unwrap();
-            }
+    let (hops, path) = traceroute.pmtu(mtu);
+    db.update_budget(pacer.budget(), pacer.take_sent())?;
+    tasks::pmtu_log(&hops, &path)?;
+    for hop in hops {
+        db.insert_hop(hop)?;
+    }
+    db.update_stop_reason(path.trace, path.stop_reason)?;
+    db.insert_path_mtu(path)?;
+    db.shutdown();
 
-            // Close the channel - this is necessary to exit
-            // the for-loop in the worker
-            drop(snd1);
-        });
+    Ok(())
+}
 
-        // Each worker listens to incoming tasks and runs them as they come in.
-        for _ in 0..n_workers {
-            let (_sendr, recvr) = (snd2.clone(), rcv1.clone());
-            let local_db = Arc::clone(&db);
-
-            s.spawn(move |_| {
-                for task in recvr.iter() {
-                    match task {
-                        Task::HopLog(hop) => tasks::hop_log(hop).unwrap(),
-                        Task::HopStats(hop) => tasks::hop_stats(&local_db, hop).unwrap(),
-                        Task::HopGeoIp(hop) => tasks::hop_geoip(&local_db, hop).unwrap(),
-                    };
+pub(crate) fn scan(cfg: AppConfig) -> Result<()> {
+    let path = cfg
+        .targets
+        .as_ref()
+        .ok_or_else(|| Error::msg("target file is missing"))?;
+    if cfg.simulate.is_some() {
+        return Err(unsimulated().into());
+    }
+    let targets = read_targets(path)?;
+    let first = targets
+        .first()
+        .ok_or_else(|| Error::msg("target file has no addresses"))?;
+
+    let db = DbHandle::new(cfg.db.clone()).context("Failed to start database actor.")?;
+
+    let (source_ip, interface) = source(&cfg, &IpAddr::V4(*first))?;
+    let pacer = pacer(&cfg, &db)?;
+    let mut scanner = Scanner::new(source_ip, scan_config(&cfg, interface.as_ref()))?
+        .with_pacer(Arc::clone(&pacer));
+
+    // Traces are stored as their targets finish, so that a scan of many
+    // targets doesn't keep all of them.
+    let mut replies = 0;
+    let scanned = scanner.scan(&targets, |trace, hops| {
+        db.insert_route(trace.route.clone())?;
+        db.insert_trace(trace)?;
+        for hop in hops {
+            replies += hop
+                .queries
+                .iter()
+                .filter(|q| matches!(q, TraceQuery::Success { .. }))
+                .count();
+            db.insert_hop(hop)?;
+        }
+
+        Ok(())
+    });
+    db.update_budget(pacer.budget(), pacer.take_sent())?;
+    scanned?;
+    if pacer.is_exhausted() {
+        println!("probe budget of {} probes per day used up", pacer.budget());
+    }
+
+    println!("{} targets, {} replies", targets.len(), replies);
+
+    db.shutdown();
+
+    Ok(())
+}
+
+pub(crate) fn replay(cfg: AppConfig) -> Result<()> {
+    let path = cfg
+        .capture
+        .as_ref()
+        .ok_or_else(|| Error::msg("capture file is missing"))?;
+    let traces = tracer::replay(path)?;
+    if traces.is_empty() {
+        println!("no traces in {}", path.display());
+        return Ok(());
+    }
+
+    let db = Arc::new(DbHandle::new(cfg.db.clone()).context("Failed to start database actor.")?);
+
+    let count = traces.len();
+    for (idx, replayed) in traces.into_iter().enumerate() {
+        let trace = replayed.trace;
+        if idx > 0 {
+            println!();
+        }
+        println!(
+            "trace {} of {}: {} to {} ({}, {})",
+            idx + 1,
+            count,
+            trace.route.source,
+            trace.route.destination,
+            trace.protocol,
+            trace.mode
+        );
+
+        db.insert_route(trace.route.clone())?;
+        db.insert_trace(trace.clone())?;
+        db.insert_trace_config(trace.id, replayed.config)?;
+
+        // Replayed hops are stored and enriched like the hops of a trace.
+        let (hops, stop_reason) = (replayed.hops, replayed.stop_reason);
+        run_tasks(&db, |push| {
+            for hop in hops {
+                db.insert_hop(hop.clone())?;
+                if !push(hop) {
+                    return Ok(());
                 }
-            });
+            }
+
+            if let Some(reason) = stop_reason {
+                db.update_stop_reason(trace.id, reason)?;
+            }
+
+            Ok(())
+        })?;
+    }
+
+    db.shutdown();
+
+    Ok(())
+}
+
+pub(crate) fn import(cfg: AppConfig) -> Result<()> {
+    let path = cfg
+        .import
+        .as_ref()
+        .ok_or_else(|| Error::msg("file to import is missing"))?;
+    let traces = tracer::import(path, cfg.format)?;
+    if traces.is_empty() {
+        println!("no traces in {}", path.display());
+        return Ok(());
+    }
+
+    let db = DbHandle::new(cfg.db.clone()).context("Failed to start database actor.")?;
+
+    // Imported hops are measured elsewhere, so they are neither logged nor
+    // geolocated like the hops of a trace.
+    let count = traces.len();
+    for imported in traces {
+        // Text doesn't tell where a trace started, which is taken to be the
+        // source given on the command line, or this host like for `trace`.
+        let imported = match imported.trace.route.source.is_unspecified() {
+            true => {
+                let destination = imported.trace.route.destination;
+                imported.with_source(route_source(&cfg, &destination)?)
+            }
+            false => imported,
+        };
+        let trace = imported.trace;
+        db.insert_route(trace.route.clone())?;
+        db.insert_trace(trace.clone())?;
+        db.insert_trace_config(trace.id, imported.config)?;
+        db.insert_trace_origin(trace.id, imported.origin)?;
+
+        for hop in imported.hops {
+            db.insert_hop(hop.clone())?;
+            tasks::hop_stats(&db, hop)?;
         }
 
-        // Close the channel, otherwise sink will never
-        // exit the for-loop
-        drop(snd2);
-    })
-    .unwrap();
+        if let Some(reason) = imported.stop_reason {
+            db.update_stop_reason(trace.id, reason)?;
+        }
+    }
+    println!("imported {} traces from {}", count, path.display());
 
     db.shutdown();
 
     Ok(())
 }
 
+/// Open the raw sockets replies are received on, if this process may open
+/// them. Traces send their probes on datagram sockets otherwise.
+fn receiver() -> Result<Option<Arc<Receiver>>> {
+    if tracer::can_open_raw_sockets() {
+        let receiver = Receiver::new()?;
+        if let Some(e) = receiver.ipv6_error() {
+            eprintln!("IPv6 replies can't be received: {}", e);
+        }
+        Ok(Some(Arc::new(receiver)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Build the trace of a command. Probes are answered by the simulated network
+/// given on the command line, or sent on the sockets of `receiver` if this
+/// process may open raw sockets, and on datagram sockets otherwise.
+fn traceroute(
+    cfg: &AppConfig,
+    receiver: Option<&Arc<Receiver>>,
+    source_ip: IpAddr,
+    destination: IpAddr,
+    config: Config,
+) -> Result<TraceRoute> {
+    let traceroute = match (&cfg.simulate, receiver) {
+        (Some(path), _) => {
+            let simulation = Simulation::from_file(path)?;
+            TraceRoute::with_prober(source_ip, destination, config, Box::new(simulation))
+        }
+        (None, Some(receiver)) => {
+            TraceRoute::with_receiver(source_ip, destination, config, Arc::clone(receiver))?
+        }
+        (None, None) => TraceRoute::unprivileged(source_ip, destination, config)?,
+    };
+
+    Ok(traceroute)
+}
+
+/// The error of scans on a simulated network, which they don't support since
+/// they match replies to probes on raw sockets of their own.
+fn unsimulated() -> tracer::Error {
+    tracer::Error::Unsupported("scans and yarrp traces can't be simulated".to_string())
+}
+
+/// Build the pacer of all probes of a command from the rates on the command
+/// line and the daily probe budget. A budget on the command line replaces the
+/// one stored in the database.
+fn pacer(cfg: &AppConfig, db: &DbHandle) -> Result<Arc<Pacer>> {
+    let stored = db.show_budget()?;
+    let budget = match cfg.budget {
+        Some(budget) => {
+            db.update_budget(budget, vec![])?;
+            budget
+        }
+        None => stored.budget,
+    };
+
+    Ok(Arc::new(
+        Pacer::new(cfg.rate)
+            .with_prefix_rate(cfg.prefix_rate)
+            .with_budget(budget, stored.used),
+    ))
+}
+
+/// Select the source address of probes to `destination` and the interface
+/// they are sent from. An interface or source address given on the command
+/// line takes precedence over the route the kernel chooses for `destination`.
+/// Only an interface given on the command line is returned, since binding
+/// sockets to a device needs privileges that a source address alone doesn't.
+fn source(cfg: &AppConfig, destination: &IpAddr) -> Result<(IpAddr, Option<NetworkInterface>)> {
+    let interface = cfg.interface.as_deref().map(find_interface).transpose()?;
+
+    let source = match cfg.source {
+        Some(source) => {
+            if source.is_ipv4() != destination.is_ipv4() {
+                return Err(tracer::Error::Interface(format!(
+                    "source {} can't reach destination {} of another address family",
+                    source, destination
+                ))
+                .into());
+            }
+            source
+        }
+        None => return Ok((interface_ip(interface.clone(), destination)?, interface)),
+    };
+
+    let owner = address_interface(&source).ok_or_else(|| {
+        tracer::Error::Interface(format!("{} isn't an address of any interface", source))
+    })?;
+    match interface {
+        Some(interface) if interface.name != owner.name => Err(tracer::Error::Interface(format!(
+            "{} isn't an address of interface {}",
+            source, interface.name
+        ))
+        .into()),
+        interface => Ok((source, interface)),
+    }
+}
+
+/// Select the source of a stored route to `destination`. A source address
+/// given on the command line is taken as is, since imported routes start at
+/// hosts elsewhere.
+fn route_source(cfg: &AppConfig, destination: &IpAddr) -> Result<IpAddr> {
+    match cfg.source {
+        Some(source) => Ok(source),
+        None => Ok(source(cfg, destination)?.0),
+    }
+}
+
+fn trace_config(cfg: &AppConfig, interface: Option<&NetworkInterface>) -> Result<Config> {
+    let first_ttl = cfg.first_ttl.unwrap_or(1);
+    let max_hops = cfg.max_hops.unwrap_or(30);
+    if first_ttl == 0 || first_ttl > max_hops {
+        return Err(Error::msg(format!(
+            "first TTL {} must be between 1 and the maximum number of hops {}",
+            first_ttl, max_hops
+        )));
+    }
+    if cfg.tries == Some(0) {
+        return Err(Error::msg("every hop needs at least one probe"));
+    }
+    if !(cfg.confidence > 0.0 && cfg.confidence < 1.0) {
+        return Err(tracer::Error::Parse(format!(
+            "confidence {} must be greater than 0 and less than 1",
+            cfg.confidence
+        ))
+        .into());
+    }
+
+    let mut config = Config::default()
+        .with_protocol(cfg.protocol)
+        .with_mode(cfg.mode)
+        .with_confidence(cfg.confidence)
+        .with_first_ttl(first_ttl)
+        .with_max_hops(max_hops);
+    if let Some(port) = cfg.port {
+        config = config.with_port(port);
+    } else if cfg.protocol == Protocol::Tcp {
+        config = config.with_port(80);
+    }
+    if let Some(tries) = cfg.tries {
+        config = config.with_tries(tries);
+    }
+    if let Some(timeout) = cfg.timeout {
+        config = config.with_timeout(timeout);
+    }
+    if let Some(wait) = cfg.wait {
+        config = config.with_wait(wait);
+    }
+    if let Some(size) = cfg.size {
+        config = config.with_size(size);
+    }
+    if let Some(tos) = cfg.tos {
+        config = config.with_tos(tos);
+    }
+    if let Some(window) = cfg.window {
+        config = config.with_window(window);
+    }
+    if cfg.fails > 0 {
+        config = config.with_gap_limit(cfg.fails);
+    }
+    if let Some(interface) = interface {
+        config = config.with_interface(&interface.name);
+    }
+
+    Ok(config)
+}
+
+fn scan_config(cfg: &AppConfig, interface: Option<&NetworkInterface>) -> ScanConfig {
+    let mut config = ScanConfig::default()
+        .with_protocol(cfg.protocol)
+        .with_rate(cfg.rate);
+    if let Some(port) = cfg.port {
+        config = config.with_port(port);
+    }
+    if let Some(max_hops) = cfg.max_hops {
+        config = config.with_max_hops(max_hops);
+    }
+    if let Some(timeout) = cfg.timeout {
+        config = config.with_timeout(timeout);
+    }
+    if let Some(interface) = interface {
+        config = config.with_interface(&interface.name);
+    }
+
+    config
+}
+
+/// Read a list of IPv4 addresses, one per line. Empty lines and lines starting
+/// with `#` are skipped.
+fn read_targets(path: &Path) -> Result<Vec<Ipv4Addr>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read targets from {}.", path.display()))?;
+
+    content
+        .lines()
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(idx, line)| {
+            line.parse::<Ipv4Addr>().map_err(|_| {
+                tracer::Error::Parse(format!(
+                    "line {}: {:?} is an invalid IPv4 address",
+                    idx + 1,
+                    line
+                ))
+                .into()
+            })
+        })
+        .collect()
+}
+
 pub(crate) fn export(cfg: AppConfig) -> Result<()> {
     let destination = cfg
         .destination
         .ok_or_else(|| Error::msg("destination address is missing"))?;
-    let db = Arc::new(DbHandle::new(cfg.db).context("Failed to start database actor.")?);
-
-    let source_ip = interface_ip(None)?;
-    let destination_ip = match destination {
-        IpAddr::V4(ip) => ip,
-        IpAddr::V6(ip) => ip.to_ipv4().unwrap(),
-    };
+    let db = Arc::new(DbHandle::new(cfg.db.clone()).context("Failed to start database actor.")?);
 
     let route = Route {
-        source: source_ip,
-        destination: destination_ip,
+        source: route_source(&cfg, &destination)?,
+        destination,
     };
 
-    let hops = db.export_route(route);
+    let hops = db.export_route(route)?;
 
     let mut wtr = csv::Writer::from_writer(std::io::stdout());
     for hop in hops {
@@ -112,3 +687,49 @@ pub(crate) fn export(cfg: AppConfig) -> Result<()> {
 
     Ok(())
 }
+
+pub(crate) fn inspect(cfg: AppConfig) -> Result<()> {
+    let destination = cfg
+        .destination
+        .ok_or_else(|| Error::msg("destination address is missing"))?;
+    let db = DbHandle::new(cfg.db.clone()).context("Failed to start database actor.")?;
+
+    let route = Route {
+        source: route_source(&cfg, &destination)?,
+        destination,
+    };
+
+    let modifications = db.show_modifications(route)?;
+    tasks::modifications_log(&modifications)?;
+
+    db.shutdown();
+
+    Ok(())
+}
+
+pub(crate) fn interfaces(cfg: AppConfig) -> Result<()> {
+    // Mark the interface of the route to the destination, if one is given.
+    let route = match cfg.destination {
+        Some(destination) => {
+            let (source_ip, interface) = source(&cfg, &destination)?;
+            interface.or_else(|| address_interface(&source_ip))
+        }
+        None => None,
+    };
+
+    for interface in tracer::interfaces() {
+        let ips = interface
+            .ips
+            .iter()
+            .map(|ip| ip.to_string())
+            .collect::<Vec<String>>()
+            .join("  ");
+        let marker = match &route {
+            Some(route) if route.name == interface.name => "  <route>",
+            _ => "",
+        };
+        println!("{}: {}{}", interface.name, ips, marker);
+    }
+
+    Ok(())
+}
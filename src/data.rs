@@ -1,24 +1,132 @@
-use anyhow::Result;
-use rusqlite::{params, types::Null};
+use rusqlite::{params, types::Null, OptionalExtension};
 use serde_rusqlite::{columns_from_statement, from_row_with_columns};
 use std::{
     fmt::Debug,
-    net::Ipv4Addr,
+    net::IpAddr,
     path::{Path, PathBuf},
     sync::{mpsc, RwLock},
     thread,
 };
 use uuid::Uuid;
 
-use crate::{geoip::IpApiResp, stats::HopStats, ExportHop, Hop, Route, Trace, TraceQuery};
+use crate::{
+    geoip::IpApiResp, stats::HopStats, Config, Error, ExportHop, ExportModification, Hop,
+    LateReply, Mode, Modification, MplsLabel, Multipath, Origin, PathMtu, ProbeBudget, Protocol,
+    Result, Route, StopReason, Trace, TraceQuery,
+};
+
+/// Steps that bring the tables of a database of an older version up to the
+/// schema, in order. `PRAGMA user_version` counts the steps a database went
+/// through. Databases of versions before it count none, so every step checks
+/// whether the tables already have its columns.
+const MIGRATIONS: [fn(&rusqlite::Connection) -> rusqlite::Result<()>; 4] =
+    [trace_probing, microseconds, reply_ttls, stop_reasons];
 
 pub fn migrate_db<P: AsRef<Path>>(path: P) -> Result<()> {
     let schema = include_str!("../ressources/schema.sql");
-    let connection = rusqlite::Connection::open(path)?;
-    connection.execute_batch(schema)?;
+    let mut connection = rusqlite::Connection::open(path)?;
+    let tx = connection.transaction()?;
+    tx.execute_batch(schema)?;
+
+    let version: i64 = tx.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for migration in MIGRATIONS.iter().skip(version.max(0) as usize) {
+        migration(&tx)?;
+    }
+    tx.pragma_update(None, "user_version", &(MIGRATIONS.len() as i64))?;
+    tx.commit()?;
+
     Ok(())
 }
 
+/// Test whether `table` has `column`.
+fn has_column(
+    connection: &rusqlite::Connection,
+    table: &str,
+    column: &str,
+) -> rusqlite::Result<bool> {
+    let mut stmt = connection.prepare(&format!("PRAGMA table_info({})", table))?;
+    let mut columns = stmt.query_map(params![], |row| row.get::<_, String>(1))?;
+
+    columns.try_fold(false, |found, name| Ok(found || name? == column))
+}
+
+/// Add `column` with `definition` to `table` unless it has it.
+fn add_column(
+    connection: &rusqlite::Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> rusqlite::Result<()> {
+    if has_column(connection, table, column)? {
+        return Ok(());
+    }
+
+    connection.execute_batch(&format!(
+        "ALTER TABLE {} ADD COLUMN {} {}",
+        table, column, definition
+    ))
+}
+
+/// Traces store the protocol and mode they were probed with.
+fn trace_probing(connection: &rusqlite::Connection) -> rusqlite::Result<()> {
+    add_column(
+        connection,
+        "trace",
+        "protocol",
+        "TEXT NOT NULL DEFAULT 'udp'",
+    )?;
+    add_column(
+        connection,
+        "trace",
+        "mode",
+        "TEXT NOT NULL DEFAULT 'classic'",
+    )
+}
+
+/// Round-trip times are stored in microseconds instead of milliseconds.
+fn microseconds(connection: &rusqlite::Connection) -> rusqlite::Result<()> {
+    to_microseconds(connection, "hop", "rtt", "rtt_us")?;
+    to_microseconds(connection, "hop_stats", "mean_ms", "mean_us")?;
+    to_microseconds(connection, "hop_stats", "median_ms", "median_us")
+}
+
+/// Replace the `millis` column of `table` with the `micros` column, if it
+/// still has it.
+fn to_microseconds(
+    connection: &rusqlite::Connection,
+    table: &str,
+    millis: &str,
+    micros: &str,
+) -> rusqlite::Result<()> {
+    if !has_column(connection, table, millis)? {
+        return Ok(());
+    }
+
+    add_column(connection, table, micros, "INTEGER")?;
+    connection.execute_batch(&format!(
+        "UPDATE {table} SET {micros} = CAST({millis} AS INTEGER) * 1000 \
+         WHERE {millis} IS NOT NULL AND {millis} != '';
+         ALTER TABLE {table} DROP COLUMN {millis}",
+        table = table,
+        millis = millis,
+        micros = micros
+    ))
+}
+
+/// Hops store the TTLs of their replies and the hops they infer from them.
+fn reply_ttls(connection: &rusqlite::Connection) -> rusqlite::Result<()> {
+    for column in &["reply_ttl", "quoted_ttl", "reverse_hops", "hidden_hops"] {
+        add_column(connection, "hop", column, "INTEGER")?;
+    }
+
+    Ok(())
+}
+
+/// Traces store why they stopped.
+fn stop_reasons(connection: &rusqlite::Connection) -> rusqlite::Result<()> {
+    add_column(connection, "trace", "stop_reason", "TEXT")
+}
+
 #[derive(Debug)]
 pub struct Manager {
     connection: rusqlite::Connection,
@@ -32,7 +140,7 @@ impl Manager {
         Ok(Self { connection })
     }
 
-    fn file<P: AsRef<Path>>(path: P) -> Result<rusqlite::Connection, rusqlite::Error> {
+    fn file<P: AsRef<Path>>(path: P) -> rusqlite::Result<rusqlite::Connection> {
         rusqlite::Connection::open(path)
     }
 }
@@ -50,40 +158,88 @@ struct Db {
 enum DbMessage {
     InsertRoute {
         route: Route,
-        respond_to: mpsc::SyncSender<()>,
+        respond_to: mpsc::SyncSender<Result<()>>,
     },
 
     InsertTrace {
         trace: Trace,
-        respond_to: mpsc::SyncSender<()>,
+        respond_to: mpsc::SyncSender<Result<()>>,
     },
 
     InsertHop {
         hop: Hop,
-        respond_to: mpsc::SyncSender<()>,
+        respond_to: mpsc::SyncSender<Result<()>>,
+    },
+
+    UpdateLate {
+        late: LateReply,
+        respond_to: mpsc::SyncSender<Result<()>>,
+    },
+
+    UpdateStopReason {
+        trace: Uuid,
+        reason: StopReason,
+        respond_to: mpsc::SyncSender<Result<()>>,
+    },
+
+    InsertMultipath {
+        multipath: Multipath,
+        respond_to: mpsc::SyncSender<Result<()>>,
+    },
+
+    InsertPathMtu {
+        path: PathMtu,
+        respond_to: mpsc::SyncSender<Result<()>>,
+    },
+
+    InsertTraceConfig {
+        trace: Uuid,
+        config: Config,
+        respond_to: mpsc::SyncSender<Result<()>>,
+    },
+
+    InsertTraceOrigin {
+        trace: Uuid,
+        origin: Origin,
+        respond_to: mpsc::SyncSender<Result<()>>,
     },
 
     InsertStats {
         hop: Hop,
         stats: HopStats,
-        respond_to: mpsc::SyncSender<()>,
+        respond_to: mpsc::SyncSender<Result<()>>,
     },
 
     InsertGeoip {
         hop: Hop,
         query: u8,
-        geoip: IpApiResp,
-        respond_to: mpsc::SyncSender<()>,
+        geoip: Box<IpApiResp>,
+        respond_to: mpsc::SyncSender<Result<()>>,
     },
 
     ExportHop {
         route: Route,
-        respond_to: mpsc::SyncSender<Vec<ExportHop>>,
+        respond_to: mpsc::SyncSender<Result<Vec<ExportHop>>>,
+    },
+
+    ShowModifications {
+        route: Route,
+        respond_to: mpsc::SyncSender<Result<Vec<ExportModification>>>,
     },
 
     ShowGeoip {
-        addr: Ipv4Addr,
-        respond_to: mpsc::SyncSender<Option<IpApiResp>>,
+        addr: IpAddr,
+        respond_to: mpsc::SyncSender<Result<Option<IpApiResp>>>,
+    },
+
+    ShowBudget {
+        respond_to: mpsc::SyncSender<Result<ProbeBudget>>,
+    },
+
+    UpdateBudget {
+        budget: u64,
+        sent: Vec<(u64, u64)>,
+        respond_to: mpsc::SyncSender<Result<()>>,
     },
 
     Shutdown,
@@ -110,30 +266,87 @@ impl Db {
     fn handle_message(&mut self, msg: DbMessage) {
         match msg {
             DbMessage::InsertRoute { route, respond_to } => {
-                let _ = self.write_lock.write().unwrap();
-                self.store
-                    .insert_route(&route.source, &route.destination)
-                    .expect("inserting a route");
+                let _lock = self.write_lock.write().unwrap();
+                let result = self.store.insert_route(&route.source, &route.destination);
 
-                let _ = respond_to.send(());
+                let _ = respond_to.send(result.map(drop));
             }
 
             DbMessage::InsertTrace { trace, respond_to } => {
-                let _ = self.write_lock.write().unwrap();
-                self.store
-                    .insert_trace(&trace.route.source, &trace.route.destination, &trace.id)
-                    .expect("inserting a trace");
-
-                let _ = respond_to.send(());
+                let _lock = self.write_lock.write().unwrap();
+                let result = self.store.insert_trace(
+                    &trace.route.source,
+                    &trace.route.destination,
+                    &trace.id,
+                    trace.protocol,
+                    trace.mode,
+                );
+
+                let _ = respond_to.send(result.map(drop));
             }
 
             DbMessage::InsertHop { hop, respond_to } => {
-                let _ = self.write_lock.write().unwrap();
-                self.store
-                    .insert_hop(&hop.trace, hop.ttl, hop.queries)
-                    .expect("inserting a hop");
+                let _lock = self.write_lock.write().unwrap();
+                let result = self.store.insert_hop(&hop.trace, hop.ttl, hop.queries);
+
+                let _ = respond_to.send(result.map(drop));
+            }
+
+            DbMessage::UpdateLate { late, respond_to } => {
+                let _lock = self.write_lock.write().unwrap();
+                let result = self.store.update_late(&late);
 
-                let _ = respond_to.send(());
+                let _ = respond_to.send(result.map(drop));
+            }
+
+            DbMessage::UpdateStopReason {
+                trace,
+                reason,
+                respond_to,
+            } => {
+                let _lock = self.write_lock.write().unwrap();
+                let result = self.store.update_stop_reason(&trace, reason);
+
+                let _ = respond_to.send(result.map(drop));
+            }
+
+            DbMessage::InsertMultipath {
+                multipath,
+                respond_to,
+            } => {
+                let _lock = self.write_lock.write().unwrap();
+                let result = self.store.insert_multipath(&multipath);
+
+                let _ = respond_to.send(result.map(drop));
+            }
+
+            DbMessage::InsertPathMtu { path, respond_to } => {
+                let _lock = self.write_lock.write().unwrap();
+                let result = self.store.insert_path_mtu(&path);
+
+                let _ = respond_to.send(result.map(drop));
+            }
+
+            DbMessage::InsertTraceConfig {
+                trace,
+                config,
+                respond_to,
+            } => {
+                let _lock = self.write_lock.write().unwrap();
+                let result = self.store.insert_trace_config(&trace, &config);
+
+                let _ = respond_to.send(result.map(drop));
+            }
+
+            DbMessage::InsertTraceOrigin {
+                trace,
+                origin,
+                respond_to,
+            } => {
+                let _lock = self.write_lock.write().unwrap();
+                let result = self.store.insert_trace_origin(&trace, &origin);
+
+                let _ = respond_to.send(result.map(drop));
             }
 
             DbMessage::InsertStats {
@@ -141,12 +354,10 @@ impl Db {
                 stats,
                 respond_to,
             } => {
-                let _ = self.write_lock.write().unwrap();
-                self.store
-                    .insert_stats(&hop.trace, hop.ttl, &stats)
-                    .expect("inserting hop stats");
+                let _lock = self.write_lock.write().unwrap();
+                let result = self.store.insert_stats(&hop.trace, hop.ttl, &stats);
 
-                let _ = respond_to.send(());
+                let _ = respond_to.send(result.map(drop));
             }
 
             DbMessage::InsertGeoip {
@@ -155,28 +366,45 @@ impl Db {
                 geoip,
                 respond_to,
             } => {
-                let _ = self.write_lock.write().unwrap();
-                self.store
-                    .insert_geoip(&hop.trace, hop.ttl, query, &geoip)
-                    .expect("inserting hop geoip");
+                let _lock = self.write_lock.write().unwrap();
+                let result = self.store.insert_geoip(&hop.trace, hop.ttl, query, &geoip);
 
-                let _ = respond_to.send(());
+                let _ = respond_to.send(result.map(drop));
             }
 
             DbMessage::ExportHop { route, respond_to } => {
-                let hops = self
-                    .store
-                    .export_route(&route.source, &route.destination)
-                    .expect("exporting a route");
+                let hops = self.store.export_route(&route.source, &route.destination);
 
                 let _ = respond_to.send(hops);
             }
 
+            DbMessage::ShowModifications { route, respond_to } => {
+                let modifications = self
+                    .store
+                    .show_modifications(&route.source, &route.destination);
+
+                let _ = respond_to.send(modifications);
+            }
+
+            DbMessage::ShowBudget { respond_to } => {
+                let budget = self.store.show_budget();
+
+                let _ = respond_to.send(budget);
+            }
+
+            DbMessage::UpdateBudget {
+                budget,
+                sent,
+                respond_to,
+            } => {
+                let _lock = self.write_lock.write().unwrap();
+                let result = self.store.update_budget(budget, sent);
+
+                let _ = respond_to.send(result.map(drop));
+            }
+
             DbMessage::ShowGeoip { addr, respond_to } => {
-                let data = match self.store.show_geoip_for_addr(&addr) {
-                    Ok(data) => Some(data),
-                    Err(_) => None,
-                };
+                let data = self.store.show_geoip_for_addr(&addr);
 
                 let _ = respond_to.send(data);
             }
@@ -194,6 +422,11 @@ pub struct DbHandle {
     sender: mpsc::SyncSender<DbMessage>,
 }
 
+/// The error of a request to a database actor that has stopped.
+fn stopped<T>(_: T) -> Error {
+    Error::Storage("the database actor has stopped".to_string())
+}
+
 impl DbHandle {
     pub fn new(db_path: PathBuf) -> Result<Self> {
         let (sender, receiver) = mpsc::sync_channel(100);
@@ -204,7 +437,7 @@ impl DbHandle {
         Ok(Self { sender })
     }
 
-    pub fn insert_route(&self, route: Route) {
+    pub fn insert_route(&self, route: Route) -> Result<()> {
         let (send, recv) = mpsc::sync_channel(1);
 
         let msg = DbMessage::InsertRoute {
@@ -212,11 +445,11 @@ impl DbHandle {
             respond_to: send,
         };
 
-        let _ = self.sender.send(msg);
-        recv.recv().expect("Db has been killed")
+        self.sender.send(msg).map_err(stopped)?;
+        recv.recv().map_err(stopped)?
     }
 
-    pub fn insert_trace(&self, trace: Trace) {
+    pub fn insert_trace(&self, trace: Trace) -> Result<()> {
         let (send, recv) = mpsc::sync_channel(1);
 
         let msg = DbMessage::InsertTrace {
@@ -224,11 +457,11 @@ impl DbHandle {
             respond_to: send,
         };
 
-        let _ = self.sender.send(msg);
-        recv.recv().expect("Db has been killed")
+        self.sender.send(msg).map_err(stopped)?;
+        recv.recv().map_err(stopped)?
     }
 
-    pub fn insert_hop(&self, hop: Hop) {
+    pub fn insert_hop(&self, hop: Hop) -> Result<()> {
         let (send, recv) = mpsc::sync_channel(1);
 
         let msg = DbMessage::InsertHop {
@@ -236,11 +469,88 @@ impl DbHandle {
             respond_to: send,
         };
 
-        let _ = self.sender.send(msg);
-        recv.recv().expect("Db has been killed")
+        self.sender.send(msg).map_err(stopped)?;
+        recv.recv().map_err(stopped)?
+    }
+
+    /// Record a reply that came in late for a query of a stored hop.
+    pub fn update_late(&self, late: LateReply) -> Result<()> {
+        let (send, recv) = mpsc::sync_channel(1);
+
+        let msg = DbMessage::UpdateLate {
+            late,
+            respond_to: send,
+        };
+
+        self.sender.send(msg).map_err(stopped)?;
+        recv.recv().map_err(stopped)?
+    }
+
+    pub fn update_stop_reason(&self, trace: Uuid, reason: StopReason) -> Result<()> {
+        let (send, recv) = mpsc::sync_channel(1);
+
+        let msg = DbMessage::UpdateStopReason {
+            trace,
+            reason,
+            respond_to: send,
+        };
+
+        self.sender.send(msg).map_err(stopped)?;
+        recv.recv().map_err(stopped)?
+    }
+
+    pub fn insert_multipath(&self, multipath: Multipath) -> Result<()> {
+        let (send, recv) = mpsc::sync_channel(1);
+
+        let msg = DbMessage::InsertMultipath {
+            multipath,
+            respond_to: send,
+        };
+
+        self.sender.send(msg).map_err(stopped)?;
+        recv.recv().map_err(stopped)?
+    }
+
+    pub fn insert_path_mtu(&self, path: PathMtu) -> Result<()> {
+        let (send, recv) = mpsc::sync_channel(1);
+
+        let msg = DbMessage::InsertPathMtu {
+            path,
+            respond_to: send,
+        };
+
+        self.sender.send(msg).map_err(stopped)?;
+        recv.recv().map_err(stopped)?
+    }
+
+    pub fn insert_trace_config(&self, trace: Uuid, config: Config) -> Result<()> {
+        let (send, recv) = mpsc::sync_channel(1);
+
+        let msg = DbMessage::InsertTraceConfig {
+            trace,
+            config,
+            respond_to: send,
+        };
+
+        self.sender.send(msg).map_err(stopped)?;
+        recv.recv().map_err(stopped)?
+    }
+
+    /// Record where an imported trace was measured.
+    pub fn insert_trace_origin(&self, trace: Uuid, origin: Origin) -> Result<()> {
+        let (send, recv) = mpsc::sync_channel(1);
+
+        let msg = DbMessage::InsertTraceOrigin {
+            trace,
+            origin,
+            respond_to: send,
+        };
+
+        self.sender.send(msg).map_err(stopped)?;
+        recv.recv().map_err(stopped)?
     }
 
-    pub fn insert_stats(&self, hop: Hop, stats: HopStats) {
+    pub fn insert_stats(&self, hop: Hop, stats: HopStats) -> Result<()> {
         let (send, recv) = mpsc::sync_channel(1);
 
         let msg = DbMessage::InsertStats {
@@ -249,25 +559,25 @@ impl DbHandle {
             respond_to: send,
         };
 
-        let _ = self.sender.send(msg);
-        recv.recv().expect("Db has been killed")
+        self.sender.send(msg).map_err(stopped)?;
+        recv.recv().map_err(stopped)?
     }
 
-    pub fn insert_geoip(&self, hop: Hop, query: u8, geoip: IpApiResp) {
+    pub fn insert_geoip(&self, hop: Hop, query: u8, geoip: IpApiResp) -> Result<()> {
         let (send, recv) = mpsc::sync_channel(1);
 
         let msg = DbMessage::InsertGeoip {
             hop,
             query,
-            geoip,
+            geoip: Box::new(geoip),
             respond_to: send,
         };
 
-        let _ = self.sender.send(msg);
-        recv.recv().expect("Db has been killed")
+        self.sender.send(msg).map_err(stopped)?;
+        recv.recv().map_err(stopped)?
     }
 
-    pub fn export_route(&self, route: Route) -> Vec<ExportHop> {
+    pub fn export_route(&self, route: Route) -> Result<Vec<ExportHop>> {
         let (send, recv) = mpsc::sync_channel(1);
 
         let msg = DbMessage::ExportHop {
@@ -275,20 +585,60 @@ impl DbHandle {
             respond_to: send,
         };
 
-        let _ = self.sender.send(msg);
-        recv.recv().expect("Db has been killed")
+        self.sender.send(msg).map_err(stopped)?;
+        recv.recv().map_err(stopped)?
     }
 
-    pub fn show_geoip(&self, addr: &Ipv4Addr) -> Option<IpApiResp> {
+    /// The header fields changed on the way of the probes of all traces of a
+    /// route.
+    pub fn show_modifications(&self, route: Route) -> Result<Vec<ExportModification>> {
+        let (send, recv) = mpsc::sync_channel(1);
+
+        let msg = DbMessage::ShowModifications {
+            route,
+            respond_to: send,
+        };
+
+        self.sender.send(msg).map_err(stopped)?;
+        recv.recv().map_err(stopped)?
+    }
+
+    /// The daily probe budget, which carries over from the last day it was
+    /// set, and the probes sent today.
+    pub fn show_budget(&self) -> Result<ProbeBudget> {
+        let (send, recv) = mpsc::sync_channel(1);
+
+        let msg = DbMessage::ShowBudget { respond_to: send };
+
+        self.sender.send(msg).map_err(stopped)?;
+        recv.recv().map_err(stopped)?
+    }
+
+    /// Set the daily probe budget and count `sent` more probes on each day
+    /// since the epoch in UTC they were sent on.
+    pub fn update_budget(&self, budget: u64, sent: Vec<(u64, u64)>) -> Result<()> {
+        let (send, recv) = mpsc::sync_channel(1);
+
+        let msg = DbMessage::UpdateBudget {
+            budget,
+            sent,
+            respond_to: send,
+        };
+
+        self.sender.send(msg).map_err(stopped)?;
+        recv.recv().map_err(stopped)?
+    }
+
+    pub fn show_geoip(&self, addr: &IpAddr) -> Result<Option<IpApiResp>> {
         let (send, recv) = mpsc::sync_channel(1);
 
         let msg = DbMessage::ShowGeoip {
-            addr: addr.clone(),
+            addr: *addr,
             respond_to: send,
         };
 
-        let _ = self.sender.send(msg);
-        recv.recv().expect("Db has been killed")
+        self.sender.send(msg).map_err(stopped)?;
+        recv.recv().map_err(stopped)?
     }
 
     pub fn shutdown(&self) {
@@ -302,7 +652,7 @@ struct Store {
 }
 
 impl Store {
-    fn show_route_id(&self, source: &Ipv4Addr, destination: &Ipv4Addr) -> Result<i64> {
+    fn show_route_id(&self, source: &IpAddr, destination: &IpAddr) -> Result<i64> {
         let conn = &self.db.connection;
         let mut stmt = conn.prepare_cached(include_str!("sql/show-route.sql"))?;
 
@@ -327,7 +677,7 @@ impl Store {
         let conn = &self.db.connection;
         let mut stmt = conn.prepare_cached(include_str!("sql/show-hops.sql"))?;
 
-        let trace_id = self.show_trace_id(&trace)?;
+        let trace_id = self.show_trace_id(trace)?;
         let rows = stmt.query_map(params![ttl, trace_id], |row| row.get(0))?;
 
         let mut hop_ids: Vec<i64> = Vec::new();
@@ -343,31 +693,43 @@ impl Store {
         let conn = &self.db.connection;
         let mut stmt = conn.prepare_cached(include_str!("sql/show-hop.sql"))?;
 
-        let trace_id = self.show_trace_id(&trace)?;
+        let trace_id = self.show_trace_id(trace)?;
         let hop_id: i64 = stmt.query_row(params![ttl, query, trace_id], |row| row.get(0))?;
 
         Ok(hop_id)
     }
 
-    fn insert_route(&self, source: &Ipv4Addr, destination: &Ipv4Addr) -> Result<i64> {
+    fn insert_route(&self, source: &IpAddr, destination: &IpAddr) -> Result<i64> {
         let conn = &self.db.connection;
 
         let mut stmt = conn.prepare_cached(include_str!("sql/insert-route.sql"))?;
 
         stmt.execute(params![&source.to_string(), &destination.to_string()])?;
-        let route_id = self.show_route_id(&source, &destination)?;
+        let route_id = self.show_route_id(source, destination)?;
 
         Ok(route_id)
     }
 
-    fn insert_trace(&self, source: &Ipv4Addr, destination: &Ipv4Addr, trace: &Uuid) -> Result<i64> {
+    fn insert_trace(
+        &self,
+        source: &IpAddr,
+        destination: &IpAddr,
+        trace: &Uuid,
+        protocol: Protocol,
+        mode: Mode,
+    ) -> Result<i64> {
         let conn = &self.db.connection;
 
         let mut stmt = conn.prepare_cached(include_str!("sql/insert-trace.sql"))?;
 
-        let route_id = self.show_route_id(&source, &destination)?;
-        stmt.execute(params![trace.to_string(), route_id])?;
-        let trace_id = self.show_trace_id(&trace)?;
+        let route_id = self.show_route_id(source, destination)?;
+        stmt.execute(params![
+            trace.to_string(),
+            route_id,
+            protocol.to_string(),
+            mode.to_string()
+        ])?;
+        let trace_id = self.show_trace_id(trace)?;
 
         Ok(trace_id)
     }
@@ -377,45 +739,227 @@ impl Store {
 
         let mut stmt = conn.prepare_cached(include_str!("sql/insert-hop.sql"))?;
 
-        let trace_id = self.show_trace_id(&trace)?;
-        let mut idx = 1;
-        for query in queries {
-            match query {
-                TraceQuery::Success { addr, rtt } => {
+        let trace_id = self.show_trace_id(trace)?;
+        for (idx, query) in (1..).zip(queries) {
+            let reverse_hops = query.reverse_hops();
+            let hidden_hops = query.hidden_hops();
+            match &query {
+                TraceQuery::Success(response) | TraceQuery::Late(response) => {
+                    let result = match query {
+                        TraceQuery::Late(_) => "late",
+                        _ => "success",
+                    };
                     stmt.execute(params![
                         ttl,
                         trace_id,
                         idx,
-                        "success",
-                        &addr.to_string(),
-                        &rtt.as_millis().to_string()
+                        result,
+                        &response.addr.to_string(),
+                        response.rtt.as_micros() as i64,
+                        response.reply_ttl,
+                        response.quoted_ttl,
+                        reverse_hops,
+                        hidden_hops
                     ])?;
+                    self.insert_mpls(trace, ttl, idx, &response.mpls)?;
+                    self.insert_modifications(trace, ttl, idx, &response.modifications)?;
                 }
                 TraceQuery::Timeout => {
-                    stmt.execute(params![ttl, trace_id, idx, "timeout", Null, Null])?;
+                    stmt.execute(params![
+                        ttl, trace_id, idx, "timeout", Null, Null, Null, Null, Null, Null
+                    ])?;
                 }
                 TraceQuery::Failure(_) => {
-                    stmt.execute(params![ttl, trace_id, idx, "fail", Null, Null])?;
+                    stmt.execute(params![
+                        ttl, trace_id, idx, "fail", Null, Null, Null, Null, Null, Null
+                    ])?;
                 }
             };
-            idx += 1;
         }
-        let hop_ids = self.show_hop_ids(&trace, ttl)?;
+        let hop_ids = self.show_hop_ids(trace, ttl)?;
 
         Ok(hop_ids)
     }
 
+    fn update_late(&self, late: &LateReply) -> Result<()> {
+        let conn = &self.db.connection;
+        let mut stmt = conn.prepare_cached(include_str!("sql/update-hop-late.sql"))?;
+
+        let trace_id = self.show_trace_id(&late.trace)?;
+        if let TraceQuery::Late(response) = &late.result {
+            stmt.execute(params![
+                late.ttl,
+                trace_id,
+                late.query,
+                &response.addr.to_string(),
+                response.rtt.as_micros() as i64,
+                response.reply_ttl,
+                response.quoted_ttl,
+                late.result.reverse_hops(),
+                late.result.hidden_hops()
+            ])?;
+            self.insert_mpls(&late.trace, late.ttl, late.query, &response.mpls)?;
+            self.insert_modifications(&late.trace, late.ttl, late.query, &response.modifications)?;
+        }
+
+        Ok(())
+    }
+
+    fn update_stop_reason(&self, trace: &Uuid, reason: StopReason) -> Result<()> {
+        let conn = &self.db.connection;
+        let mut stmt = conn.prepare_cached(include_str!("sql/update-trace-stop.sql"))?;
+
+        stmt.execute(params![trace.to_string(), reason.to_string()])?;
+
+        Ok(())
+    }
+
+    fn insert_mpls(&self, trace: &Uuid, ttl: u8, query: u8, mpls: &[MplsLabel]) -> Result<()> {
+        if mpls.is_empty() {
+            return Ok(());
+        }
+
+        let conn = &self.db.connection;
+        let mut stmt = conn.prepare_cached(include_str!("sql/insert-mpls.sql"))?;
+
+        let hop_id = self.show_hop_id(trace, ttl, query)?;
+        for (position, entry) in (1..).zip(mpls) {
+            stmt.execute(params![
+                hop_id,
+                position,
+                entry.label,
+                entry.tc,
+                entry.bottom,
+                entry.ttl
+            ])?;
+        }
+
+        Ok(())
+    }
+
+    fn insert_modifications(
+        &self,
+        trace: &Uuid,
+        ttl: u8,
+        query: u8,
+        modifications: &[Modification],
+    ) -> Result<()> {
+        if modifications.is_empty() {
+            return Ok(());
+        }
+
+        let conn = &self.db.connection;
+        let mut stmt = conn.prepare_cached(include_str!("sql/insert-modification.sql"))?;
+
+        let hop_id = self.show_hop_id(trace, ttl, query)?;
+        for modification in modifications {
+            stmt.execute(params![
+                hop_id,
+                modification.field.to_string(),
+                &modification.sent,
+                &modification.quoted
+            ])?;
+        }
+
+        Ok(())
+    }
+
+    fn show_vertex_id(&self, trace_id: i64, ttl: u8, addr: &IpAddr) -> Result<i64> {
+        let conn = &self.db.connection;
+        let mut stmt = conn.prepare_cached(include_str!("sql/show-vertex.sql"))?;
+
+        let vertex_id: i64 =
+            stmt.query_row(params![trace_id, ttl, addr.to_string()], |row| row.get(0))?;
+
+        Ok(vertex_id)
+    }
+
+    fn insert_multipath(&self, multipath: &Multipath) -> Result<()> {
+        let conn = &self.db.connection;
+        let mut vertex_stmt = conn.prepare_cached(include_str!("sql/insert-vertex.sql"))?;
+        let mut edge_stmt = conn.prepare_cached(include_str!("sql/insert-edge.sql"))?;
+
+        let trace_id = self.show_trace_id(&multipath.trace)?;
+
+        for vertex in &multipath.vertices {
+            vertex_stmt.execute(params![trace_id, vertex.ttl, vertex.addr.to_string()])?;
+        }
+
+        for edge in &multipath.edges {
+            let source = self.show_vertex_id(trace_id, edge.ttl, &edge.from)?;
+            let destination = self.show_vertex_id(trace_id, edge.ttl + 1, &edge.to)?;
+            edge_stmt.execute(params![trace_id, source, destination])?;
+        }
+
+        Ok(())
+    }
+
+    fn insert_trace_config(&self, trace: &Uuid, config: &Config) -> Result<()> {
+        let conn = &self.db.connection;
+        let mut stmt = conn.prepare_cached(include_str!("sql/insert-trace-config.sql"))?;
+
+        let trace_id = self.show_trace_id(trace)?;
+        stmt.execute(params![
+            trace_id,
+            config.port,
+            config.first_ttl,
+            config.max_hops,
+            config.tries,
+            config.timeout.as_millis() as i64,
+            config.wait.as_millis() as i64,
+            config.size,
+            config.tos,
+            config.confidence,
+            config.window,
+            config.gap_limit,
+            config.interface
+        ])?;
+
+        Ok(())
+    }
+
+    fn insert_trace_origin(&self, trace: &Uuid, origin: &Origin) -> Result<()> {
+        let conn = &self.db.connection;
+        let mut stmt = conn.prepare_cached(include_str!("sql/insert-trace-origin.sql"))?;
+
+        let trace_id = self.show_trace_id(trace)?;
+        stmt.execute(params![
+            trace_id,
+            origin.platform.to_string(),
+            origin.vantage_point,
+            origin.measurement,
+            origin.started.map(|started| started as i64)
+        ])?;
+
+        Ok(())
+    }
+
+    fn insert_path_mtu(&self, path: &PathMtu) -> Result<()> {
+        let conn = &self.db.connection;
+        let mut hop_stmt = conn.prepare_cached(include_str!("sql/insert-hop-mtu.sql"))?;
+        let mut path_stmt = conn.prepare_cached(include_str!("sql/insert-path-mtu.sql"))?;
+
+        let trace_id = self.show_trace_id(&path.trace)?;
+
+        for hop in &path.hops {
+            hop_stmt.execute(params![trace_id, hop.ttl, hop.mtu])?;
+        }
+        path_stmt.execute(params![trace_id, path.mtu])?;
+
+        Ok(())
+    }
+
     fn insert_stats(&self, trace: &Uuid, ttl: u8, stats: &HopStats) -> Result<()> {
         let conn = &self.db.connection;
         let mut stmt = conn.prepare_cached(include_str!("sql/insert-stats.sql"))?;
 
-        let hop_ids = self.show_hop_ids(&trace, ttl)?;
+        let hop_ids = self.show_hop_ids(trace, ttl)?;
 
         for id in hop_ids {
             stmt.execute(params![
                 id,
-                stats.mean.map(|ms| ms.as_millis().to_string()),
-                stats.median.map(|ms| ms.as_millis().to_string())
+                stats.mean.map(|mean| mean.as_micros() as i64),
+                stats.median.map(|median| median.as_micros() as i64)
             ])?;
         }
 
@@ -426,7 +970,7 @@ impl Store {
         let conn = &self.db.connection;
         let mut stmt = conn.prepare_cached(include_str!("sql/insert-geoip.sql"))?;
 
-        let hop_id = self.show_hop_id(&trace, ttl, query)?;
+        let hop_id = self.show_hop_id(trace, ttl, query)?;
 
         stmt.execute(params![
             hop_id,
@@ -448,7 +992,30 @@ impl Store {
         Ok(())
     }
 
-    fn export_route(&self, source: &Ipv4Addr, destination: &Ipv4Addr) -> Result<Vec<ExportHop>> {
+    fn show_modifications(
+        &self,
+        source: &IpAddr,
+        destination: &IpAddr,
+    ) -> Result<Vec<ExportModification>> {
+        let conn = &self.db.connection;
+        let mut stmt = conn.prepare_cached(include_str!("sql/show-modifications.sql"))?;
+        let columns = columns_from_statement(&stmt);
+
+        let rows = stmt.query_and_then(
+            params![&source.to_string(), &destination.to_string()],
+            |row| from_row_with_columns::<ExportModification>(row, &columns),
+        )?;
+
+        let mut modifications: Vec<ExportModification> = vec![];
+
+        for row in rows {
+            modifications.push(row?);
+        }
+
+        Ok(modifications)
+    }
+
+    fn export_route(&self, source: &IpAddr, destination: &IpAddr) -> Result<Vec<ExportHop>> {
         let conn = &self.db.connection;
         let mut stmt = conn.prepare_cached(include_str!("sql/export-route.sql"))?;
         let columns = columns_from_statement(&stmt);
@@ -467,13 +1034,45 @@ impl Store {
         Ok(exports)
     }
 
-    fn show_geoip_for_addr(&self, source: &Ipv4Addr) -> Result<IpApiResp> {
+    fn show_budget(&self) -> Result<ProbeBudget> {
+        let conn = &self.db.connection;
+        let mut stmt = conn.prepare_cached(include_str!("sql/show-budget.sql"))?;
+
+        let mut rows = stmt.query([])?;
+        let budget = match rows.next()? {
+            Some(row) => ProbeBudget {
+                budget: row.get::<_, i64>(0)? as u64,
+                used: row.get::<_, i64>(1)? as u64,
+            },
+            None => ProbeBudget::default(),
+        };
+
+        Ok(budget)
+    }
+
+    fn update_budget(&self, budget: u64, sent: Vec<(u64, u64)>) -> Result<()> {
+        let conn = &self.db.connection;
+        let mut stmt = conn.prepare_cached(include_str!("sql/insert-budget.sql"))?;
+
+        // A budget without probes is stored for today.
+        if sent.is_empty() {
+            stmt.execute(params![budget as i64, 0, Option::<i64>::None])?;
+        }
+        for (day, sent) in sent {
+            stmt.execute(params![budget as i64, sent as i64, day as i64])?;
+        }
+
+        Ok(())
+    }
+
+    /// The stored geolocation of an address, if it was looked up before.
+    fn show_geoip_for_addr(&self, source: &IpAddr) -> Result<Option<IpApiResp>> {
         let conn = &self.db.connection;
         let mut stmt = conn.prepare_cached(include_str!("sql/show-geoip-for-hop.sql"))?;
 
         let result = stmt.query_row(params![source.to_string()], |row| {
             Ok(IpApiResp {
-                ip: source.clone(),
+                ip: *source,
                 city: row.get(0).ok(),
                 region: row.get(1).ok(),
                 region_code: row.get(2).ok(),
@@ -498,8 +1097,8 @@ impl Store {
                 currency_name: None,
                 languages: None,
             })
-        })?;
+        });
 
-        Ok(result)
+        Ok(result.optional()?)
     }
 }
@@ -0,0 +1,625 @@
+use std::{
+    io, mem,
+    net::{IpAddr, SocketAddr},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    prober::{Probe, Prober},
+    receiver::{enable_hop_limits, enable_timestamps, socket_ip, timestamp_instant, Answer},
+    traceroute::{bind_device, Config},
+    Error, Mode, Protocol, ReplyKind, Result,
+};
+
+/// How often the reader thread picks up the sockets of new probes and checks
+/// whether it should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// ICMP types of Echo Replies.
+const ECHO_REPLY: u8 = 0;
+const ECHO_REPLY_V6: u8 = 129;
+
+/// Test whether this process may open raw sockets, which takes root or
+/// CAP_NET_RAW on Linux.
+pub fn can_open_raw_sockets() -> bool {
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_RAW, libc::IPPROTO_ICMP) };
+    if fd < 0 {
+        return false;
+    }
+    unsafe { libc::close(fd) };
+
+    true
+}
+
+/// A socket probes are sent on, closed when it is dropped.
+struct Socket {
+    fd: libc::c_int,
+    /// Sequence number of the only probe sent on the socket. Replies on
+    /// sockets shared by probes carry the sequence themselves.
+    seq: Option<u16>,
+    /// The socket is closed if no reply came in until then.
+    expires: Option<Instant>,
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// Sends probes on datagram sockets that any user may open, and reads the
+/// ICMP errors they cause from the error queue of the sockets. ICMP probes
+/// are sent on a single ping socket, which needs the group of the user to be
+/// in `net.ipv4.ping_group_range`. UDP probes are each sent on a socket of
+/// their own, since the kernel doesn't hand out the UDP header quoted in
+/// errors, so classic traces are the only ones they support.
+///
+/// The kernel builds the headers of the probes, so replies carry no MPLS
+/// labels or modified header fields, and the source port and ICMP identifier
+/// are chosen by the kernel.
+pub(crate) struct Datagram {
+    protocol: Protocol,
+    source: IpAddr,
+    destination: IpAddr,
+    tos: u8,
+    interface: Option<String>,
+    /// The ping socket of ICMP traces.
+    ping: Option<libc::c_int>,
+    /// Sockets are only closed by the reader thread, so that none is closed
+    /// while it is polled.
+    sockets: Arc<Mutex<Vec<Socket>>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Datagram {
+    /// Open the sockets of a trace with `config` and start the reader thread,
+    /// which sends replies to `respond_to`. ICMP probes try to keep `ident`
+    /// as their identifier.
+    pub(crate) fn new(
+        source: IpAddr,
+        destination: IpAddr,
+        config: &Config,
+        ident: u16,
+        respond_to: mpsc::Sender<Answer>,
+    ) -> Result<Self> {
+        match (config.protocol, config.mode) {
+            (Protocol::Tcp, _) => {
+                return Err(Error::Unsupported(
+                    "tcp probes need raw sockets, run as root or grant CAP_NET_RAW".to_string(),
+                ))
+            }
+            (Protocol::Udp, Mode::Classic) | (Protocol::Icmp, _) => {}
+            (Protocol::Udp, mode) => {
+                return Err(Error::Unsupported(format!(
+                    "udp probes of {} traces need raw sockets, run as root, grant CAP_NET_RAW or use icmp probes",
+                    mode
+                )))
+            }
+        }
+
+        let mut datagram = Datagram {
+            protocol: config.protocol,
+            source,
+            destination,
+            tos: config.tos,
+            interface: config.interface.clone(),
+            ping: None,
+            sockets: Arc::new(Mutex::new(vec![])),
+            stop: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        };
+
+        if config.protocol == Protocol::Icmp {
+            let socket = datagram.open(ident).map_err(|e| {
+                Error::socket(
+                    "unable to open an ICMP datagram socket, add your group to net.ipv4.ping_group_range or use udp probes",
+                    e,
+                )
+            })?;
+            datagram.ping = Some(socket.fd);
+            datagram.sockets.lock().unwrap().push(socket);
+        }
+
+        let sockets = Arc::clone(&datagram.sockets);
+        let stop = Arc::clone(&datagram.stop);
+        datagram.thread = Some(thread::spawn(move || run(&sockets, respond_to, &stop)));
+
+        Ok(datagram)
+    }
+
+    /// Open a socket for the protocol of this trace, bound to the source
+    /// address and `port` if it is free, and ask the kernel to queue the ICMP
+    /// errors of its probes.
+    fn open(&self, port: u16) -> io::Result<Socket> {
+        let ipv6 = self.destination.is_ipv6();
+        let (family, protocol) = match (self.protocol, ipv6) {
+            (Protocol::Icmp, false) => (libc::AF_INET, libc::IPPROTO_ICMP),
+            (Protocol::Icmp, true) => (libc::AF_INET6, libc::IPPROTO_ICMPV6),
+            (_, false) => (libc::AF_INET, libc::IPPROTO_UDP),
+            (_, true) => (libc::AF_INET6, libc::IPPROTO_UDP),
+        };
+
+        let fd = unsafe { libc::socket(family, libc::SOCK_DGRAM, protocol) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = Socket {
+            fd,
+            seq: None,
+            expires: None,
+        };
+
+        if bind(fd, SocketAddr::new(self.source, port)).is_err() {
+            bind(fd, SocketAddr::new(self.source, 0))?;
+        }
+        if let Some(interface) = &self.interface {
+            bind_device(fd, interface)?;
+        }
+        enable_errors(fd, ipv6)?;
+        enable_timestamps(fd);
+        if ipv6 {
+            enable_hop_limits(fd);
+            set_option(fd, libc::IPPROTO_IPV6, libc::IPV6_TCLASS, self.tos.into())?;
+        } else {
+            set_option(fd, libc::IPPROTO_IP, libc::IP_RECVTTL, 1)?;
+            set_option(fd, libc::IPPROTO_IP, libc::IP_TOS, self.tos.into())?;
+        }
+        set_dont_fragment(fd, ipv6)?;
+
+        Ok(socket)
+    }
+}
+
+impl Prober for Datagram {
+    /// Send `probe` on a datagram socket. The kernel replaces its transport
+    /// header. UDP probes are each sent on a new socket, which is closed once
+    /// the probe expires. Replies are sent to the channel of the reader thread
+    /// instead of `respond_to`.
+    fn send(&mut self, probe: &Probe<'_>, _respond_to: &mpsc::Sender<Answer>) -> io::Result<()> {
+        let ipv6 = self.destination.is_ipv6();
+        let segment = probe.builder.segment(probe.packet);
+
+        match self.ping {
+            Some(fd) => {
+                set_ttl(fd, ipv6, probe.ttl)?;
+                send_to(fd, segment, SocketAddr::new(self.destination, 0))
+            }
+            None => {
+                let mut socket = self.open(0)?;
+                let fd = socket.fd;
+                set_ttl(fd, ipv6, probe.ttl)?;
+                socket.seq = Some(probe.seq);
+                socket.expires = Some(probe.expires);
+
+                // The socket is handed to the reader thread before the probe
+                // is sent so that no reply can arrive before it polls it.
+                self.sockets.lock().unwrap().push(socket);
+                let payload = segment.get(8..).unwrap_or_default();
+                send_to(fd, payload, SocketAddr::new(self.destination, probe.port))
+            }
+        }
+    }
+
+    /// The ICMP identifier the kernel puts into the probes of this trace.
+    fn ident(&self) -> Option<u16> {
+        self.ping.and_then(|fd| local_port(fd).ok())
+    }
+}
+
+impl Drop for Datagram {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Read replies until the trace is dropped, and close the sockets of probes
+/// that expired.
+fn run(sockets: &Mutex<Vec<Socket>>, respond_to: mpsc::Sender<Answer>, stop: &AtomicBool) {
+    let mut buf = [0u8; 4096];
+
+    while !stop.load(Ordering::Relaxed) {
+        let polled: Vec<(libc::c_int, Option<u16>)> = {
+            let mut sockets = sockets.lock().unwrap();
+            let now = Instant::now();
+            sockets.retain(|socket| socket.expires.is_none_or(|expires| expires > now));
+            sockets
+                .iter()
+                .map(|socket| (socket.fd, socket.seq))
+                .collect()
+        };
+
+        let mut pollfds: Vec<libc::pollfd> = polled
+            .iter()
+            .map(|&(fd, _)| libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+        let res = unsafe {
+            libc::poll(
+                pollfds.as_mut_ptr(),
+                pollfds.len() as libc::nfds_t,
+                POLL_INTERVAL.as_millis() as libc::c_int,
+            )
+        };
+        if res <= 0 {
+            continue;
+        }
+        let read = Instant::now();
+
+        for (pollfd, &(fd, seq)) in pollfds.iter().zip(&polled) {
+            // Errors are queued apart from the packets the socket receives.
+            if pollfd.revents & libc::POLLERR != 0 {
+                if let Ok(Some(answer)) = recv_error(fd, &mut buf, seq, read) {
+                    let _ = respond_to.send(answer);
+                }
+            }
+            if pollfd.revents & libc::POLLIN != 0 {
+                if let Ok(Some(answer)) = recv_echo(fd, &mut buf, read) {
+                    let _ = respond_to.send(answer);
+                }
+            }
+        }
+    }
+}
+
+/// A message read from a socket: its length, the address it came from, the
+/// TTL it arrived with, when the kernel received it and the extended error
+/// it reports, if the kernel reported them.
+struct Message {
+    len: usize,
+    addr: Option<IpAddr>,
+    ttl: Option<u8>,
+    stamp: Option<libc::timespec>,
+    error: Option<(ReplyKind, Option<IpAddr>)>,
+}
+
+impl Message {
+    /// The reply to probe `seq` from `addr` this message carries, read at
+    /// `read`.
+    fn answer(&self, seq: u16, addr: IpAddr, reply: ReplyKind, read: Instant) -> Answer {
+        Answer {
+            seq,
+            addr,
+            reply,
+            mpls: vec![],
+            ttl: self.ttl,
+            quoted_ttl: None,
+            modifications: vec![],
+            received: self
+                .stamp
+                .map_or(read, |stamp| timestamp_instant(stamp, read)),
+            // The kernel hands out the quoted probe, not the reply.
+            packet: vec![],
+        }
+    }
+}
+
+/// Read a packet, or an error if `flags` has MSG_ERRQUEUE, from `fd` into
+/// `buf`.
+fn recv(fd: libc::c_int, buf: &mut [u8], flags: libc::c_int) -> io::Result<Message> {
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    // Control messages are aligned like their headers.
+    let mut control = [0u64; 64];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut addr as *mut libc::sockaddr_storage as *mut libc::c_void;
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    let len = unsafe { libc::recvmsg(fd, &mut msg, flags) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut message = Message {
+        len: len as usize,
+        addr: socket_ip(&addr),
+        ttl: None,
+        stamp: None,
+        error: None,
+    };
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            let data = libc::CMSG_DATA(cmsg);
+            match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                (libc::IPPROTO_IP, libc::IP_TTL) | (libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT) => {
+                    message.ttl = Some(ptr::read_unaligned(data as *const libc::c_int) as u8);
+                }
+                (level, kind) => control_message(level, kind, data, &mut message),
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    Ok(message)
+}
+
+/// Read an ICMP error from the error queue of `fd`. Probes sent on a socket
+/// of their own have the sequence number `seq`, the others are ICMP probes
+/// whose header the kernel hands out with the error.
+fn recv_error(
+    fd: libc::c_int,
+    buf: &mut [u8],
+    seq: Option<u16>,
+    read: Instant,
+) -> io::Result<Option<Answer>> {
+    let message = recv(fd, buf, MSG_ERRQUEUE)?;
+    let (reply, offender) = match message.error {
+        Some(error) => error,
+        None => return Ok(None),
+    };
+    let seq = match seq.or_else(|| echo_seq(&buf[..message.len])) {
+        Some(seq) => seq,
+        None => return Ok(None),
+    };
+
+    Ok(offender.map(|addr| message.answer(seq, addr, reply, read)))
+}
+
+/// Read an Echo Reply from the ping socket `fd`. Packets received on UDP
+/// sockets are dropped.
+fn recv_echo(fd: libc::c_int, buf: &mut [u8], read: Instant) -> io::Result<Option<Answer>> {
+    let message = recv(fd, buf, 0)?;
+    let echo = &buf[..message.len];
+    if !matches!(echo.first(), Some(&ECHO_REPLY) | Some(&ECHO_REPLY_V6)) {
+        return Ok(None);
+    }
+
+    Ok(echo_seq(echo)
+        .zip(message.addr)
+        .map(|(seq, addr)| message.answer(seq, addr, ReplyKind::EchoReply, read)))
+}
+
+/// The sequence number of an ICMP Echo Request or Reply.
+fn echo_seq(echo: &[u8]) -> Option<u16> {
+    echo.get(6..8)
+        .map(|seq| u16::from_be_bytes([seq[0], seq[1]]))
+}
+
+#[cfg(target_os = "linux")]
+const MSG_ERRQUEUE: libc::c_int = libc::MSG_ERRQUEUE;
+
+#[cfg(not(target_os = "linux"))]
+const MSG_ERRQUEUE: libc::c_int = 0;
+
+/// Ask the kernel to queue the ICMP errors caused by the packets sent on
+/// `fd`, along with the TTL they arrived with.
+#[cfg(target_os = "linux")]
+fn enable_errors(fd: libc::c_int, ipv6: bool) -> io::Result<()> {
+    if ipv6 {
+        set_option(fd, libc::IPPROTO_IPV6, libc::IPV6_RECVERR, 1)
+    } else {
+        set_option(fd, libc::IPPROTO_IP, libc::IP_RECVERR, 1)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn enable_errors(_fd: libc::c_int, _ipv6: bool) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "unprivileged traces are only supported on Linux",
+    ))
+}
+
+/// Record the receive timestamp or the ICMP error reported in a control
+/// message in `message`.
+#[cfg(target_os = "linux")]
+unsafe fn control_message(
+    level: libc::c_int,
+    kind: libc::c_int,
+    data: *const libc::c_uchar,
+    message: &mut Message,
+) {
+    match (level, kind) {
+        (libc::SOL_SOCKET, libc::SO_TIMESTAMPNS) => {
+            message.stamp = Some(ptr::read_unaligned(data as *const libc::timespec));
+        }
+        (libc::IPPROTO_IP, libc::IP_RECVERR) | (libc::IPPROTO_IPV6, libc::IPV6_RECVERR) => {
+            message.error = extended_error(data as *const libc::sock_extended_err);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+unsafe fn control_message(
+    _level: libc::c_int,
+    _kind: libc::c_int,
+    _data: *const libc::c_uchar,
+    _message: &mut Message,
+) {
+}
+
+/// The kind of reply and the address of the node that sent it, of an ICMP
+/// error reported by the kernel.
+#[cfg(target_os = "linux")]
+unsafe fn extended_error(
+    error: *const libc::sock_extended_err,
+) -> Option<(ReplyKind, Option<IpAddr>)> {
+    let ee = ptr::read_unaligned(error);
+    let reply = match (ee.ee_origin, ee.ee_type, ee.ee_code) {
+        (libc::SO_EE_ORIGIN_ICMP, 11, _) => ReplyKind::TimeExceeded,
+        (libc::SO_EE_ORIGIN_ICMP, 3, 4) => ReplyKind::PacketTooBig {
+            mtu: ee.ee_info.min(u32::from(u16::MAX)) as u16,
+        },
+        (libc::SO_EE_ORIGIN_ICMP, 3, _) => ReplyKind::Unreachable,
+        (libc::SO_EE_ORIGIN_ICMP6, 3, _) => ReplyKind::TimeExceeded,
+        (libc::SO_EE_ORIGIN_ICMP6, 2, _) => ReplyKind::PacketTooBig {
+            mtu: ee.ee_info.min(u32::from(u16::MAX)) as u16,
+        },
+        (libc::SO_EE_ORIGIN_ICMP6, 1, _) => ReplyKind::Unreachable,
+        _ => return None,
+    };
+
+    // The address of the sender follows the error.
+    let mut offender: libc::sockaddr_storage = mem::zeroed();
+    let addr = libc::SO_EE_OFFENDER(error);
+    let len = match (*addr).sa_family as libc::c_int {
+        libc::AF_INET => mem::size_of::<libc::sockaddr_in>(),
+        libc::AF_INET6 => mem::size_of::<libc::sockaddr_in6>(),
+        _ => 0,
+    };
+    ptr::copy_nonoverlapping(
+        addr as *const u8,
+        &mut offender as *mut libc::sockaddr_storage as *mut u8,
+        len,
+    );
+
+    Some((reply, socket_ip(&offender)))
+}
+
+/// Set the DF flag on the packets sent on `fd`, without limiting them to the
+/// MTU the kernel learned for the path.
+#[cfg(target_os = "linux")]
+fn set_dont_fragment(fd: libc::c_int, ipv6: bool) -> io::Result<()> {
+    if ipv6 {
+        set_option(
+            fd,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_PROBE,
+        )?;
+        set_option(fd, libc::IPPROTO_IPV6, libc::IPV6_DONTFRAG, 1)
+    } else {
+        set_option(
+            fd,
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            libc::IP_PMTUDISC_PROBE,
+        )
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_dont_fragment(_fd: libc::c_int, _ipv6: bool) -> io::Result<()> {
+    Ok(())
+}
+
+/// Set the TTL or hop limit of the packets sent on `fd`.
+fn set_ttl(fd: libc::c_int, ipv6: bool, ttl: u8) -> io::Result<()> {
+    if ipv6 {
+        set_option(fd, libc::IPPROTO_IPV6, libc::IPV6_UNICAST_HOPS, ttl.into())
+    } else {
+        set_option(fd, libc::IPPROTO_IP, libc::IP_TTL, ttl.into())
+    }
+}
+
+fn set_option(
+    fd: libc::c_int,
+    level: libc::c_int,
+    option: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            option,
+            &value as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Convert a socket address to its C representation.
+fn sockaddr(addr: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(addr) => {
+            let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = addr.port().to_be();
+            sin.sin_addr.s_addr = u32::from(*addr.ip()).to_be();
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(addr) => {
+            let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = addr.port().to_be();
+            sin6.sin6_addr.s6_addr = addr.ip().octets();
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+
+    (storage, len as libc::socklen_t)
+}
+
+fn bind(fd: libc::c_int, addr: SocketAddr) -> io::Result<()> {
+    let (addr, len) = sockaddr(addr);
+    let res = unsafe {
+        libc::bind(
+            fd,
+            &addr as *const libc::sockaddr_storage as *const libc::sockaddr,
+            len,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+fn send_to(fd: libc::c_int, buf: &[u8], addr: SocketAddr) -> io::Result<()> {
+    let (addr, len) = sockaddr(addr);
+    let res = unsafe {
+        libc::sendto(
+            fd,
+            buf.as_ptr() as *const libc::c_void,
+            buf.len(),
+            0,
+            &addr as *const libc::sockaddr_storage as *const libc::sockaddr,
+            len,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// The local port `fd` is bound to, which is the identifier of the Echo
+/// Requests sent on ping sockets.
+fn local_port(fd: libc::c_int) -> io::Result<u16> {
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let res = unsafe {
+        libc::getsockname(
+            fd,
+            &mut addr as *mut libc::sockaddr_storage as *mut libc::sockaddr,
+            &mut len,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // The port is at the same offset in addresses of both families.
+    let addr = unsafe { &*(&addr as *const _ as *const libc::sockaddr_in) };
+    Ok(u16::from_be(addr.sin_port))
+}
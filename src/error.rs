@@ -0,0 +1,65 @@
+use std::{fmt, io};
+
+/// Errors returned by the tracer library.
+#[derive(Debug)]
+pub enum Error {
+    /// This process lacks the privileges to open a socket, like raw sockets
+    /// without root or CAP_NET_RAW.
+    Permission(String),
+    /// A network interface or source address is unknown or can't reach the
+    /// destination.
+    Interface(String),
+    /// A socket couldn't be opened, configured or used.
+    Socket(String),
+    /// The database or a capture file couldn't be opened, read or written.
+    Storage(String),
+    /// The geolocation provider isn't configured or its lookup failed.
+    Geo(String),
+    /// A value couldn't be parsed.
+    Parse(String),
+    /// The configuration asks for probes the chosen backend can't send.
+    Unsupported(String),
+}
+
+impl Error {
+    /// The error of a failed socket operation, described by `context`.
+    /// Operations that were denied are permission errors.
+    pub(crate) fn socket(context: &str, error: io::Error) -> Self {
+        let message = format!("{}: {}", context, error);
+        match error.kind() {
+            io::ErrorKind::PermissionDenied => Error::Permission(message),
+            _ => Error::Socket(message),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Permission(message)
+            | Error::Interface(message)
+            | Error::Socket(message)
+            | Error::Storage(message)
+            | Error::Geo(message)
+            | Error::Parse(message)
+            | Error::Unsupported(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<rusqlite::Error> for Error {
+    fn from(error: rusqlite::Error) -> Self {
+        Error::Storage(error.to_string())
+    }
+}
+
+impl From<serde_rusqlite::Error> for Error {
+    fn from(error: serde_rusqlite::Error) -> Self {
+        Error::Storage(error.to_string())
+    }
+}
+
+/// Results of the tracer library.
+pub type Result<T> = std::result::Result<T, Error>;
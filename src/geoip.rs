@@ -1,11 +1,12 @@
-use anyhow::{Error, Result};
 use serde::Deserialize;
 use std::env;
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::IpAddr;
+
+use crate::{Error, Result};
 
 #[derive(Debug, Deserialize)]
 pub struct IpApiResp {
-    pub ip: Ipv4Addr,
+    pub ip: IpAddr,
     pub city: Option<String>,
     pub region: Option<String>,
     pub region_code: Option<String>,
@@ -34,11 +35,19 @@ pub struct IpApiResp {
 pub(crate) fn fetch_ip_api(ip: &IpAddr) -> Result<IpApiResp> {
     let api_key = match env::var("TRACER_IPAPI_KEY") {
         Ok(val) => val,
-        Err(_) => return Err(Error::msg("Set the TRACER_IPAPI_KEY environment variable.")),
+        Err(_) => {
+            return Err(Error::Geo(
+                "Set the TRACER_IPAPI_KEY environment variable.".to_string(),
+            ))
+        }
     };
 
-    let url = format!("https://ipapi.co/{}/json/?key={}", ip.to_string(), api_key);
-    let resp = ureq::get(url.as_str()).call()?.into_json()?;
+    let url = format!("https://ipapi.co/{}/json/?key={}", ip, api_key);
+    let resp = ureq::get(url.as_str())
+        .call()
+        .map_err(|e| Error::Geo(format!("looking up {}: {}", ip, e)))?
+        .into_json()
+        .map_err(|e| Error::Geo(format!("reading the lookup of {}: {}", ip, e)))?;
 
     Ok(resp)
 }
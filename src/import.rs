@@ -0,0 +1,123 @@
+use std::{fmt, fs, net::IpAddr, path::Path, str::FromStr};
+
+use crate::{Config, Error, Hop, Origin, Result, StopReason, Trace};
+
+mod atlas;
+mod text;
+mod warts;
+
+/// The formats traces of other tools can be imported from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// The binary format of scamper.
+    Warts,
+    /// The JSON of RIPE Atlas traceroute results, either an array or one
+    /// result per line.
+    Atlas,
+    /// The text of tracer's hop logs, Linux traceroute and mtr reports, and
+    /// the JSON of mtr reports.
+    Text,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Warts => write!(f, "warts"),
+            Format::Atlas => write!(f, "atlas"),
+            Format::Text => write!(f, "text"),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "warts" => Ok(Format::Warts),
+            "atlas" => Ok(Format::Atlas),
+            "text" => Ok(Format::Text),
+            _ => Err(Error::Parse(format!("{:?} is an invalid format", s))),
+        }
+    }
+}
+
+/// A trace read from the results of another tool.
+#[derive(Debug, Clone)]
+pub struct ImportedTrace {
+    pub trace: Trace,
+    pub origin: Origin,
+    /// The configuration of the trace as far as the results tell it.
+    pub config: Config,
+    pub hops: Vec<Hop>,
+    pub stop_reason: Option<StopReason>,
+}
+
+impl ImportedTrace {
+    /// Set the source of a trace whose results don't record it, like the
+    /// text of a traceroute. The source is its vantage point too, unless the
+    /// results name the host. Traces with a source are left as they are.
+    pub fn with_source(mut self, source: IpAddr) -> Self {
+        if !self.trace.route.source.is_unspecified() {
+            return self;
+        }
+
+        self.trace.route.source = source;
+        for hop in &mut self.hops {
+            hop.source = source;
+        }
+        if self.origin.vantage_point.is_empty() {
+            self.origin.vantage_point = source.to_string();
+        }
+
+        self
+    }
+}
+
+/// Read the traces of a file of another tool. The format is detected from
+/// the content of the file unless it is given. Results that aren't traces,
+/// like the pings of a warts file, are skipped.
+pub fn import(path: &Path, format: Option<Format>) -> Result<Vec<ImportedTrace>> {
+    let content =
+        fs::read(path).map_err(|e| Error::Storage(format!("{}: {}", path.display(), e)))?;
+    let traces = match format.map_or_else(|| detect(&content), Ok) {
+        Ok(Format::Warts) => warts::parse(&content),
+        Ok(Format::Atlas) => atlas::parse(&content),
+        Ok(Format::Text) => text::parse(&content, path),
+        Err(e) => Err(e),
+    };
+
+    traces.map_err(|e| match e {
+        Error::Parse(message) => Error::Parse(format!("{}: {}", path.display(), message)),
+        e => e,
+    })
+}
+
+/// Detect the format of a file from its first bytes, or from the first
+/// lines of text.
+fn detect(content: &[u8]) -> Result<Format> {
+    match content {
+        [0x12, 0x05, ..] => Ok(Format::Warts),
+        [0x1f, 0x8b, ..] | [b'B', b'Z', b'h', ..] => Err(Error::Unsupported(
+            "compressed files must be decompressed before they are imported".to_string(),
+        )),
+        _ if text::detect(content) => Ok(Format::Text),
+        _ => match content.iter().find(|byte| !byte.is_ascii_whitespace()) {
+            Some(b'[') | Some(b'{') => Ok(Format::Atlas),
+            _ => Err(Error::Parse(
+                "the format of the file can't be detected".to_string(),
+            )),
+        },
+    }
+}
+
+/// Drop the hops beyond the first one the destination answered. Returns
+/// `Completed` if the destination answered.
+fn stop_at_destination(hops: &mut Vec<Hop>, destination: IpAddr) -> Option<StopReason> {
+    let idx = hops
+        .iter()
+        .position(|hop| hop.is_destination(destination))?;
+    hops.truncate(idx + 1);
+
+    Some(StopReason::Completed)
+}
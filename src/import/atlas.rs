@@ -0,0 +1,255 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::{net::IpAddr, time::Duration};
+
+use super::{stop_at_destination, ImportedTrace};
+use crate::{
+    Config, Error, Hop, Mode, MplsLabel, Origin, Platform, Protocol, ReplyKind, Response, Result,
+    StopReason, Trace, TraceQuery,
+};
+
+/// Atlas ends a trace that gave up after too many hops without a reply with
+/// a hop of this number.
+const GAVE_UP: u8 = 255;
+
+/// A measurement result of RIPE Atlas, which may be of another type than a
+/// traceroute.
+#[derive(Debug, Deserialize)]
+struct AtlasResult {
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    src_addr: Option<IpAddr>,
+    from: Option<IpAddr>,
+    dst_addr: Option<IpAddr>,
+    prb_id: Option<u64>,
+    msm_id: Option<u64>,
+    timestamp: Option<u64>,
+    proto: Option<String>,
+    paris_id: Option<u16>,
+    size: Option<u16>,
+    #[serde(default)]
+    result: Vec<AtlasHop>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AtlasHop {
+    hop: u8,
+    #[serde(default)]
+    result: Vec<AtlasReply>,
+    error: Option<String>,
+}
+
+/// A reply to a probe, or `x` if the probe timed out.
+#[derive(Debug, Deserialize)]
+struct AtlasReply {
+    from: Option<IpAddr>,
+    rtt: Option<f64>,
+    ttl: Option<u8>,
+    /// The TTL the probe arrived with, left out if it is 1.
+    ittl: Option<u8>,
+    /// The code of an ICMP unreachable error, a letter or a number.
+    err: Option<Value>,
+    late: Option<u32>,
+    #[serde(default)]
+    dup: bool,
+    x: Option<String>,
+    error: Option<String>,
+    /// The TCP flags of the reply.
+    flags: Option<String>,
+    mtu: Option<u16>,
+    icmpext: Option<IcmpExtensions>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IcmpExtensions {
+    #[serde(default)]
+    obj: Vec<IcmpObject>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IcmpObject {
+    #[serde(default)]
+    mpls: Vec<AtlasMpls>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AtlasMpls {
+    label: u32,
+    exp: u8,
+    s: u8,
+    ttl: u8,
+}
+
+/// Parse the traceroute results of a file of RIPE Atlas results, which is
+/// either a JSON array or one result per line. Results of other types are
+/// skipped.
+pub(super) fn parse(content: &[u8]) -> Result<Vec<ImportedTrace>> {
+    let mut results = vec![];
+    for value in serde_json::Deserializer::from_slice(content).into_iter::<Value>() {
+        match value.map_err(|e| Error::Parse(e.to_string()))? {
+            Value::Array(values) => results.extend(values),
+            value => results.push(value),
+        }
+    }
+
+    let mut traces = vec![];
+    for (idx, value) in results.into_iter().enumerate() {
+        let result: AtlasResult = serde_json::from_value(value)
+            .map_err(|e| Error::Parse(format!("result {}: {}", idx + 1, e)))?;
+        if result.kind.as_deref() != Some("traceroute") {
+            continue;
+        }
+        if let Some(trace) = trace(result) {
+            traces.push(trace);
+        }
+    }
+
+    Ok(traces)
+}
+
+/// Convert a traceroute result. Returns `None` for results without a
+/// source or destination, like the ones of probes that couldn't resolve
+/// the target.
+fn trace(result: AtlasResult) -> Option<ImportedTrace> {
+    let source = result.src_addr.or(result.from)?;
+    let destination = result.dst_addr?;
+
+    let protocol = match result.proto.as_deref() {
+        Some("ICMP") => Protocol::Icmp,
+        Some("TCP") => Protocol::Tcp,
+        _ => Protocol::Udp,
+    };
+    // Every probe of a Paris trace shares the flow of its Paris ID.
+    let mode = match result.paris_id {
+        Some(id) if id > 0 => Mode::Paris,
+        _ => Mode::Classic,
+    };
+    let trace = Trace::new(source, destination, protocol, mode);
+
+    let mut hops = result
+        .result
+        .into_iter()
+        .map(|hop| Hop {
+            trace: trace.id,
+            ttl: hop.hop,
+            source,
+            destination,
+            queries: match hop.error {
+                Some(error) => vec![TraceQuery::Failure(error)],
+                None => hop
+                    .result
+                    .into_iter()
+                    .filter(|reply| !reply.dup)
+                    .map(|reply| query(reply, protocol, destination))
+                    .collect(),
+            },
+        })
+        .collect::<Vec<_>>();
+
+    let mut stop_reason = stop_at_destination(&mut hops, destination);
+    if stop_reason.is_none()
+        && hops
+            .last()
+            .is_some_and(|hop| hop.ttl == GAVE_UP && hop.is_unresponsive())
+    {
+        hops.pop();
+        stop_reason = Some(StopReason::GapLimit);
+    }
+
+    let first_ttl = hops.first().map_or(1, |hop| hop.ttl);
+    let tries = hops
+        .iter()
+        .map(|hop| {
+            hop.queries
+                .iter()
+                .filter(|query| !matches!(query, TraceQuery::Late(_)))
+                .count()
+        })
+        .max()
+        .unwrap_or(0)
+        .clamp(1, usize::from(u8::MAX)) as u8;
+    let mut config = Config::default()
+        .with_protocol(protocol)
+        .with_mode(mode)
+        .with_first_ttl(first_ttl)
+        .with_tries(tries);
+    if let Some(size) = result.size {
+        config = config.with_size(size);
+    }
+
+    Some(ImportedTrace {
+        trace,
+        origin: Origin {
+            platform: Platform::RipeAtlas,
+            vantage_point: result
+                .prb_id
+                .map_or_else(|| source.to_string(), |id| id.to_string()),
+            measurement: result.msm_id.map(|id| id.to_string()),
+            started: result.timestamp,
+        },
+        config,
+        hops,
+        stop_reason,
+    })
+}
+
+/// Convert a reply to a probe into a query.
+fn query(reply: AtlasReply, protocol: Protocol, destination: IpAddr) -> TraceQuery {
+    if let Some(error) = reply.error {
+        return TraceQuery::Failure(error);
+    }
+    let addr = match (reply.x, reply.from) {
+        (None, Some(addr)) => addr,
+        _ => return TraceQuery::Timeout,
+    };
+
+    let kind = if let Some(mtu) = reply.mtu {
+        ReplyKind::PacketTooBig { mtu }
+    } else if reply.err.is_some() {
+        ReplyKind::Unreachable
+    } else if let Some(flags) = reply.flags.as_deref() {
+        match flags.contains('R') {
+            true => ReplyKind::Reset,
+            false => ReplyKind::SynAck,
+        }
+    } else if protocol == Protocol::Icmp && addr == destination {
+        ReplyKind::EchoReply
+    } else {
+        ReplyKind::TimeExceeded
+    };
+    // Only ICMP errors quote the probe.
+    let quoted_ttl = match kind {
+        ReplyKind::EchoReply | ReplyKind::SynAck | ReplyKind::Reset => None,
+        _ => Some(reply.ittl.unwrap_or(1)),
+    };
+    let mpls = reply
+        .icmpext
+        .map(|extensions| {
+            extensions
+                .obj
+                .into_iter()
+                .flat_map(|object| object.mpls)
+                .map(|entry| MplsLabel {
+                    label: entry.label,
+                    tc: entry.exp,
+                    bottom: entry.s == 1,
+                    ttl: entry.ttl,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let response = Response {
+        rtt: Duration::from_secs_f64(reply.rtt.unwrap_or(0.0).max(0.0) / 1000.0),
+        addr,
+        reply: kind,
+        mpls,
+        reply_ttl: reply.ttl,
+        quoted_ttl,
+        modifications: vec![],
+    };
+    match reply.late {
+        Some(_) => TraceQuery::Late(response),
+        None => TraceQuery::Success(response),
+    }
+}
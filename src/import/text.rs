@@ -0,0 +1,673 @@
+use serde_json::Value;
+use std::{
+    convert::TryFrom,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::Path,
+    time::Duration,
+};
+
+use super::{stop_at_destination, ImportedTrace};
+use crate::{
+    Config, Error, Hop, Mode, MplsLabel, Origin, Platform, Protocol, ReplyKind, Response, Result,
+    Trace, TraceQuery,
+};
+
+/// The file that is imported. tracer's logs are named after their
+/// destination, like `output/8.8.8.8.trace`, since they don't print it.
+struct Archive<'a> {
+    name: &'a str,
+    destination: Option<IpAddr>,
+}
+
+/// A trace as far as its text was read.
+struct TextTrace {
+    destination: Option<IpAddr>,
+    /// The host that printed the trace, if the text names it.
+    vantage_point: String,
+    started: Option<u64>,
+    config: Config,
+    /// The number of probes per hop, if the text tells it. It is counted
+    /// from the hops otherwise.
+    tries: Option<u8>,
+    hops: Vec<(u8, Vec<TraceQuery>)>,
+}
+
+impl TextTrace {
+    fn new(protocol: Protocol) -> Self {
+        TextTrace {
+            destination: None,
+            vantage_point: String::new(),
+            started: None,
+            config: Config::default().with_protocol(protocol),
+            tries: None,
+            hops: vec![],
+        }
+    }
+
+    /// The TTL of the hop that was read last.
+    fn last_ttl(&self) -> Option<u8> {
+        self.hops.last().map(|(ttl, _)| *ttl)
+    }
+}
+
+/// The formats of text, told apart by their first lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    HopLog,
+    Traceroute,
+    MtrReport,
+    MtrJson,
+}
+
+impl Kind {
+    /// Tell the format of the non-empty, trimmed lines of a text. Returns
+    /// `None` if it is none of them.
+    fn of(lines: &[&str]) -> Option<Self> {
+        let first = lines.first()?;
+        if first.starts_with('{') {
+            Some(Kind::MtrJson)
+        } else if first.starts_with('[') {
+            None
+        } else if lines.iter().any(|line| is_traceroute_header(line)) {
+            Some(Kind::Traceroute)
+        } else if lines
+            .iter()
+            .any(|line| line.starts_with("HOST:") || line.contains(".|--"))
+        {
+            Some(Kind::MtrReport)
+        } else if first.starts_with("trace ")
+            || first
+                .split_once(':')
+                .is_some_and(|(ttl, _)| ttl.parse::<u8>().is_ok())
+        {
+            Some(Kind::HopLog)
+        } else {
+            None
+        }
+    }
+}
+
+fn lines(text: &str) -> Vec<&str> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/// Test whether a file holds text this module reads. The JSON of mtr
+/// reports is told apart from other JSON by its `report` key.
+pub(super) fn detect(content: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(content);
+
+    match Kind::of(&lines(&text)) {
+        Some(Kind::MtrJson) => serde_json::Deserializer::from_slice(content)
+            .into_iter::<Value>()
+            .next()
+            .and_then(|value| value.ok())
+            .is_some_and(|value| value.get("report").is_some()),
+        kind => kind.is_some(),
+    }
+}
+
+/// Parse the text a traceroute printed: the hops tracer logs, the output of
+/// Linux traceroute or the report of mtr, either as text or as JSON. Every
+/// format is told apart by its first lines, and text of no known format is
+/// read as hops tracer logged. The source address isn't printed by any of
+/// them, so traces come with an unspecified source.
+pub(super) fn parse(content: &[u8], path: &Path) -> Result<Vec<ImportedTrace>> {
+    let text = String::from_utf8_lossy(content);
+    let archive = Archive {
+        name: path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default(),
+        destination: path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse().ok()),
+    };
+
+    let lines = lines(&text);
+    if lines.is_empty() {
+        return Ok(vec![]);
+    }
+    let (platform, traces) = match Kind::of(&lines).unwrap_or(Kind::HopLog) {
+        Kind::MtrJson => (Platform::Mtr, mtr_json(&text)?),
+        Kind::Traceroute => (Platform::Traceroute, traceroute(&text)?),
+        Kind::MtrReport => (Platform::Mtr, mtr_report(&text)?),
+        Kind::HopLog => (Platform::Tracer, hop_log(&text)?),
+    };
+
+    traces
+        .into_iter()
+        .filter(|trace| !trace.hops.is_empty())
+        .map(|trace| finish(trace, platform, &archive))
+        .collect()
+}
+
+/// Parse the hops tracer printed, one per line like
+/// `3: 10.0.0.1 (4.211ms) <MPLS L=24001,TC=0,S=1,TTL=1>  *  X`. A new trace
+/// starts with a `trace 2 of 3` line, or when the TTLs start over.
+fn hop_log(text: &str) -> Result<Vec<TextTrace>> {
+    let mut traces = vec![TextTrace::new(Protocol::Udp)];
+    for (idx, line) in text.lines().map(str::trim).enumerate() {
+        let error = |message: &str| Error::Parse(format!("line {}: {}", idx + 1, message));
+        if line.is_empty()
+            || line.starts_with("probe budget")
+            || line.starts_with("path mtu")
+            || line.starts_with("no traces")
+        {
+            continue;
+        }
+        if line.starts_with("trace ") {
+            traces.push(TextTrace::new(Protocol::Udp));
+            continue;
+        }
+
+        let (ttl, queries) = line
+            .split_once(':')
+            .ok_or_else(|| error("a hop doesn't start with its TTL"))?;
+        let ttl = ttl.parse::<u8>().map_err(|_| error("invalid TTL"))?;
+        let queries = queries
+            .split("  ")
+            .map(str::trim)
+            // The hops of `pmtu` end with the MTU of the path up to them.
+            .filter(|query| !query.is_empty() && !query.starts_with("mtu "))
+            .map(|query| logged_query(query).ok_or_else(|| error("invalid query")))
+            .collect::<Result<Vec<_>>>()?;
+
+        if traces[traces.len() - 1]
+            .last_ttl()
+            .is_some_and(|last| ttl <= last)
+        {
+            traces.push(TextTrace::new(Protocol::Udp));
+        }
+        traces.last_mut().unwrap().hops.push((ttl, queries));
+    }
+
+    Ok(traces)
+}
+
+/// Parse a query tracer printed: `*`, `X`, or the address and round-trip
+/// time of a reply with the markers that follow it. Older versions printed
+/// whole milliseconds.
+fn logged_query(query: &str) -> Option<TraceQuery> {
+    match query {
+        "*" => return Some(TraceQuery::Timeout),
+        "X" => return Some(TraceQuery::Failure(String::new())),
+        _ => {}
+    }
+
+    let (addr, rest) = query.split_once(" (")?;
+    let (rtt, markers) = rest.split_once(')')?;
+    let (rtt, late) = match rtt.strip_suffix(", late") {
+        Some(rtt) => (rtt, true),
+        None => (rtt, false),
+    };
+    let rtt = rtt.strip_suffix("ms")?.parse().ok()?;
+
+    let mut mpls = vec![];
+    let mut quoted_ttl = None;
+    for marker in markers.split('<').skip(1) {
+        let marker = marker.trim().strip_suffix('>')?;
+        if let Some(labels) = marker.strip_prefix("MPLS ") {
+            for label in labels.split_whitespace() {
+                mpls.push(mpls_label(label, ',', ["L", "TC", "S", "TTL"])?);
+            }
+        } else if let Some(hidden) = marker.strip_prefix("hidden ") {
+            // Hops are hidden when the quoted TTL is above 1.
+            quoted_ttl = Some(hidden.parse::<u8>().ok()?.checked_add(1)?);
+        }
+    }
+
+    let mut query = reply(addr.parse().ok()?, rtt, late);
+    if let TraceQuery::Success(response) | TraceQuery::Late(response) = &mut query {
+        response.mpls = mpls;
+        response.quoted_ttl = quoted_ttl;
+    }
+
+    Some(query)
+}
+
+fn is_traceroute_header(line: &str) -> bool {
+    line.starts_with("traceroute to ") || line.starts_with("traceroute6 to ")
+}
+
+/// Parse the output of Linux traceroute, like
+/// `traceroute to 8.8.8.8 (8.8.8.8), 30 hops max, 60 byte packets` and hops
+/// like ` 3  router (10.0.0.1)  4.211 ms !H  *  10.0.0.2  5.012 ms`. Each
+/// header starts a new trace.
+fn traceroute(text: &str) -> Result<Vec<TextTrace>> {
+    let mut traces: Vec<TextTrace> = vec![];
+    for (idx, line) in text.lines().map(str::trim).enumerate() {
+        let error = |message: &str| Error::Parse(format!("line {}: {}", idx + 1, message));
+        if line.is_empty() {
+            continue;
+        }
+        if is_traceroute_header(line) {
+            let mut trace = TextTrace::new(Protocol::Udp);
+            let (target, options) = line
+                .split_once(" to ")
+                .map(|(_, rest)| rest.split_once(',').unwrap_or((rest, "")))
+                .unwrap_or_default();
+            trace.destination = target
+                .split_once('(')
+                .and_then(|(_, addr)| addr.strip_suffix(')'))
+                .unwrap_or(target)
+                .trim()
+                .parse()
+                .ok();
+            for option in options.split(',').map(str::trim) {
+                if let Some(max_hops) = option.strip_suffix(" hops max") {
+                    trace.config = trace.config.with_max_hops(max_hops.parse().unwrap_or(30));
+                } else if let Some(size) = option.strip_suffix(" byte packets") {
+                    if let Ok(size) = size.parse() {
+                        trace.config = trace.config.with_size(size);
+                    }
+                }
+            }
+            traces.push(trace);
+            continue;
+        }
+
+        let trace = traces
+            .last_mut()
+            .ok_or_else(|| error("a hop comes before the header"))?;
+        let mut tokens = line.split_whitespace().peekable();
+        let ttl = tokens
+            .next()
+            .and_then(|ttl| ttl.parse::<u8>().ok())
+            .ok_or_else(|| error("a hop doesn't start with its TTL"))?;
+
+        // Every query is answered by the address printed last before it.
+        let mut addr = None;
+        let mut queries = vec![];
+        while let Some(token) = tokens.next() {
+            if token == "*" {
+                queries.push(TraceQuery::Timeout);
+            } else if let Some(annotation) = token.strip_prefix('!') {
+                let kind = match annotation.strip_prefix('F') {
+                    Some(mtu) => ReplyKind::PacketTooBig {
+                        mtu: mtu.trim_start_matches('-').parse().unwrap_or(0),
+                    },
+                    None => ReplyKind::Unreachable,
+                };
+                if let Some(TraceQuery::Success(Response { reply, .. })) = queries.last_mut() {
+                    *reply = kind;
+                }
+            } else if let Some(labels) = token.strip_prefix("<MPLS:") {
+                let labels = labels
+                    .trim_end_matches('>')
+                    .split('/')
+                    .map(|label| mpls_label(label, ',', ["L", "E", "S", "T"]))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| error("invalid MPLS label stack"))?;
+                if let Some(TraceQuery::Success(Response { mpls, .. })) = queries.last_mut() {
+                    *mpls = labels;
+                }
+            } else if let Some(ip) = token.strip_prefix('(').and_then(|t| t.strip_suffix(')')) {
+                addr = Some(ip.parse().map_err(|_| error("invalid address"))?);
+            } else if tokens.peek() == Some(&"ms") {
+                tokens.next();
+                let rtt = token
+                    .parse()
+                    .map_err(|_| error("invalid round-trip time"))?;
+                let addr = addr.ok_or_else(|| error("a reply has no address"))?;
+                queries.push(reply(addr, rtt, false));
+            } else {
+                // A host name is followed by its address, unless traceroute
+                // ran with -n and printed the address alone.
+                addr = token.parse().ok();
+            }
+        }
+        trace.hops.push((ttl, queries));
+    }
+
+    Ok(traces)
+}
+
+/// The default columns of mtr reports.
+const MTR_COLUMNS: [&str; 7] = ["Loss%", "Snt", "Last", "Avg", "Best", "Wrst", "StDev"];
+
+/// Parse a report of `mtr --report`, like
+/// `  3.|-- 10.0.0.1   0.0%    10    4.2   4.3   4.1   4.9   0.2`. Reports
+/// only have statistics per hop, so every reply of a hop gets its average
+/// round-trip time. The label stack of an address follows it in lines like
+/// `[MPLS: Lbl 24001 TC 0 S 1 TTL 1]`. Each `Start:` or `HOST:` line starts
+/// a new trace.
+fn mtr_report(text: &str) -> Result<Vec<TextTrace>> {
+    let mut traces: Vec<TextTrace> = vec![];
+    let mut columns = MTR_COLUMNS.to_vec();
+    // Whether the lines after a hop list its other addresses.
+    let mut other_address = false;
+    for (idx, line) in text.lines().map(str::trim).enumerate() {
+        let error = |message: &str| Error::Parse(format!("line {}: {}", idx + 1, message));
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('|') || line.starts_with('`') {
+            // Other addresses of a hop are listed below it without
+            // statistics.
+            other_address = true;
+            continue;
+        }
+        if let Some(label) = line
+            .strip_prefix("[MPLS:")
+            .and_then(|label| label.strip_suffix(']'))
+        {
+            let fields = label.split_whitespace().collect::<Vec<_>>();
+            let label = fields
+                .chunks(2)
+                .map(|field| field.join("="))
+                .collect::<Vec<_>>()
+                .join(",");
+            let label = mpls_label(&label, ',', ["Lbl", "TC", "S", "TTL"])
+                .ok_or_else(|| error("invalid MPLS label"))?;
+            let hop = traces.last_mut().and_then(|trace| trace.hops.last_mut());
+            if let (Some((_, queries)), false) = (hop, other_address) {
+                for query in queries {
+                    if let TraceQuery::Success(response) = query {
+                        response.mpls.push(label);
+                    }
+                }
+            }
+            continue;
+        }
+        other_address = false;
+        if let Some(started) = line.strip_prefix("Start:") {
+            let mut trace = TextTrace::new(Protocol::Icmp);
+            trace.started = timestamp(started.trim());
+            traces.push(trace);
+            continue;
+        }
+        if let Some(header) = line.strip_prefix("HOST:") {
+            if traces.last().is_none_or(|trace| !trace.hops.is_empty()) {
+                traces.push(TextTrace::new(Protocol::Icmp));
+            }
+            let mut header = header.split_whitespace();
+            traces.last_mut().unwrap().vantage_point = header.next().unwrap_or("").to_string();
+            columns = header.collect();
+            continue;
+        }
+
+        let (ttl, hop) = line
+            .split_once('.')
+            .ok_or_else(|| error("a hop doesn't start with its TTL"))?;
+        let ttl = ttl
+            .parse::<u8>()
+            .map_err(|_| error("a hop doesn't start with its TTL"))?;
+        let mut tokens = hop.trim_start_matches("|--").split_whitespace().peekable();
+        let host = tokens.next().ok_or_else(|| error("a hop has no host"))?;
+        let addr = match tokens.peek().and_then(|t| t.strip_prefix('(')) {
+            Some(ip) => {
+                tokens.next();
+                ip.trim_end_matches(')').parse().ok()
+            }
+            None => host.parse().ok(),
+        };
+        let values = tokens.collect::<Vec<_>>();
+        let column = |name: &str| -> Option<f64> {
+            let idx = columns.iter().position(|column| *column == name)?;
+            values.get(idx)?.trim_end_matches('%').parse().ok()
+        };
+
+        let sent = column("Snt").ok_or_else(|| error("a hop has no Snt column"))? as usize;
+        let loss = column("Loss%").unwrap_or(100.0);
+        let received = match addr {
+            Some(_) => (sent as f64 * (100.0 - loss) / 100.0).round() as usize,
+            None if host == "???" => 0,
+            None => {
+                return Err(Error::Unsupported(format!(
+                    "line {}: mtr reports with host names but no addresses can't be imported, \
+                     run mtr with -n or -b",
+                    idx + 1
+                )))
+            }
+        };
+        let rtt = column("Avg").unwrap_or(0.0);
+
+        let trace = match traces.last_mut() {
+            Some(trace) => trace,
+            None => {
+                traces.push(TextTrace::new(Protocol::Icmp));
+                traces.last_mut().unwrap()
+            }
+        };
+        let mut queries = addr
+            .map(|addr| vec![reply(addr, rtt, false); received.min(sent)])
+            .unwrap_or_default();
+        queries.resize(sent.max(queries.len()), TraceQuery::Timeout);
+        trace.tries = trace.tries.max(u8::try_from(sent).ok());
+        trace.hops.push((ttl, queries));
+    }
+
+    for trace in &mut traces {
+        trace.destination = trace.destination.or_else(|| last_address(trace));
+    }
+
+    Ok(traces)
+}
+
+/// Parse a report of `mtr --json`. Older versions of mtr print numbers as
+/// strings.
+fn mtr_json(text: &str) -> Result<Vec<TextTrace>> {
+    let value: Value = serde_json::from_str(text).map_err(|e| Error::Parse(e.to_string()))?;
+    let report = &value["report"];
+    let mtr = &report["mtr"];
+    let hubs = report["hubs"]
+        .as_array()
+        .ok_or_else(|| Error::Parse("the report has no hubs".to_string()))?;
+    let number = |value: &Value| -> Option<f64> {
+        match value {
+            Value::Number(number) => number.as_f64(),
+            Value::String(string) => {
+                let string = string.trim_end_matches('%');
+                match string.strip_prefix("0x") {
+                    Some(hex) => u64::from_str_radix(hex, 16).ok().map(|n| n as f64),
+                    None => string.parse().ok(),
+                }
+            }
+            _ => None,
+        }
+    };
+
+    let mut trace = TextTrace::new(Protocol::Icmp);
+    trace.vantage_point = mtr["src"].as_str().unwrap_or_default().to_string();
+    trace.destination = mtr["dst"].as_str().and_then(|dst| dst.parse().ok());
+    trace.tries = number(&mtr["tests"]).map(|tests| tests as u8);
+    if let Some(size) = number(&mtr["psize"]) {
+        trace.config = trace.config.with_size(size as u16);
+    }
+    if let Some(tos) = number(&mtr["tos"]) {
+        trace.config = trace.config.with_tos(tos as u8);
+    }
+
+    for (idx, hub) in hubs.iter().enumerate() {
+        let ttl = number(&hub["count"]).unwrap_or(idx as f64 + 1.0) as u8;
+        let host = hub["host"].as_str().unwrap_or("???");
+        let addr = host
+            .split_once('(')
+            .map_or(host, |(_, ip)| ip.trim_end_matches(')'))
+            .parse::<IpAddr>()
+            .ok();
+        if addr.is_none() && host != "???" {
+            return Err(Error::Unsupported(format!(
+                "hop {} of the report has the host name {} but no address, run mtr with -n or -b",
+                ttl, host
+            )));
+        }
+
+        let sent = number(&hub["Snt"]).unwrap_or(0.0) as usize;
+        let loss = number(&hub["Loss%"]).unwrap_or(100.0);
+        let received = (sent as f64 * (100.0 - loss) / 100.0).round() as usize;
+        let rtt = number(&hub["Avg"]).unwrap_or(0.0);
+        let mut queries = addr
+            .map(|addr| vec![reply(addr, rtt, false); received.min(sent)])
+            .unwrap_or_default();
+        queries.resize(sent.max(queries.len()), TraceQuery::Timeout);
+
+        // Hubs of the same TTL are other addresses of a hop.
+        match trace.hops.last_mut() {
+            Some((last, hop)) if *last == ttl => hop.extend(queries),
+            _ => trace.hops.push((ttl, queries)),
+        }
+    }
+    trace.destination = trace.destination.or_else(|| last_address(&trace));
+
+    Ok(vec![trace])
+}
+
+/// The address that answered the last hop with a reply. mtr stops at the
+/// destination, so it is the destination if mtr didn't print it.
+fn last_address(trace: &TextTrace) -> Option<IpAddr> {
+    trace.hops.iter().rev().find_map(|(_, queries)| {
+        queries.iter().find_map(|query| match query {
+            TraceQuery::Success(response) => Some(response.addr),
+            _ => None,
+        })
+    })
+}
+
+/// Build the trace of a text. Replies of the destination are taken as the
+/// reply the destination sends to the protocol of the trace, since the
+/// text doesn't tell the kind of a reply.
+fn finish(trace: TextTrace, platform: Platform, archive: &Archive<'_>) -> Result<ImportedTrace> {
+    let destination = trace.destination.or(archive.destination).ok_or_else(|| {
+        Error::Parse(
+            "the destination of a trace is unknown, name the file after it like 8.8.8.8.trace"
+                .to_string(),
+        )
+    })?;
+    let source = match destination {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let protocol = trace.config.protocol;
+    let final_reply = match protocol {
+        Protocol::Udp => ReplyKind::Unreachable,
+        Protocol::Icmp => ReplyKind::EchoReply,
+        Protocol::Tcp => ReplyKind::SynAck,
+    };
+
+    let imported = Trace::new(source, destination, protocol, Mode::Classic);
+    let mut hops = trace
+        .hops
+        .into_iter()
+        .map(|(ttl, mut queries)| {
+            for query in &mut queries {
+                if let TraceQuery::Success(response) | TraceQuery::Late(response) = query {
+                    if response.addr == destination && response.reply == ReplyKind::TimeExceeded {
+                        response.reply = final_reply;
+                    }
+                }
+            }
+            Hop {
+                trace: imported.id,
+                ttl,
+                source,
+                destination,
+                queries,
+            }
+        })
+        .collect::<Vec<_>>();
+    let stop_reason = stop_at_destination(&mut hops, destination);
+
+    let tries = trace.tries.unwrap_or_else(|| {
+        hops.iter()
+            .map(|hop| hop.queries.len())
+            .max()
+            .unwrap_or(0)
+            .min(usize::from(u8::MAX)) as u8
+    });
+    let config = trace
+        .config
+        .with_first_ttl(hops.first().map_or(1, |hop| hop.ttl))
+        .with_tries(tries.max(1));
+
+    Ok(ImportedTrace {
+        trace: imported,
+        origin: Origin {
+            platform,
+            vantage_point: trace.vantage_point,
+            measurement: Some(archive.name.to_string()).filter(|name| !name.is_empty()),
+            started: trace.started,
+        },
+        config,
+        hops,
+        stop_reason,
+    })
+}
+
+/// A reply from `addr` after `rtt` milliseconds. The kind of the reply
+/// isn't printed, so it is taken as Time Exceeded until it is known to be
+/// from the destination.
+fn reply(addr: IpAddr, rtt: f64, late: bool) -> TraceQuery {
+    let response = Response {
+        rtt: Duration::from_secs_f64(rtt.max(0.0) / 1000.0),
+        addr,
+        reply: ReplyKind::TimeExceeded,
+        mpls: vec![],
+        reply_ttl: None,
+        quoted_ttl: None,
+        modifications: vec![],
+    };
+
+    match late {
+        true => TraceQuery::Late(response),
+        false => TraceQuery::Success(response),
+    }
+}
+
+/// Parse an entry of a label stack like `L=24001,TC=0,S=1,TTL=1`, whose
+/// fields are named `names` in the order label, traffic class, bottom of
+/// stack and TTL.
+fn mpls_label(entry: &str, separator: char, names: [&str; 4]) -> Option<MplsLabel> {
+    let mut fields = [0u32; 4];
+    for field in entry.split(separator) {
+        let (name, value) = field.split_once('=')?;
+        let idx = names.iter().position(|known| *known == name)?;
+        fields[idx] = value.parse().ok()?;
+    }
+
+    Some(MplsLabel {
+        label: fields[0],
+        tc: fields[1] as u8,
+        bottom: fields[2] == 1,
+        ttl: fields[3] as u8,
+    })
+}
+
+/// Parse the start of an mtr report like `2023-05-04T10:00:00+0200` into
+/// seconds since the epoch. Older versions of mtr print the local time
+/// without its zone, which is left out.
+fn timestamp(time: &str) -> Option<u64> {
+    let (date, time) = time.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>());
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+
+    let (clock, offset) = match time.find(['+', '-']) {
+        Some(idx) => time.split_at(idx),
+        None => (time.trim_end_matches('Z'), "+0000"),
+    };
+    let mut clock = clock.splitn(3, ':').map(|part| part.parse::<i64>());
+    let (hour, minute, second) = (
+        clock.next()?.ok()?,
+        clock.next()?.ok()?,
+        clock.next().unwrap_or(Ok(0)).ok()?,
+    );
+    let sign = if offset.starts_with('-') { -1 } else { 1 };
+    let offset = offset[1..].replace(':', "");
+    let offset = offset.get(..2)?.parse::<i64>().ok()? * 3600
+        + offset.get(2..4).unwrap_or("0").parse::<i64>().ok()? * 60;
+
+    // Days since the epoch of the proleptic Gregorian calendar.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let seconds = days * 86_400 + hour * 3600 + minute * 60 + second - sign * offset;
+    u64::try_from(seconds).ok()
+}
@@ -0,0 +1,545 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+
+use super::ImportedTrace;
+use crate::{
+    Config, Error, Hop, Mode, MplsLabel, Origin, Platform, Protocol, ReplyKind, Response, Result,
+    StopReason, Trace, TraceQuery,
+};
+
+/// Every record starts with this magic.
+const MAGIC: u16 = 0x1205;
+
+/// Types of records.
+const LIST: u16 = 0x0001;
+const CYCLE_START: u16 = 0x0002;
+const CYCLE_DEF: u16 = 0x0003;
+const TRACE: u16 = 0x0006;
+
+/// Types of traces.
+const TRACE_ICMP_ECHO: u8 = 0x01;
+const TRACE_UDP: u8 = 0x02;
+const TRACE_TCP: u8 = 0x03;
+const TRACE_ICMP_ECHO_PARIS: u8 = 0x04;
+const TRACE_UDP_PARIS: u8 = 0x05;
+const TRACE_TCP_ACK: u8 = 0x06;
+
+/// Reasons traces stopped.
+const STOP_COMPLETED: u8 = 0x01;
+const STOP_GAPLIMIT: u8 = 0x05;
+const STOP_HOPLIMIT: u8 = 0x07;
+
+/// Flags of hops.
+const HOP_TCP: u8 = 0x20;
+const HOP_UDP: u8 = 0x40;
+
+/// Types of addresses.
+const ADDR_IPV4: u8 = 0x01;
+const ADDR_IPV6: u8 = 0x02;
+
+/// Defaults of scamper for parameters a trace doesn't record.
+const DEFAULT_ATTEMPTS: u8 = 2;
+const DEFAULT_WAIT: u8 = 5;
+
+/// The fields of a warts record, which are in network byte order.
+struct Cursor<'a> {
+    buf: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Cursor { buf, offset: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.offset >= self.buf.len()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .buf
+            .get(self.offset..self.offset + len)
+            .ok_or_else(|| Error::Parse("a warts record is truncated".to_string()))?;
+        self.offset += len;
+
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// A time as seconds and microseconds. Returns the seconds.
+    fn timeval(&mut self) -> Result<u32> {
+        let seconds = self.u32()?;
+        self.u32()?;
+
+        Ok(seconds)
+    }
+
+    /// A string that ends with a null byte.
+    fn string(&mut self) -> Result<String> {
+        let rest = &self.buf[self.offset.min(self.buf.len())..];
+        let len = rest
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or_else(|| Error::Parse("a warts string isn't terminated".to_string()))?;
+        let string = String::from_utf8_lossy(&rest[..len]).into_owned();
+        self.offset += len + 1;
+
+        Ok(string)
+    }
+
+    /// An address, either defined in place or referring to an address
+    /// defined earlier in the same record by its index. Addresses other than
+    /// IP addresses are `None`.
+    fn address(&mut self, table: &mut Vec<Option<IpAddr>>) -> Result<Option<IpAddr>> {
+        let len = self.u8()?;
+        if len == 0 {
+            let id = self.u32()? as usize;
+            return table.get(id).copied().ok_or_else(|| {
+                Error::Parse(format!("a warts record refers to unknown address {}", id))
+            });
+        }
+
+        let kind = self.u8()?;
+        let bytes = self.bytes(usize::from(len))?;
+        let addr = match (kind, bytes.len()) {
+            (ADDR_IPV4, 4) => Some(IpAddr::V4(Ipv4Addr::new(
+                bytes[0], bytes[1], bytes[2], bytes[3],
+            ))),
+            (ADDR_IPV6, 16) => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(bytes);
+                Some(IpAddr::V6(Ipv6Addr::from(octets)))
+            }
+            _ => None,
+        };
+        table.push(addr);
+
+        Ok(addr)
+    }
+
+    /// The flags that tell which parameters follow, and the parameters.
+    fn params(&mut self) -> Result<Params<'a>> {
+        let mut flags = vec![self.u8()?];
+        // Records without any flag set have no parameters, not even their
+        // length.
+        if flags[0] == 0 {
+            return Ok(Params {
+                flags,
+                fields: Cursor::new(&[]),
+            });
+        }
+        // The high bit of each byte of flags tells whether another follows.
+        while flags[flags.len() - 1] & 0x80 != 0 {
+            flags.push(self.u8()?);
+        }
+        let len = usize::from(self.u16()?);
+
+        Ok(Params {
+            flags,
+            fields: Cursor::new(self.bytes(len)?),
+        })
+    }
+}
+
+/// The parameters of a record, in the order of the flags that are set.
+/// Parameters after the ones that are read are skipped, so parameters of
+/// later versions of scamper are ignored.
+struct Params<'a> {
+    flags: Vec<u8>,
+    fields: Cursor<'a>,
+}
+
+impl<'a> Params<'a> {
+    /// Test whether the parameter `id` is present. Ids start at 1, each byte
+    /// of flags holds seven of them.
+    fn has(&self, id: usize) -> bool {
+        let (byte, bit) = ((id - 1) / 7, (id - 1) % 7);
+        self.flags
+            .get(byte)
+            .is_some_and(|flags| flags & (1 << bit) != 0)
+    }
+
+    /// Read the parameter `id` with `read` if it is present. Every parameter
+    /// before it that is present must have been read.
+    fn read<T>(&mut self, id: usize, read: fn(&mut Cursor<'a>) -> Result<T>) -> Result<Option<T>> {
+        if self.has(id) {
+            read(&mut self.fields).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn address(&mut self, id: usize, table: &mut Vec<Option<IpAddr>>) -> Result<Option<IpAddr>> {
+        if self.has(id) {
+            self.fields.address(table)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// A list of destinations scamper probed, and the monitor that probed them.
+struct List {
+    name: String,
+    monitor: Option<String>,
+}
+
+/// A round of probing a list.
+struct Cycle {
+    id: u32,
+    hostname: Option<String>,
+}
+
+/// A reply to a probe of a trace.
+struct WartsHop {
+    addr: Option<IpAddr>,
+    probe_ttl: u8,
+    reply_ttl: Option<u8>,
+    flags: u8,
+    probe_id: u8,
+    rtt: u32,
+    icmp_type: u8,
+    icmp_code: u8,
+    next_hop_mtu: Option<u16>,
+    quoted_ttl: Option<u8>,
+    tcp_flags: u8,
+    mpls: Vec<MplsLabel>,
+}
+
+/// Parse the traces of a warts file. Lists and cycles give the vantage
+/// point and measurement of the traces that follow them.
+pub(super) fn parse(content: &[u8]) -> Result<Vec<ImportedTrace>> {
+    let mut lists = HashMap::new();
+    let mut cycles = HashMap::new();
+    let mut traces = vec![];
+
+    let mut records = Cursor::new(content);
+    while !records.is_empty() {
+        let offset = records.offset;
+        if records.u16()? != MAGIC {
+            return Err(Error::Parse(format!(
+                "no warts record at offset {}",
+                offset
+            )));
+        }
+        let kind = records.u16()?;
+        let len = records.u32()? as usize;
+        let mut record = Cursor::new(records.bytes(len)?);
+
+        match kind {
+            LIST => {
+                let id = record.u32()?;
+                record.u32()?;
+                let name = record.string()?;
+                let mut params = record.params()?;
+                params.read(1, Cursor::string)?;
+                let monitor = params.read(2, Cursor::string)?;
+                lists.insert(id, List { name, monitor });
+            }
+            CYCLE_START | CYCLE_DEF => {
+                let id = record.u32()?;
+                record.u32()?;
+                let cycle = record.u32()?;
+                record.u32()?;
+                let mut params = record.params()?;
+                params.read(1, Cursor::u32)?;
+                let hostname = params.read(2, Cursor::string)?;
+                cycles.insert(
+                    id,
+                    Cycle {
+                        id: cycle,
+                        hostname,
+                    },
+                );
+            }
+            TRACE => traces.push(trace(&mut record, &lists, &cycles)?),
+            _ => {}
+        }
+    }
+
+    Ok(traces)
+}
+
+/// Parse a trace record.
+fn trace(
+    record: &mut Cursor<'_>,
+    lists: &HashMap<u32, List>,
+    cycles: &HashMap<u32, Cycle>,
+) -> Result<ImportedTrace> {
+    let mut table = vec![];
+    let mut params = record.params()?;
+    let list = params.read(1, Cursor::u32)?;
+    let cycle = params.read(2, Cursor::u32)?;
+    let source_gid = params.read(3, Cursor::u32)?;
+    params.read(4, Cursor::u32)?;
+    let started = params.read(5, Cursor::timeval)?;
+    let stop_reason = params.read(6, Cursor::u8)?;
+    params.read(7, Cursor::u8)?;
+    params.read(8, Cursor::u8)?;
+    let attempts = params.read(9, Cursor::u8)?;
+    let hop_limit = params.read(10, Cursor::u8)?;
+    let kind = params.read(11, Cursor::u8)?;
+    let size = params.read(12, Cursor::u16)?;
+    params.read(13, Cursor::u16)?;
+    let port = params.read(14, Cursor::u16)?;
+    let first_ttl = params.read(15, Cursor::u8)?;
+    let tos = params.read(16, Cursor::u8)?;
+    let timeout = params.read(17, Cursor::u8)?;
+    params.read(18, Cursor::u8)?;
+    let hop_count = params.read(19, Cursor::u16)?;
+    let gap_limit = params.read(20, Cursor::u8)?;
+    params.read(21, Cursor::u8)?;
+    params.read(22, Cursor::u8)?;
+    params.read(23, Cursor::u16)?;
+    let wait = params.read(24, Cursor::u8)?;
+    params.read(25, Cursor::u8)?;
+    let source = params.address(26, &mut table)?;
+    let destination = params.address(27, &mut table)?;
+
+    let (source, destination) = match (source, destination) {
+        (Some(source), Some(destination)) => (source, destination),
+        _ if source_gid.is_some() => {
+            return Err(Error::Unsupported(
+                "traces of warts files with global address records can't be imported".to_string(),
+            ))
+        }
+        _ => return Err(Error::Parse("a warts trace has no addresses".to_string())),
+    };
+
+    let mut replies = vec![];
+    for _ in 0..record.u16()? {
+        replies.push(hop(record, &mut table)?);
+    }
+
+    let (protocol, mode) = match kind.unwrap_or(TRACE_UDP) {
+        TRACE_ICMP_ECHO => (Protocol::Icmp, Mode::Classic),
+        TRACE_ICMP_ECHO_PARIS => (Protocol::Icmp, Mode::Paris),
+        TRACE_UDP_PARIS => (Protocol::Udp, Mode::Paris),
+        TRACE_TCP | TRACE_TCP_ACK => (Protocol::Tcp, Mode::Classic),
+        _ => (Protocol::Udp, Mode::Classic),
+    };
+    let trace = Trace::new(source, destination, protocol, mode);
+
+    let first_ttl = first_ttl.unwrap_or(1).max(1);
+    let attempts = attempts.unwrap_or(DEFAULT_ATTEMPTS).max(1);
+    // Hops that answered no probe aren't recorded, so every TTL up to the
+    // last one probed starts out timed out.
+    let last_ttl = replies
+        .iter()
+        .map(|reply| u16::from(reply.probe_ttl))
+        .chain(hop_count)
+        .max()
+        .unwrap_or(0)
+        .min(u16::from(u8::MAX)) as u8;
+    let mut hops = (first_ttl..=last_ttl)
+        .map(|ttl| Hop {
+            trace: trace.id,
+            ttl,
+            source,
+            destination,
+            queries: vec![TraceQuery::Timeout; usize::from(attempts)],
+        })
+        .collect::<Vec<_>>();
+
+    // Older versions of scamper number attempts from 0.
+    let base = match replies.iter().any(|reply| reply.probe_id == 0) {
+        true => 0,
+        false => 1,
+    };
+    for reply in replies {
+        let (addr, kind) = match (reply.addr, reply_kind(&reply, destination.is_ipv6())) {
+            (Some(addr), Some(kind)) => (addr, kind),
+            _ => continue,
+        };
+        let hop = match reply
+            .probe_ttl
+            .checked_sub(first_ttl)
+            .and_then(|idx| hops.get_mut(usize::from(idx)))
+        {
+            Some(hop) => hop,
+            None => continue,
+        };
+        let idx = usize::from(reply.probe_id.saturating_sub(base));
+        if idx >= hop.queries.len() {
+            hop.queries.resize(idx + 1, TraceQuery::Timeout);
+        }
+        // Only the first reply to a probe counts.
+        if let TraceQuery::Timeout = hop.queries[idx] {
+            hop.queries[idx] = TraceQuery::Success(Response {
+                rtt: Duration::from_micros(u64::from(reply.rtt)),
+                addr,
+                reply: kind,
+                mpls: reply.mpls,
+                reply_ttl: reply.reply_ttl,
+                quoted_ttl: reply.quoted_ttl,
+                modifications: vec![],
+            });
+        }
+    }
+
+    let stop_reason = match stop_reason {
+        Some(STOP_COMPLETED) => Some(StopReason::Completed),
+        Some(STOP_GAPLIMIT) => Some(StopReason::GapLimit),
+        Some(STOP_HOPLIMIT) => Some(StopReason::MaxHops),
+        _ => None,
+    };
+
+    let mut config = Config::default()
+        .with_protocol(protocol)
+        .with_mode(mode)
+        .with_first_ttl(first_ttl)
+        .with_max_hops(hop_limit.filter(|limit| *limit > 0).unwrap_or(u8::MAX))
+        .with_tries(attempts)
+        .with_timeout(u64::from(timeout.unwrap_or(DEFAULT_WAIT)) * 1000)
+        .with_tos(tos.unwrap_or(0));
+    if let Some(port) = port.filter(|_| protocol != Protocol::Icmp) {
+        config = config.with_port(port);
+    }
+    if let Some(size) = size {
+        config = config.with_size(size);
+    }
+    // The wait between probes is in hundredths of a second.
+    if let Some(wait) = wait {
+        config = config.with_wait(u64::from(wait) * 10);
+    }
+    if let Some(gap_limit) = gap_limit {
+        config = config.with_gap_limit(gap_limit);
+    }
+
+    // Traces are measured by the monitor of their list, or by the host of
+    // their cycle.
+    let list = list.and_then(|list| lists.get(&list));
+    let cycle = cycle.and_then(|cycle| cycles.get(&cycle));
+    let vantage_point = list
+        .and_then(|list| list.monitor.clone())
+        .or_else(|| cycle.and_then(|cycle| cycle.hostname.clone()))
+        .unwrap_or_else(|| source.to_string());
+    let measurement = match (list, cycle) {
+        (Some(list), Some(cycle)) => Some(format!("{} cycle {}", list.name, cycle.id)),
+        (Some(list), None) => Some(list.name.clone()),
+        (None, Some(cycle)) => Some(format!("cycle {}", cycle.id)),
+        (None, None) => None,
+    };
+
+    Ok(ImportedTrace {
+        trace,
+        origin: Origin {
+            platform: Platform::Scamper,
+            vantage_point,
+            measurement,
+            started: started.map(u64::from),
+        },
+        config,
+        hops,
+        stop_reason,
+    })
+}
+
+/// Parse a reply of a trace record.
+fn hop(record: &mut Cursor<'_>, table: &mut Vec<Option<IpAddr>>) -> Result<WartsHop> {
+    let mut params = record.params()?;
+    params.read(1, Cursor::u32)?;
+    let probe_ttl = params.read(2, Cursor::u8)?;
+    let reply_ttl = params.read(3, Cursor::u8)?;
+    let flags = params.read(4, Cursor::u8)?;
+    let probe_id = params.read(5, Cursor::u8)?;
+    let rtt = params.read(6, Cursor::u32)?;
+    let icmp = params.read(7, Cursor::u16)?;
+    params.read(8, Cursor::u16)?;
+    params.read(9, Cursor::u16)?;
+    params.read(10, Cursor::u16)?;
+    params.read(11, Cursor::u8)?;
+    let next_hop_mtu = params.read(12, Cursor::u16)?;
+    params.read(13, Cursor::u16)?;
+    let quoted_ttl = params.read(14, Cursor::u8)?;
+    let tcp_flags = params.read(15, Cursor::u8)?;
+    params.read(16, Cursor::u8)?;
+    let mpls = params.read(17, icmp_extensions)?;
+    let addr = params.address(18, table)?;
+
+    let [icmp_type, icmp_code] = icmp.unwrap_or(0).to_be_bytes();
+    Ok(WartsHop {
+        addr,
+        probe_ttl: probe_ttl.unwrap_or(0),
+        reply_ttl,
+        flags: flags.unwrap_or(0),
+        probe_id: probe_id.unwrap_or(0),
+        rtt: rtt.unwrap_or(0),
+        icmp_type,
+        icmp_code,
+        next_hop_mtu,
+        quoted_ttl,
+        tcp_flags: tcp_flags.unwrap_or(0),
+        mpls: mpls.unwrap_or_default(),
+    })
+}
+
+/// Parse the ICMP extensions of a reply and return the MPLS label stack
+/// they carry.
+fn icmp_extensions(cursor: &mut Cursor<'_>) -> Result<Vec<MplsLabel>> {
+    let len = usize::from(cursor.u16()?);
+    let mut extensions = Cursor::new(cursor.bytes(len)?);
+
+    let mut mpls = vec![];
+    while !extensions.is_empty() {
+        let len = usize::from(extensions.u16()?);
+        let (class, c_type) = (extensions.u8()?, extensions.u8()?);
+        let data = extensions.bytes(len)?;
+
+        // Class 1, C-Type 1 is the incoming MPLS label stack.
+        if class == 1 && c_type == 1 {
+            mpls.extend(data.chunks_exact(4).map(|entry| {
+                let entry = u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]);
+                MplsLabel {
+                    label: entry >> 12,
+                    tc: (entry >> 9 & 0x7) as u8,
+                    bottom: entry >> 8 & 0x1 == 1,
+                    ttl: entry as u8,
+                }
+            }));
+        }
+    }
+
+    Ok(mpls)
+}
+
+/// The kind of a reply from its ICMP type and code or its TCP flags.
+/// Returns `None` for replies tracer has no kind for.
+fn reply_kind(hop: &WartsHop, ipv6: bool) -> Option<ReplyKind> {
+    if hop.flags & HOP_TCP != 0 {
+        return match hop.tcp_flags {
+            flags if flags & 0x04 != 0 => Some(ReplyKind::Reset),
+            flags if flags & 0x12 == 0x12 => Some(ReplyKind::SynAck),
+            _ => None,
+        };
+    }
+    if hop.flags & HOP_UDP != 0 {
+        return None;
+    }
+
+    let mtu = hop.next_hop_mtu.unwrap_or(0);
+    match (ipv6, hop.icmp_type, hop.icmp_code) {
+        (false, 0, _) | (true, 129, _) => Some(ReplyKind::EchoReply),
+        (false, 11, _) | (true, 3, _) => Some(ReplyKind::TimeExceeded),
+        (false, 3, 4) | (true, 2, _) => Some(ReplyKind::PacketTooBig { mtu }),
+        (false, 3, _) | (true, 1, _) => Some(ReplyKind::Unreachable),
+        _ => None,
+    }
+}
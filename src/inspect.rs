@@ -0,0 +1,156 @@
+use pnet::packet::{ip::IpNextHeaderProtocols, util};
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    ops::Range,
+};
+
+use crate::{reply::Reply, Field, Modification};
+
+/// Compare the headers an error message quotes with the probe it quotes as
+/// it was sent, and return the fields that were changed on the way. The TTL
+/// is expected to change and isn't compared. Returns nothing for replies
+/// that quote no probe.
+pub(crate) fn modifications(probe: &[u8], reply: &Reply) -> Vec<Modification> {
+    let (header, quoted) = match reply {
+        Reply::Error { header, quoted, .. } => (header.as_slice(), quoted.as_slice()),
+        _ => return vec![],
+    };
+
+    let mut diff = Diff::default();
+    let (segment, protocol) = match probe.first().map(|version| version >> 4) {
+        Some(4) if probe.len() >= 20 && header.len() >= 20 => {
+            diff.ipv4(probe, header);
+            let offset = (probe[0] & 0x0f) as usize * 4;
+            (probe.get(offset..).unwrap_or_default(), probe[9])
+        }
+        Some(6) if probe.len() >= 40 && header.len() >= 40 => {
+            diff.ipv6(probe, header);
+            (&probe[40..], probe[6])
+        }
+        _ => return vec![],
+    };
+
+    diff.transport(protocol, segment, quoted);
+
+    diff.modifications
+}
+
+#[derive(Default)]
+struct Diff {
+    modifications: Vec<Modification>,
+}
+
+impl Diff {
+    /// Compare the IPv4 header of a probe with the quoted one.
+    fn ipv4(&mut self, sent: &[u8], quoted: &[u8]) {
+        self.number(Field::Tos, sent, quoted, 1..2);
+        self.number(Field::IpLength, sent, quoted, 2..4);
+        self.number(Field::IpIdent, sent, quoted, 4..6);
+        self.number(Field::IpFlags, sent, quoted, 6..8);
+
+        // Every router updates the checksum for the TTL it decremented, so
+        // the quoted checksum is compared to the one of the probe with the
+        // quoted TTL.
+        let mut expected = sent[..20].to_vec();
+        expected[8] = quoted[8];
+        let checksum = util::checksum(&expected, 5).to_be_bytes();
+        self.number(Field::IpChecksum, &checksum, &quoted[10..12], 0..2);
+
+        self.ipv4_addr(Field::SourceAddress, sent, quoted, 12..16);
+        self.ipv4_addr(Field::DestinationAddress, sent, quoted, 16..20);
+    }
+
+    /// Compare the IPv6 header of a probe with the quoted one. The flow label
+    /// is chosen by the kernel and isn't compared.
+    fn ipv6(&mut self, sent: &[u8], quoted: &[u8]) {
+        let traffic_class = |header: &[u8]| [(header[0] & 0x0f) << 4 | header[1] >> 4];
+        self.number(
+            Field::Tos,
+            &traffic_class(sent),
+            &traffic_class(quoted),
+            0..1,
+        );
+        self.number(Field::IpLength, sent, quoted, 4..6);
+        self.ipv6_addr(Field::SourceAddress, sent, quoted, 8..24);
+        self.ipv6_addr(Field::DestinationAddress, sent, quoted, 24..40);
+    }
+
+    /// Compare the transport header of a probe with the quoted one, as far
+    /// as it was quoted.
+    fn transport(&mut self, protocol: u8, sent: &[u8], quoted: &[u8]) {
+        let fields: &[(Field, Range<usize>)] = match protocol {
+            p if p == IpNextHeaderProtocols::Udp.0 => &[
+                (Field::SourcePort, 0..2),
+                (Field::DestinationPort, 2..4),
+                (Field::UdpLength, 4..6),
+                (Field::Checksum, 6..8),
+            ],
+            p if p == IpNextHeaderProtocols::Tcp.0 => &[
+                (Field::SourcePort, 0..2),
+                (Field::DestinationPort, 2..4),
+                (Field::TcpSequence, 4..8),
+                (Field::Checksum, 16..18),
+            ],
+            p if p == IpNextHeaderProtocols::Icmp.0 || p == IpNextHeaderProtocols::Icmpv6.0 => &[
+                (Field::Checksum, 2..4),
+                (Field::IcmpIdent, 4..6),
+                (Field::IcmpSequence, 6..8),
+            ],
+            _ => &[],
+        };
+
+        for (field, range) in fields {
+            if quoted.len() >= range.end && sent.len() >= range.end {
+                self.number(*field, sent, quoted, range.clone());
+            }
+        }
+
+        // Quotes may be padded, but shouldn't be shorter than the probe.
+        if quoted.len() < sent.len() {
+            self.push(Field::QuoteLength, sent.len(), quoted.len());
+        }
+    }
+
+    fn number(&mut self, field: Field, sent: &[u8], quoted: &[u8], range: Range<usize>) {
+        let value = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .fold(0u64, |value, byte| value << 8 | u64::from(*byte))
+        };
+        let (sent, quoted) = (value(&sent[range.clone()]), value(&quoted[range]));
+
+        if sent != quoted {
+            self.push(field, sent, quoted);
+        }
+    }
+
+    fn ipv4_addr(&mut self, field: Field, sent: &[u8], quoted: &[u8], range: Range<usize>) {
+        let addr = |bytes: &[u8]| Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]);
+        let (sent, quoted) = (addr(&sent[range.clone()]), addr(&quoted[range]));
+
+        if sent != quoted {
+            self.push(field, sent, quoted);
+        }
+    }
+
+    fn ipv6_addr(&mut self, field: Field, sent: &[u8], quoted: &[u8], range: Range<usize>) {
+        let addr = |bytes: &[u8]| {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(bytes);
+            Ipv6Addr::from(octets)
+        };
+        let (sent, quoted) = (addr(&sent[range.clone()]), addr(&quoted[range]));
+
+        if sent != quoted {
+            self.push(field, sent, quoted);
+        }
+    }
+
+    fn push<T: ToString>(&mut self, field: Field, sent: T, quoted: T) {
+        self.modifications.push(Modification {
+            field,
+            sent: sent.to_string(),
+            quoted: quoted.to_string(),
+        });
+    }
+}
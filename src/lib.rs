@@ -1,38 +1,406 @@
-use anyhow::Result;
 use pnet::datalink::NetworkInterface;
 use serde::{Deserialize, Serialize};
-use std::{
-    net::{IpAddr, Ipv4Addr},
-    time::Duration,
-};
+use std::{fmt, net::IpAddr, str::FromStr, time::Duration};
 use uuid::Uuid;
 
+mod capture;
 pub mod data;
+mod datagram;
+mod error;
 mod geoip;
+mod import;
+mod inspect;
+mod mda;
+mod pacer;
 mod packet;
+mod prober;
+mod receiver;
+mod replay;
+mod reply;
+mod scan;
+mod simulate;
 mod stats;
 pub mod tasks;
 mod traceroute;
 
 pub use crate::{
+    capture::Capture,
     data::DbHandle,
-    traceroute::{Config, TraceRoute},
+    datagram::can_open_raw_sockets,
+    error::{Error, Result},
+    import::{import, Format, ImportedTrace},
+    pacer::Pacer,
+    prober::{Probe, Prober},
+    receiver::{Answer, Receiver},
+    replay::{replay, ReplayedTrace},
+    scan::{ScanConfig, Scanner},
+    simulate::Simulation,
+    traceroute::{address_interface, find_interface, Config, TraceRoute},
 };
 
+/// The protocol used to send the probes of a trace.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    /// UDP datagrams to a high destination port.
+    #[default]
+    Udp,
+    /// ICMP Echo Requests.
+    Icmp,
+    /// TCP SYN segments to a chosen port, like tcptraceroute.
+    Tcp,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protocol::Udp => write!(f, "udp"),
+            Protocol::Icmp => write!(f, "icmp"),
+            Protocol::Tcp => write!(f, "tcp"),
+        }
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "udp" => Ok(Protocol::Udp),
+            "icmp" => Ok(Protocol::Icmp),
+            "tcp" => Ok(Protocol::Tcp),
+            _ => Err(Error::Parse(format!("{:?} is an invalid protocol", s))),
+        }
+    }
+}
+
+/// The way the probes of a trace are varied.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Every probe gets a new flow identifier, so per-flow load balancers
+    /// can route the queries of a single hop differently.
+    #[default]
+    Classic,
+    /// Paris traceroute: the flow identifier is kept fixed for the whole
+    /// trace and probes are told apart by fields load balancers don't hash.
+    Paris,
+    /// Multipath Detection Algorithm: the flow identifier is varied at each
+    /// TTL until all next hops have been found with a chosen confidence.
+    Mda,
+    /// Yarrp-style stateless scan: probes of all TTLs are sent in random
+    /// order and carry their state in their headers.
+    Yarrp,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Classic => write!(f, "classic"),
+            Mode::Paris => write!(f, "paris"),
+            Mode::Mda => write!(f, "mda"),
+            Mode::Yarrp => write!(f, "yarrp"),
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "classic" => Ok(Mode::Classic),
+            "paris" => Ok(Mode::Paris),
+            "mda" => Ok(Mode::Mda),
+            "yarrp" => Ok(Mode::Yarrp),
+            _ => Err(Error::Parse(format!("{:?} is an invalid mode", s))),
+        }
+    }
+}
+
+/// Why a trace stopped probing further hops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StopReason {
+    /// The destination, or a reply only a destination sends, was reached.
+    Completed,
+    /// Too many consecutive hops didn't answer any probe.
+    GapLimit,
+    /// The maximum number of hops was probed.
+    MaxHops,
+    /// The probe budget of the day was used up.
+    Budget,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Completed => write!(f, "completed"),
+            StopReason::GapLimit => write!(f, "gap-limit"),
+            StopReason::MaxHops => write!(f, "max-hops"),
+            StopReason::Budget => write!(f, "budget"),
+        }
+    }
+}
+
+impl FromStr for StopReason {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "completed" => Ok(StopReason::Completed),
+            "gap-limit" => Ok(StopReason::GapLimit),
+            "max-hops" => Ok(StopReason::MaxHops),
+            "budget" => Ok(StopReason::Budget),
+            _ => Err(Error::Parse(format!("{:?} is an invalid stop reason", s))),
+        }
+    }
+}
+
+/// The measurement platform an imported trace comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Platform {
+    /// scamper, whose traces are read from warts files.
+    Scamper,
+    /// RIPE Atlas, whose traces are read from the JSON of its results.
+    RipeAtlas,
+    /// tracer itself, whose traces are read from the hops it printed.
+    Tracer,
+    /// The traceroute of Linux, whose traces are read from its output.
+    Traceroute,
+    /// mtr, whose traces are read from its reports.
+    Mtr,
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Platform::Scamper => write!(f, "scamper"),
+            Platform::RipeAtlas => write!(f, "ripe-atlas"),
+            Platform::Tracer => write!(f, "tracer"),
+            Platform::Traceroute => write!(f, "traceroute"),
+            Platform::Mtr => write!(f, "mtr"),
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "scamper" => Ok(Platform::Scamper),
+            "ripe-atlas" => Ok(Platform::RipeAtlas),
+            "tracer" => Ok(Platform::Tracer),
+            "traceroute" => Ok(Platform::Traceroute),
+            "mtr" => Ok(Platform::Mtr),
+            _ => Err(Error::Parse(format!("{:?} is an invalid platform", s))),
+        }
+    }
+}
+
+/// The kind of packet a remote node answered a probe with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplyKind {
+    /// ICMP Time Exceeded, sent by a router along the path.
+    TimeExceeded,
+    /// ICMP Destination Unreachable.
+    Unreachable,
+    /// ICMP Fragmentation Needed or ICMPv6 Packet Too Big, sent by a router
+    /// that can't forward a probe with the DF flag set. `mtu` is the MTU of
+    /// its next hop, or 0 if the router didn't report it.
+    PacketTooBig { mtu: u16 },
+    /// ICMP Echo Reply, sent by the destination of an ICMP trace.
+    EchoReply,
+    /// TCP SYN-ACK, sent by the destination of a TCP trace to an open port.
+    SynAck,
+    /// TCP RST, sent by the destination of a TCP trace to a closed port.
+    Reset,
+}
+
+impl ReplyKind {
+    /// Test whether this kind of reply can only be sent by the destination.
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            ReplyKind::EchoReply | ReplyKind::SynAck | ReplyKind::Reset
+        )
+    }
+}
+
+/// An entry of the MPLS label stack a router quoted in the ICMP extensions
+/// of its reply (RFC 4950).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MplsLabel {
+    /// The 20-bit label.
+    pub label: u32,
+    /// Traffic class, formerly the experimental bits.
+    pub tc: u8,
+    /// Set on the bottom entry of the stack.
+    pub bottom: bool,
+    pub ttl: u8,
+}
+
+impl fmt::Display for MplsLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "L={},TC={},S={},TTL={}",
+            self.label,
+            self.tc,
+            u8::from(self.bottom),
+            self.ttl
+        )
+    }
+}
+
+/// A header field of a probe that a middlebox may change on the way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    /// The TOS byte of IPv4 or the traffic class of IPv6, DSCP and ECN.
+    Tos,
+    /// The total length of IPv4 or the payload length of IPv6.
+    IpLength,
+    IpIdent,
+    /// The flags and fragment offset of IPv4.
+    IpFlags,
+    IpChecksum,
+    SourceAddress,
+    DestinationAddress,
+    SourcePort,
+    DestinationPort,
+    UdpLength,
+    /// The UDP, TCP or ICMP checksum.
+    Checksum,
+    TcpSequence,
+    IcmpIdent,
+    IcmpSequence,
+    /// The length of the quoted transport header and payload, which is
+    /// shorter than the probe if the quote was truncated.
+    QuoteLength,
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Field::Tos => "tos",
+            Field::IpLength => "ip-length",
+            Field::IpIdent => "ip-id",
+            Field::IpFlags => "ip-flags",
+            Field::IpChecksum => "ip-checksum",
+            Field::SourceAddress => "source-address",
+            Field::DestinationAddress => "destination-address",
+            Field::SourcePort => "source-port",
+            Field::DestinationPort => "destination-port",
+            Field::UdpLength => "udp-length",
+            Field::Checksum => "checksum",
+            Field::TcpSequence => "tcp-sequence",
+            Field::IcmpIdent => "icmp-id",
+            Field::IcmpSequence => "icmp-seq",
+            Field::QuoteLength => "quote-length",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// A header field whose value in the quote of a reply differs from the one
+/// the probe was sent with, tracebox-style.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Modification {
+    pub field: Field,
+    /// The value the probe was sent with.
+    pub sent: String,
+    /// The value the reply quoted.
+    pub quoted: String,
+}
+
 /// Result of a single query execution. A query can either succeed and return
 /// the round-trip time and address of a hop, or yield a timeout or fail.
 #[derive(Debug, Clone)]
 pub enum TraceQuery {
-    Success {
-        /// Round-Trip Time
-        rtt: Duration,
-        /// IP address of a remote node
-        addr: IpAddr,
-    },
+    Success(Response),
+    /// A reply that came in after the probe timed out.
+    Late(Response),
     Timeout,
     Failure(String),
 }
 
+/// The reply a hop sent to a query.
+#[derive(Debug, Clone)]
+pub struct Response {
+    /// Round-Trip Time
+    pub rtt: Duration,
+    /// IP address of a remote node
+    pub addr: IpAddr,
+    /// The kind of reply the remote node sent.
+    pub reply: ReplyKind,
+    /// The MPLS label stack quoted in the reply, top entry first.
+    pub mpls: Vec<MplsLabel>,
+    /// The TTL of the reply when it arrived, if it is known.
+    pub reply_ttl: Option<u8>,
+    /// The TTL of the probe when it arrived, as quoted in an error message.
+    pub quoted_ttl: Option<u8>,
+    /// The header fields of the probe that were changed on the way.
+    pub modifications: Vec<Modification>,
+}
+
+/// The initial TTLs hosts and routers commonly send packets with.
+const INITIAL_TTLS: [u8; 4] = [32, 64, 128, 255];
+
+impl TraceQuery {
+    /// The reply to this query, whether it came in in time or late.
+    pub fn response(&self) -> Option<&Response> {
+        match self {
+            TraceQuery::Success(response) | TraceQuery::Late(response) => Some(response),
+            TraceQuery::Timeout | TraceQuery::Failure(_) => None,
+        }
+    }
+
+    /// Estimate the number of hops the reply travelled back, assuming it was
+    /// sent with the smallest common initial TTL that isn't below its TTL on
+    /// arrival. The replying node is counted, like TTLs count hops forward,
+    /// so the reply of a symmetric path at TTL `n` travelled `n` hops back.
+    pub fn reverse_hops(&self) -> Option<u8> {
+        let ttl = self.response()?.reply_ttl?;
+        let initial = INITIAL_TTLS
+            .iter()
+            .copied()
+            .find(|initial| *initial >= ttl)?;
+
+        Some(initial - ttl + 1)
+    }
+
+    /// The number of hops before the replying router that forwarded the
+    /// probe without decrementing its IP TTL, like the routers of an MPLS
+    /// tunnel that expires probes on the label TTL. A Time Exceeded reply
+    /// quotes a TTL of 1 unless such hops were hidden from the trace.
+    pub fn hidden_hops(&self) -> Option<u8> {
+        match self.response()? {
+            Response {
+                reply: ReplyKind::TimeExceeded,
+                quoted_ttl: Some(ttl),
+                ..
+            } => Some(ttl.saturating_sub(1)),
+            _ => None,
+        }
+    }
+}
+
+/// A reply that came in late for a query of a hop that was already yielded.
+#[derive(Debug, Clone)]
+pub struct LateReply {
+    /// The unique trace id of the hop.
+    pub trace: Uuid,
+    /// The TTL of the hop.
+    pub ttl: u8,
+    /// The index of the query within the hop, starting at 1.
+    pub query: u8,
+    /// The late query result.
+    pub result: TraceQuery,
+}
+
 /// Single traceroute hop containing TTL, the source and destination IP of a
 /// trace, and a vector of traceroute query results
 #[derive(Debug, Clone)]
@@ -42,19 +410,28 @@ pub struct Hop {
     /// Current Time-To-Live.
     pub ttl: u8,
     /// The source of the trace.
-    pub source: Ipv4Addr,
+    pub source: IpAddr,
     /// The destination of the trace.
-    pub destination: Ipv4Addr,
+    pub destination: IpAddr,
     /// Traceroute query results.
     pub queries: Vec<TraceQuery>,
 }
 
 impl Hop {
-    fn is_ip(&self, ip: Ipv4Addr) -> bool {
-        self.queries.iter().any(|query| match query {
-            TraceQuery::Success { addr, .. } => addr == &IpAddr::V4(ip),
-            _ => false,
-        })
+    /// Test whether any query of this hop was answered by the destination,
+    /// either from its address or with a reply only a destination sends.
+    fn is_destination(&self, ip: IpAddr) -> bool {
+        self.queries
+            .iter()
+            .filter_map(TraceQuery::response)
+            .any(|response| response.addr == ip || response.reply.is_final())
+    }
+
+    /// Test whether no query of this hop was answered.
+    fn is_unresponsive(&self) -> bool {
+        self.queries
+            .iter()
+            .all(|query| matches!(query, TraceQuery::Timeout))
     }
 }
 
@@ -62,50 +439,178 @@ impl Hop {
 pub struct Trace {
     pub id: Uuid,
     pub route: Route,
+    /// The protocol used to probe this trace.
+    pub protocol: Protocol,
+    /// The way the probes of this trace were varied.
+    pub mode: Mode,
 }
 
 impl Trace {
     /// Creates new instance of a trace.
-    pub fn new(source: Ipv4Addr, destination: Ipv4Addr) -> Self {
+    pub fn new(source: IpAddr, destination: IpAddr, protocol: Protocol, mode: Mode) -> Self {
         let id = Uuid::new_v4();
         let route = Route {
             source,
             destination,
         };
 
-        Trace { id, route }
+        Trace {
+            id,
+            route,
+            protocol,
+            mode,
+        }
     }
 }
 
+/// Where and by whom an imported trace was measured.
+#[derive(Debug, Clone)]
+pub struct Origin {
+    pub platform: Platform,
+    /// The host that sent the probes, like the ID of a RIPE Atlas probe or
+    /// the name of a scamper monitor.
+    pub vantage_point: String,
+    /// The measurement the trace is part of, like the ID of a RIPE Atlas
+    /// measurement or a scamper list and cycle.
+    pub measurement: Option<String>,
+    /// When the trace started, in seconds since the epoch.
+    pub started: Option<u64>,
+}
+
+/// An interface that answered probes of a trace at a TTL.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Vertex {
+    pub ttl: u8,
+    pub addr: IpAddr,
+}
+
+/// A link between an interface answering at `ttl` and one answering at
+/// `ttl + 1` to probes of the same flow.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Edge {
+    pub ttl: u8,
+    pub from: IpAddr,
+    pub to: IpAddr,
+}
+
+/// The graph of all paths to the destination of a trace, as discovered by the
+/// Multipath Detection Algorithm.
+#[derive(Debug, Clone)]
+pub struct Multipath {
+    /// The unique trace id of this graph.
+    pub trace: Uuid,
+    pub vertices: Vec<Vertex>,
+    pub edges: Vec<Edge>,
+    pub stop_reason: StopReason,
+}
+
+/// The MTU of the path up to the hop at `ttl`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HopMtu {
+    pub ttl: u8,
+    pub mtu: u16,
+}
+
+/// The MTUs found by path MTU discovery along a trace.
+#[derive(Debug, Clone)]
+pub struct PathMtu {
+    /// The unique trace id of the discovery.
+    pub trace: Uuid,
+    pub hops: Vec<HopMtu>,
+    /// The MTU of the whole path, or of the path up to the last hop if the
+    /// destination wasn't reached.
+    pub mtu: u16,
+    pub stop_reason: StopReason,
+}
+
+/// A daily probe budget and the probes that were sent today.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+pub struct ProbeBudget {
+    /// Probes that may be sent per day, 0 if there is no limit.
+    pub budget: u64,
+    pub used: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Route {
-    pub source: Ipv4Addr,
-    pub destination: Ipv4Addr,
+    pub source: IpAddr,
+    pub destination: IpAddr,
 }
 
-/// Fetch the IP address for an network interface. If no interface is provided
-/// (`None`) return the IP address of the default network interface. Otherwise
-/// return the IP address of the provided network interface.
-pub fn interface_ip(interface: Option<NetworkInterface>) -> Result<Ipv4Addr> {
+/// Fetch the IP address for an network interface that can reach
+/// `destination`. If no interface is provided (`None`) return the source
+/// address the routing table of the kernel chooses for `destination`.
+/// Otherwise return the IP address of the provided network interface.
+pub fn interface_ip(interface: Option<NetworkInterface>, destination: &IpAddr) -> Result<IpAddr> {
     match interface {
-        Some(interface) => traceroute::interface_ip(interface),
-        None => traceroute::interface_ip(traceroute::default_interface()),
+        Some(interface) => traceroute::interface_ip(interface, destination),
+        None => match traceroute::route_source(destination) {
+            Some(source) => Ok(source),
+            None => {
+                traceroute::interface_ip(traceroute::default_interface(destination)?, destination)
+            }
+        },
     }
 }
 
+/// Fetch the MTU of a network interface. If no interface is provided
+/// (`None`) return the MTU of the interface the routing table of the kernel
+/// chooses for `destination`. Falls back to the Ethernet MTU of 1500 bytes if
+/// the MTU can't be read.
+pub fn interface_mtu(interface: Option<NetworkInterface>, destination: &IpAddr) -> u16 {
+    interface
+        .map_or_else(|| traceroute::default_interface(destination), Ok)
+        .ok()
+        .and_then(|interface| traceroute::interface_mtu(&interface))
+        .unwrap_or(1500)
+}
+
+/// List the network interfaces probes can be sent from: all interfaces that
+/// are up and have an address, except loopback interfaces.
+pub fn interfaces() -> Vec<NetworkInterface> {
+    traceroute::available_interfaces()
+}
+
+/// A header field changed on the way of a probe to a hop of a trace.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExportModification {
+    pub trace: Uuid,
+    pub ttl: u8,
+    pub query: u8,
+    pub addr: IpAddr,
+    pub field: String,
+    pub sent: String,
+    pub quoted: String,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ExportHop {
-    pub source: Ipv4Addr,
-    pub destination: Ipv4Addr,
+    pub source: IpAddr,
+    pub destination: IpAddr,
     pub trace: Uuid,
+    pub protocol: String,
+    pub mode: String,
+    pub stop_reason: Option<String>,
+    pub platform: Option<String>,
+    pub vantage_point: Option<String>,
     pub ttl: u8,
     pub query: u8,
     pub query_result: String,
-    pub addr: Option<Ipv4Addr>,
-    // FIXME: the rtt is a i128
-    pub rtt: Option<String>,
-    pub hop_mean_ms: Option<String>,
-    pub hop_median_ms: Option<String>,
+    pub addr: Option<IpAddr>,
+    /// Round-trip time in milliseconds, with microsecond precision.
+    pub rtt: Option<f64>,
+    pub hop_mean_ms: Option<f64>,
+    pub hop_median_ms: Option<f64>,
+    pub reply_ttl: Option<u8>,
+    pub quoted_ttl: Option<u8>,
+    pub reverse_hops: Option<u8>,
+    /// How many hops longer the reverse path is than the forward one.
+    pub asymmetry: Option<i16>,
+    pub hidden_hops: Option<u8>,
+    /// The MPLS label stack quoted in the reply, top entry first.
+    pub mpls: Option<String>,
+    pub hop_mtu: Option<u16>,
+    pub path_mtu: Option<u16>,
     pub city: Option<String>,
     pub country: Option<String>,
     pub country_code: Option<String>,
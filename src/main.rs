@@ -7,14 +7,38 @@ use std::{
     path::PathBuf,
     process::exit,
 };
+use tracer::{Format, Mode, Protocol};
 
 mod cmd;
 
 #[derive(Debug)]
 pub struct AppConfig {
     pub destination: Option<IpAddr>,
-    pub count: i32,
-    pub fails: i32,
+    pub targets: Option<PathBuf>,
+    pub capture: Option<PathBuf>,
+    pub import: Option<PathBuf>,
+    pub format: Option<Format>,
+    pub count: u32,
+    pub fails: u8,
+    pub protocol: Protocol,
+    pub port: Option<u16>,
+    pub first_ttl: Option<u8>,
+    pub max_hops: Option<u8>,
+    pub tries: Option<u8>,
+    pub timeout: Option<u64>,
+    pub wait: Option<u64>,
+    pub size: Option<u16>,
+    pub tos: Option<u8>,
+    pub mode: Mode,
+    pub confidence: f64,
+    pub window: Option<u8>,
+    pub rate: u32,
+    pub prefix_rate: u32,
+    pub budget: Option<u64>,
+    pub interface: Option<String>,
+    pub source: Option<IpAddr>,
+    pub simulate: Option<PathBuf>,
+    pub pcap: Option<PathBuf>,
     pub db: PathBuf,
 }
 
@@ -28,8 +52,31 @@ impl Default for AppConfig {
     fn default() -> Self {
         Self {
             destination: None,
+            targets: None,
+            capture: None,
+            import: None,
+            format: None,
             count: 1,
             fails: 10,
+            protocol: Protocol::Udp,
+            port: None,
+            first_ttl: None,
+            max_hops: None,
+            tries: None,
+            timeout: None,
+            wait: None,
+            size: None,
+            tos: None,
+            mode: Mode::Classic,
+            confidence: 0.95,
+            window: None,
+            rate: 1000,
+            prefix_rate: 0,
+            budget: None,
+            interface: None,
+            source: None,
+            simulate: None,
+            pcap: None,
             db: PathBuf::from("tracer.db"),
         }
     }
@@ -39,7 +86,13 @@ impl Default for AppConfig {
 enum AppCommand {
     Init,
     Trace,
+    Pmtu,
+    Scan,
+    Replay,
+    Import,
     Export,
+    Inspect,
+    Interfaces,
 }
 
 #[derive(Debug)]
@@ -64,17 +117,77 @@ Trace routes.
 
 USAGE:
     tracer SUBCOMMAND [OPTIONS] DESTINATION
+    tracer scan [OPTIONS] FILE
+    tracer replay [OPTIONS] FILE
+    tracer import [OPTIONS] FILE
 
 SUBCOMMANDS:
     init
     trace
+    pmtu
+    scan
+    replay
+    import
     export
+    inspect
+    interfaces
 
 OPTIONS:
     -c, --count NUMBER            Number of traces to the destination. Defaults
                                   to 1.
-    -n, --num-fails NUMBER        Number of failure for any hop along the way
-                                  before giving up. Defaults to 1.
+    -n, --num-fails NUMBER        Number of consecutive hops without any reply
+                                  before giving up, 0 never gives up. Defaults
+                                  to 10.
+    -P, --protocol PROTOCOL       Protocol used to probe, either udp, icmp or
+                                  tcp. Defaults to udp.
+    -p, --port NUMBER             Destination port of UDP and TCP probes.
+                                  Defaults to 33434 for UDP and 80 for TCP.
+    -f, --first-ttl NUMBER        TTL of the first hop that is probed. Defaults
+                                  to 1.
+    -m, --max-hops NUMBER         Maximum number of hops. Defaults to 30.
+    -q, --tries NUMBER            Number of probes per hop. Defaults to 3.
+    -W, --timeout MILLISECONDS    Time to wait for the reply to a probe.
+                                  Defaults to 5000.
+    -z, --wait MILLISECONDS       Minimum time between two probes. Defaults to
+                                  0.
+    -S, --size BYTES              Size of probes including the IP header.
+                                  Defaults to the smallest size of each
+                                  protocol.
+    -t, --tos NUMBER              TOS byte or IPv6 traffic class of probes, the
+                                  DSCP times 4. Defaults to 0.
+    -M, --mode MODE               How probes are varied, either classic, paris,
+                                  mda or yarrp. Paris keeps the flow of all
+                                  probes fixed, MDA enumerates all paths, yarrp
+                                  probes all TTLs in random order like scan.
+                                  Defaults to classic.
+    -C, --confidence NUMBER       Confidence with which MDA finds all next hops
+                                  of a hop, between 0 and 1 exclusive.
+                                  Defaults to 0.95.
+    -w, --window NUMBER           Number of TTLs probed at once, 0 probes all
+                                  TTLs at once. Defaults to probing one TTL
+                                  after the other.
+    -r, --rate NUMBER             Probes sent per second, 0 sends as fast as
+                                  possible. Defaults to 1000.
+    --prefix-rate NUMBER          Probes sent per second to each /24 IPv4 or
+                                  /48 IPv6 prefix, 0 doesn't limit them.
+                                  Defaults to 0.
+    --budget NUMBER               Probes sent per day, stored in the database
+                                  for later runs, 0 doesn't limit them.
+                                  Defaults to the stored budget.
+    -i, --interface NAME          Network interface probes are sent from.
+                                  Defaults to the interface of the route to
+                                  the destination.
+    -s, --source ADDRESS          Source address of probes. Defaults to the
+                                  address of the interface of the route to the
+                                  destination.
+    --simulate FILE               Answer probes from the simulated network
+                                  described in a JSON file instead of sending
+                                  them.
+    --pcap FILE                   Write every probe and reply of traces to a
+                                  pcapng file.
+    --format FORMAT               Format of the file to import, either warts,
+                                  atlas or text. Defaults to the format
+                                  detected from the file.
     -D, --db PATH                 Path to SQLITE database. Defaults to ./tracer.db.
     -h, --help                    Prints help information.
 "#;
@@ -87,17 +200,17 @@ fn parse_ip(s: &str) -> Result<IpAddr, AddrParseError> {
     s.parse()
 }
 
-fn main() -> Result<()> {
+fn main() {
     // Parse the command line arguments and exit early if we have an issue
     // during parsing or we detected the help flag.
     let args = cli_args()
         .context("Failed to parse application arguments.")
         .unwrap_or_else(|e| {
             eprintln!("{:?}", e);
-            exit(1);
+            exit(EXIT_USAGE);
         });
 
-    if let true = args.help {
+    if args.help {
         println!("{}", HELP);
         exit(0);
     }
@@ -106,13 +219,44 @@ fn main() -> Result<()> {
     dotenv().ok();
 
     // Run the
-    match args.command {
-        AppCommand::Init => cmd::init(args.cfg)?,
-        AppCommand::Trace => cmd::trace(args.cfg)?,
-        AppCommand::Export => cmd::export(args.cfg)?,
+    let result = match args.command {
+        AppCommand::Init => cmd::init(args.cfg),
+        AppCommand::Trace => cmd::trace(args.cfg),
+        AppCommand::Pmtu => cmd::pmtu(args.cfg),
+        AppCommand::Scan => cmd::scan(args.cfg),
+        AppCommand::Replay => cmd::replay(args.cfg),
+        AppCommand::Import => cmd::import(args.cfg),
+        AppCommand::Export => cmd::export(args.cfg),
+        AppCommand::Inspect => cmd::inspect(args.cfg),
+        AppCommand::Interfaces => cmd::interfaces(args.cfg),
     };
 
-    Ok(())
+    if let Err(e) = result {
+        eprintln!("Error: {:?}", e);
+        exit(exit_code(&e));
+    }
+}
+
+/// Exit code of invalid command line arguments.
+const EXIT_USAGE: i32 = 2;
+
+/// The exit code of a failed command, by the kind of the library error that
+/// caused it. Other errors exit with 1.
+fn exit_code(error: &Error) -> i32 {
+    let cause = error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<tracer::Error>());
+
+    match cause {
+        None => 1,
+        Some(tracer::Error::Permission(_)) => 3,
+        Some(tracer::Error::Interface(_)) => 4,
+        Some(tracer::Error::Socket(_)) => 5,
+        Some(tracer::Error::Storage(_)) => 6,
+        Some(tracer::Error::Geo(_)) => 7,
+        Some(tracer::Error::Parse(_)) => 8,
+        Some(tracer::Error::Unsupported(_)) => 9,
+    }
 }
 
 fn cli_args() -> Result<AppArgs> {
@@ -147,13 +291,26 @@ fn cli_args() -> Result<AppArgs> {
     let command = match args.subcommand()?.as_deref() {
         Some("init") => Ok(AppCommand::Init),
         Some("trace") => Ok(AppCommand::Trace),
+        Some("pmtu") => Ok(AppCommand::Pmtu),
+        Some("scan") => Ok(AppCommand::Scan),
+        Some("replay") => Ok(AppCommand::Replay),
+        Some("import") => Ok(AppCommand::Import),
         Some("export") => Ok(AppCommand::Export),
+        Some("inspect") => Ok(AppCommand::Inspect),
+        Some("interfaces") => Ok(AppCommand::Interfaces),
         Some(v) => Err(Error::msg(format!("{:?} is an invalid command", v))),
         None => Err(Error::msg("missing subcommand")),
     }?;
 
+    // Scans read their targets from a file instead, replays a capture and
+    // imports the results of other tools.
+    match command {
+        AppCommand::Scan => app_args.cfg.targets = args.opt_free_from_os_str(parse_path)?,
+        AppCommand::Replay => app_args.cfg.capture = args.opt_free_from_os_str(parse_path)?,
+        AppCommand::Import => app_args.cfg.import = args.opt_free_from_os_str(parse_path)?,
+        _ => app_args.cfg.destination = args.opt_free_from_fn(parse_ip)?,
+    };
     app_args.command = command;
-    app_args.cfg.destination = args.opt_free_from_fn(parse_ip)?;
 
     // And now we parse optional arguments.
     if let Some(count) = args.opt_value_from_str(["-c", "--count"])? {
@@ -164,6 +321,86 @@ fn cli_args() -> Result<AppArgs> {
         app_args.cfg.fails = fails;
     }
 
+    if let Some(protocol) = args.opt_value_from_str(["-P", "--protocol"])? {
+        app_args.cfg.protocol = protocol;
+    }
+
+    if let Some(port) = args.opt_value_from_str(["-p", "--port"])? {
+        app_args.cfg.port = Some(port);
+    }
+
+    if let Some(first_ttl) = args.opt_value_from_str(["-f", "--first-ttl"])? {
+        app_args.cfg.first_ttl = Some(first_ttl);
+    }
+
+    if let Some(max_hops) = args.opt_value_from_str(["-m", "--max-hops"])? {
+        app_args.cfg.max_hops = Some(max_hops);
+    }
+
+    if let Some(tries) = args.opt_value_from_str(["-q", "--tries"])? {
+        app_args.cfg.tries = Some(tries);
+    }
+
+    if let Some(timeout) = args.opt_value_from_str(["-W", "--timeout"])? {
+        app_args.cfg.timeout = Some(timeout);
+    }
+
+    if let Some(wait) = args.opt_value_from_str(["-z", "--wait"])? {
+        app_args.cfg.wait = Some(wait);
+    }
+
+    if let Some(size) = args.opt_value_from_str(["-S", "--size"])? {
+        app_args.cfg.size = Some(size);
+    }
+
+    if let Some(tos) = args.opt_value_from_str(["-t", "--tos"])? {
+        app_args.cfg.tos = Some(tos);
+    }
+
+    if let Some(mode) = args.opt_value_from_str(["-M", "--mode"])? {
+        app_args.cfg.mode = mode;
+    }
+
+    if let Some(confidence) = args.opt_value_from_str(["-C", "--confidence"])? {
+        app_args.cfg.confidence = confidence;
+    }
+
+    if let Some(window) = args.opt_value_from_str(["-w", "--window"])? {
+        app_args.cfg.window = Some(window);
+    }
+
+    if let Some(rate) = args.opt_value_from_str(["-r", "--rate"])? {
+        app_args.cfg.rate = rate;
+    }
+
+    if let Some(prefix_rate) = args.opt_value_from_str("--prefix-rate")? {
+        app_args.cfg.prefix_rate = prefix_rate;
+    }
+
+    if let Some(budget) = args.opt_value_from_str("--budget")? {
+        app_args.cfg.budget = Some(budget);
+    }
+
+    if let Some(interface) = args.opt_value_from_str(["-i", "--interface"])? {
+        app_args.cfg.interface = Some(interface);
+    }
+
+    if let Some(source) = args.opt_value_from_fn(["-s", "--source"], parse_ip)? {
+        app_args.cfg.source = Some(source);
+    }
+
+    if let Some(simulate) = args.opt_value_from_os_str("--simulate", parse_path)? {
+        app_args.cfg.simulate = Some(simulate);
+    }
+
+    if let Some(pcap) = args.opt_value_from_os_str("--pcap", parse_path)? {
+        app_args.cfg.pcap = Some(pcap);
+    }
+
+    if let Some(format) = args.opt_value_from_str("--format")? {
+        app_args.cfg.format = Some(format);
+    }
+
     if let Ok(db) = args.value_from_os_str(["-D", "--db"], parse_path) {
         app_args.cfg.db = db;
     }
@@ -0,0 +1,206 @@
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    net::IpAddr,
+};
+
+use crate::{Edge, StopReason, TraceQuery, Vertex};
+
+/// Upper bound of flows probed to find the next hops of a single vertex. It
+/// stops the search for flows through vertices few flows pass through.
+const MAX_FLOWS_PER_VERTEX: usize = 128;
+
+/// Upper bound of the probes `stopping_point` asks for. No vertex is probed
+/// with more flows than `MAX_FLOWS_PER_VERTEX` anyway.
+const MAX_STOPPING_POINT: i32 = 1024;
+
+/// Interfaces that answered the probes of each flow, by TTL. `None` marks a
+/// probe that wasn't answered.
+type Paths = HashMap<u16, HashMap<u8, Option<IpAddr>>>;
+
+/// Run the Multipath Detection Algorithm from `first_ttl` up to `max_hops`.
+/// `probe` sends a single probe with a TTL and a flow identifier, and returns
+/// `None` once the probe budget is exhausted. Returns the vertices and edges
+/// of the graph and why the search stopped.
+///
+/// For every vertex at the previous TTL, flows that pass through it are
+/// probed at the current TTL until enough of them have been sent to have seen
+/// all next hops of the vertex with the given confidence. The search stops at
+/// the first TTL that was answered by the destination, once the budget is
+/// exhausted, or after `gap_limit` consecutive TTLs that answered no probe.
+pub(crate) fn discover<F>(
+    mut probe: F,
+    first_ttl: u8,
+    max_hops: u8,
+    confidence: f64,
+    gap_limit: Option<u8>,
+    destination: IpAddr,
+) -> (Vec<Vertex>, Vec<Edge>, StopReason)
+where
+    F: FnMut(u8, u16) -> Option<TraceQuery>,
+{
+    let mut paths: Paths = HashMap::new();
+    let mut next_flow: u16 = 0;
+    let mut vertices: BTreeSet<Vertex> = BTreeSet::new();
+    let mut edges: BTreeSet<Edge> = BTreeSet::new();
+    let mut stop_reason = StopReason::MaxHops;
+    let mut gap = 0;
+
+    for ttl in first_ttl..=max_hops {
+        let mut done = false;
+        let mut exhausted = false;
+
+        // The first TTL has a single predecessor, the source, which every flow
+        // passes through.
+        let mut seen: BTreeSet<Option<IpAddr>> = if ttl == first_ttl {
+            vec![None].into_iter().collect()
+        } else {
+            paths
+                .values()
+                .filter_map(|path| path.get(&(ttl - 1)).copied())
+                .collect()
+        };
+        let mut queue: VecDeque<Option<IpAddr>> = seen.iter().copied().collect();
+
+        'search: while let Some(vertex) = queue.pop_front() {
+            let mut flows = 0;
+
+            loop {
+                let through: Vec<u16> = paths
+                    .iter()
+                    .filter(|(_, path)| ttl == first_ttl || path.get(&(ttl - 1)) == Some(&vertex))
+                    .map(|(&flow, _)| flow)
+                    .collect();
+                let answers: Vec<Option<IpAddr>> = through
+                    .iter()
+                    .filter_map(|flow| paths[flow].get(&ttl).copied())
+                    .collect();
+                let found = answers
+                    .iter()
+                    .flatten()
+                    .collect::<BTreeSet<&IpAddr>>()
+                    .len();
+
+                if answers.len() >= stopping_point(found, confidence)
+                    || flows >= MAX_FLOWS_PER_VERTEX
+                {
+                    break;
+                }
+                flows += 1;
+
+                let flow = match through.iter().find(|flow| !paths[flow].contains_key(&ttl)) {
+                    Some(&flow) => flow,
+                    // New flows are probed at the previous TTL first to find
+                    // out which vertex they pass through. Vertices first
+                    // reached that way have their next hops searched as well.
+                    None => {
+                        let flow = next_flow;
+                        next_flow = next_flow.wrapping_add(1);
+                        let path = paths.entry(flow).or_default();
+                        if ttl != first_ttl {
+                            let addr = match probe(ttl - 1, flow) {
+                                Some(query) => answer(query).0,
+                                None => {
+                                    exhausted = true;
+                                    break 'search;
+                                }
+                            };
+                            path.insert(ttl - 1, addr);
+                            if seen.insert(addr) {
+                                if let Some(addr) = addr {
+                                    vertices.insert(Vertex { ttl: ttl - 1, addr });
+                                }
+                                queue.push_back(addr);
+                            }
+                            continue;
+                        }
+                        flow
+                    }
+                };
+
+                let (addr, is_final) = match probe(ttl, flow) {
+                    Some(query) => answer(query),
+                    None => {
+                        exhausted = true;
+                        break 'search;
+                    }
+                };
+                paths.entry(flow).or_default().insert(ttl, addr);
+                done |= is_final || addr == Some(destination);
+            }
+        }
+
+        let mut unresponsive = true;
+        for path in paths.values() {
+            if let Some(Some(addr)) = path.get(&ttl) {
+                unresponsive = false;
+                vertices.insert(Vertex { ttl, addr: *addr });
+
+                if let Some(Some(from)) = path.get(&(ttl.wrapping_sub(1))) {
+                    edges.insert(Edge {
+                        ttl: ttl - 1,
+                        from: *from,
+                        to: *addr,
+                    });
+                }
+            }
+        }
+
+        if done {
+            stop_reason = StopReason::Completed;
+            break;
+        } else if exhausted {
+            stop_reason = StopReason::Budget;
+            break;
+        } else if unresponsive {
+            gap += 1;
+            if Some(gap) == gap_limit {
+                stop_reason = StopReason::GapLimit;
+                break;
+            }
+        } else {
+            gap = 0;
+        }
+    }
+
+    (
+        vertices.into_iter().collect(),
+        edges.into_iter().collect(),
+        stop_reason,
+    )
+}
+
+/// The address that answered a query and whether only a destination could
+/// have sent the reply.
+fn answer(query: TraceQuery) -> (Option<IpAddr>, bool) {
+    match query.response() {
+        Some(response) => (Some(response.addr), response.reply.is_final()),
+        None => (None, false),
+    }
+}
+
+/// The number of probes needed to rule out, with the given confidence, that a
+/// vertex has more than `found` next hops. This is the smallest `n` for which
+/// `n` probes spread uniformly over `found + 1` next hops miss at least one of
+/// them with a probability of at most `1 - confidence`.
+pub(crate) fn stopping_point(found: usize, confidence: f64) -> usize {
+    if found == 0 {
+        return 1;
+    }
+
+    let hops = (found + 1) as i32;
+    let alpha = 1.0 - confidence;
+
+    (1..=MAX_STOPPING_POINT)
+        .find(|&n| {
+            // Inclusion-exclusion over the hops that weren't seen.
+            let mut miss = 0.0;
+            let mut binomial = 1.0;
+            for i in 1..hops {
+                binomial = binomial * f64::from(hops - i + 1) / f64::from(i);
+                let term = binomial * (f64::from(hops - i) / f64::from(hops)).powi(n);
+                miss += if i % 2 == 1 { term } else { -term };
+            }
+            miss <= alpha
+        })
+        .map_or(usize::MAX, |n| n as usize)
+}
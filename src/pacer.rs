@@ -0,0 +1,239 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Prefix lengths of the destination prefixes limited by a per-prefix rate.
+const PREFIX_LENGTH_V4: u8 = 24;
+const PREFIX_LENGTH_V6: u8 = 48;
+
+/// Number of per-prefix buckets kept before idle ones are forgotten.
+const MAX_PREFIXES: usize = 4096;
+
+/// Paces the probes of all traces and scans that share it. Probes are sent
+/// at most at a global rate and at a rate per destination prefix, and no
+/// more than a budget of probes are sent per day.
+#[derive(Debug)]
+pub struct Pacer {
+    rate: u32,
+    prefix_rate: u32,
+    budget: u64,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    global: Bucket,
+    prefixes: HashMap<IpAddr, Bucket>,
+    /// Day since the epoch in UTC the budget was last used on.
+    day: u64,
+    /// Probes sent on that day, including the ones sent before this pacer
+    /// was created.
+    used: u64,
+    /// Probes sent since the last call of `take_sent`, by the day they were
+    /// sent on.
+    sent: Vec<(u64, u64)>,
+}
+
+impl Pacer {
+    /// Creates a pacer that sends at most `rate` probes per second, 0 sends
+    /// as fast as possible.
+    pub fn new(rate: u32) -> Self {
+        Pacer {
+            rate,
+            prefix_rate: 0,
+            budget: 0,
+            state: Mutex::new(State {
+                global: Bucket::new(rate),
+                prefixes: HashMap::new(),
+                day: today(),
+                used: 0,
+                sent: vec![],
+            }),
+        }
+    }
+
+    /// Builder: Probes sent per second to each /24 IPv4 or /48 IPv6
+    /// destination prefix, 0 doesn't limit them
+    pub fn with_prefix_rate(mut self, prefix_rate: u32) -> Self {
+        self.prefix_rate = prefix_rate;
+        self
+    }
+
+    /// Builder: Probes sent per day in UTC, of which `used` were sent today
+    /// already, 0 doesn't limit them
+    pub fn with_budget(mut self, budget: u64, used: u64) -> Self {
+        self.budget = budget;
+        self.state.get_mut().unwrap().used = used;
+        self
+    }
+
+    /// The probes per second of this pacer.
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    /// The daily probe budget of this pacer, 0 if it has none.
+    pub fn budget(&self) -> u64 {
+        self.budget
+    }
+
+    /// Wait until a probe to `destination` may be sent and count it. Returns
+    /// `false` without waiting once the budget of the day is used up.
+    pub fn acquire(&self, destination: &IpAddr) -> bool {
+        let prefix = prefix(destination);
+
+        loop {
+            let delay = {
+                let mut state = self.state.lock().unwrap();
+                let now = Instant::now();
+
+                let day = today();
+                if day != state.day {
+                    state.day = day;
+                    state.used = 0;
+                }
+                if self.budget > 0 && state.used >= self.budget {
+                    return false;
+                }
+
+                if state.prefixes.len() > MAX_PREFIXES {
+                    state.prefixes.retain(|_, bucket| !bucket.is_full(now));
+                }
+                let prefix_rate = self.prefix_rate;
+                let State {
+                    global, prefixes, ..
+                } = &mut *state;
+                // Prefixes aren't tracked without a rate for them.
+                let mut bucket = match prefix_rate {
+                    0 => None,
+                    _ => Some(
+                        prefixes
+                            .entry(prefix)
+                            .or_insert_with(|| Bucket::new(prefix_rate)),
+                    ),
+                };
+
+                let delay = global.delay(now).max(
+                    bucket
+                        .as_mut()
+                        .map_or(Duration::from_millis(0), |bucket| bucket.delay(now)),
+                );
+                if delay == Duration::from_millis(0) {
+                    global.take();
+                    if let Some(bucket) = bucket {
+                        bucket.take();
+                    }
+                    state.used += 1;
+                    match state.sent.last_mut() {
+                        Some((sent_on, sent)) if *sent_on == day => *sent += 1,
+                        _ => state.sent.push((day, 1)),
+                    }
+                    return true;
+                }
+
+                delay
+            };
+
+            thread::sleep(delay);
+        }
+    }
+
+    /// Test whether the budget of the day is used up.
+    pub fn is_exhausted(&self) -> bool {
+        let state = self.state.lock().unwrap();
+
+        self.budget > 0 && state.day == today() && state.used >= self.budget
+    }
+
+    /// Return the number of probes sent since the last call on each day
+    /// since the epoch in UTC they were sent on.
+    pub fn take_sent(&self) -> Vec<(u64, u64)> {
+        let mut state = self.state.lock().unwrap();
+
+        std::mem::take(&mut state.sent)
+    }
+}
+
+/// A token bucket that fills at a rate of tokens per second up to a burst
+/// of 10 milliseconds worth of tokens, which makes up for oversleeping
+/// without sending probes in bursts.
+#[derive(Debug)]
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u32) -> Self {
+        Bucket {
+            rate: f64::from(rate),
+            tokens: 1.0,
+            updated: Instant::now(),
+        }
+    }
+
+    fn burst(&self) -> f64 {
+        (self.rate / 100.0).max(1.0)
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst());
+        self.updated = now;
+    }
+
+    /// The time until a token is available. Buckets with no rate always
+    /// have one.
+    fn delay(&mut self, now: Instant) -> Duration {
+        if self.rate == 0.0 {
+            return Duration::from_millis(0);
+        }
+
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            Duration::from_millis(0)
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / self.rate)
+        }
+    }
+
+    fn take(&mut self) {
+        if self.rate > 0.0 {
+            self.tokens -= 1.0;
+        }
+    }
+
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.rate == 0.0 || {
+            self.refill(now);
+            self.tokens >= self.burst()
+        }
+    }
+}
+
+/// The destination prefix `destination` is in.
+fn prefix(destination: &IpAddr) -> IpAddr {
+    match destination {
+        IpAddr::V4(addr) => {
+            let mask = u32::MAX << (32 - PREFIX_LENGTH_V4);
+            IpAddr::V4((u32::from(*addr) & mask).into())
+        }
+        IpAddr::V6(addr) => {
+            let mask = u128::MAX << (128 - PREFIX_LENGTH_V6);
+            IpAddr::V6((u128::from(*addr) & mask).into())
+        }
+    }
+}
+
+/// The current day since the epoch in UTC, like `date('now')` in SQLite.
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() / 86400)
+        .unwrap_or(0)
+}
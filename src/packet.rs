@@ -1,60 +1,412 @@
-use pnet::packet::{ip::IpNextHeaderProtocols, ipv4, udp, Packet};
+use pnet::packet::{
+    icmp::{self, echo_request, IcmpPacket, IcmpTypes},
+    icmpv6::{self, Icmpv6Packet, Icmpv6Types},
+    ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
+    ipv4::{self, Ipv4Flags},
+    ipv6,
+    tcp::{self, TcpFlags},
+    udp, MutablePacket, Packet,
+};
 use rand::random;
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr};
 
+use crate::{reply::Matcher, Mode, Protocol};
+
+/// A probe ready to be sent. IPv4 probes start with the IP header, IPv6 probes
+/// start with the transport header because the kernel builds the IPv6 header.
+pub struct ProbePacket {
+    buf: Vec<u8>,
+}
+
+impl Packet for ProbePacket {
+    fn packet(&self) -> &[u8] {
+        &self.buf
+    }
+
+    fn payload(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct PacketBuilder {
-    source_ip: Ipv4Addr,
-    destination_ip: Ipv4Addr,
+    source_ip: IpAddr,
+    destination_ip: IpAddr,
+    protocol: Protocol,
+    mode: Mode,
+    ident: u16,
+    source_port: u16,
+    /// Size of probes including the IP header. Probes have a fixed size
+    /// otherwise.
+    size: Option<u16>,
+    /// TOS byte of IPv4 probes and traffic class of IPv6 probes.
+    tos: u8,
 }
 
-impl<'a> PacketBuilder {
-    pub fn new(source_ip: Ipv4Addr, destination_ip: Ipv4Addr) -> Self {
+impl PacketBuilder {
+    pub fn new(source_ip: IpAddr, destination_ip: IpAddr, protocol: Protocol, mode: Mode) -> Self {
         PacketBuilder {
             source_ip,
             destination_ip,
+            protocol,
+            mode,
+            ident: random::<u16>(),
+            source_port: random::<u16>() | 0x8000,
+            size: None,
+            tos: 0,
         }
     }
 
-    pub fn build_packet(&self, ttl: u8, port: u16) -> impl Packet {
-        Self::build_udp_packet(self.source_ip, self.destination_ip, ttl, port)
+    /// A copy of this builder whose probes carry `tos` as TOS byte or
+    /// traffic class.
+    pub fn with_tos(&self, tos: u8) -> Self {
+        PacketBuilder { tos, ..*self }
     }
 
-    /// Create a new UDP packet
-    fn build_udp_packet(
-        source_ip: Ipv4Addr,
-        destination_ip: Ipv4Addr,
-        ttl: u8,
-        port: u16,
-    ) -> ipv4::Ipv4Packet<'a> {
-        const UDP_SIZE: usize = 64;
-        const IPV4_SIZE: usize = ipv4::MutableIpv4Packet::minimum_packet_size() + UDP_SIZE;
+    /// A copy of this builder whose probes are `size` bytes long including
+    /// the IP header, as far as their headers fit.
+    pub fn with_size(&self, size: u16) -> Self {
+        PacketBuilder {
+            size: Some(size),
+            ..*self
+        }
+    }
+
+    /// The length of the transport header and payload of a probe, `default`
+    /// if no size is set and at least `min`.
+    fn segment_size(&self, default: usize, min: usize) -> usize {
+        let ip_header = match self.destination_ip {
+            IpAddr::V4(_) => ipv4::MutableIpv4Packet::minimum_packet_size(),
+            IpAddr::V6(_) => 40,
+        };
+
+        match self.size {
+            Some(size) => (size as usize).saturating_sub(ip_header).max(min),
+            None => default,
+        }
+    }
+
+    /// A copy of this builder whose Echo Requests carry `ident` as
+    /// identifier.
+    pub fn with_ident(&self, ident: u16) -> Self {
+        PacketBuilder { ident, ..*self }
+    }
+
+    /// The ICMP identifier of all Echo Requests built by this builder.
+    pub fn ident(&self) -> u16 {
+        self.ident
+    }
 
-        let mut udp_buf: Vec<u8> = vec![0; UDP_SIZE];
-        let ipv4_buf: Vec<u8> = vec![0; IPV4_SIZE];
+    /// The source port of TCP probes, and of UDP probes of Paris and MDA
+    /// traces, of a flow.
+    pub fn source_port(&self, flow: u16) -> u16 {
+        self.source_port.wrapping_add(flow)
+    }
+
+    /// The TCP sequence number of the probe with sequence number `seq`.
+    pub fn tcp_sequence(&self, seq: u16) -> u32 {
+        (self.ident as u32) << 16 | seq as u32
+    }
+
+    /// The IPv4 IP ID of the probe with sequence number `seq`. The random
+    /// identifier of the builder keeps the probes of other traces apart.
+    fn ip_ident(&self, seq: u16) -> u16 {
+        self.ident.wrapping_add(seq)
+    }
+
+    /// The source port of a probe. Classic UDP traces change it with every
+    /// probe, all others keep it fixed for a flow.
+    fn probe_source_port(&self, seq: u16, flow: u16) -> u16 {
+        match (self.protocol, self.mode) {
+            (Protocol::Udp, Mode::Classic) => self.source_port(seq),
+            _ => self.source_port(flow),
+        }
+    }
+
+    /// The header fields that tie a reply to the probe built with the same
+    /// arguments.
+    pub fn matcher(&self, port: u16, seq: u16, flow: u16) -> Matcher {
+        Matcher {
+            protocol: self.protocol,
+            next_header: next_header(self.protocol, self.destination_ip.is_ipv6()),
+            ipv6: self.destination_ip.is_ipv6(),
+            source_port: self.probe_source_port(seq, flow),
+            destination_port: port,
+            ident: self.ident,
+            seq,
+            tcp_sequence: self.tcp_sequence(seq),
+            destination: self.destination_ip,
+            ip_ident: match self.destination_ip {
+                IpAddr::V4(_) => Some(self.ip_ident(seq)),
+                IpAddr::V6(_) => None,
+            },
+        }
+    }
+
+    pub fn build_packet(&self, ttl: u8, port: u16, seq: u16, flow: u16) -> ProbePacket {
+        // Paris and MDA traces keep every field that load balancers hash on
+        // fixed for all probes of a flow, and keep the ICMP checksum constant.
+        // UDP probes of all modes carry the probe sequence in the UDP checksum,
+        // which ICMP errors quote, to match replies to probes. IPv4 probes carry
+        // it, offset by the identifier, in the IP ID as well, which NATs leave alone when they rewrite
+        // the ports, checksums or identifiers.
+        let icmp_checksum = match self.mode {
+            Mode::Classic => None,
+            Mode::Paris | Mode::Mda | Mode::Yarrp => Some(self.ident.wrapping_add(flow)),
+        };
+        let source_port = self.probe_source_port(seq, flow);
+
+        let segment = match self.protocol {
+            Protocol::Udp => self.build_udp_packet(source_port, port, Some(seq)),
+            Protocol::Icmp => self.build_icmp_packet(self.ident, seq, icmp_checksum, &[]),
+            Protocol::Tcp => self.build_tcp_packet(source_port, port, self.tcp_sequence(seq)),
+        };
+
+        let buf = match (self.source_ip, self.destination_ip) {
+            (IpAddr::V4(source_ip), IpAddr::V4(destination_ip)) => self.build_ipv4_packet(
+                source_ip,
+                destination_ip,
+                ttl,
+                self.ip_ident(seq),
+                next_header(self.protocol, false),
+                &segment,
+            ),
+            _ => segment,
+        };
+
+        ProbePacket { buf }
+    }
+
+    /// The probe `packet` with TTL `ttl` as it leaves this host. IPv6 probes
+    /// get the header the kernel adds to them.
+    pub fn sent_packet(&self, ttl: u8, packet: &ProbePacket) -> Vec<u8> {
+        match (self.source_ip, self.destination_ip) {
+            (IpAddr::V6(source_ip), IpAddr::V6(destination_ip)) => {
+                let ipv6_size = ipv6::MutableIpv6Packet::minimum_packet_size() + packet.buf.len();
+                let mut ipv6_buf: Vec<u8> = vec![0; ipv6_size];
+
+                let mut ipv6_packet = ipv6::MutableIpv6Packet::new(&mut ipv6_buf[..]).unwrap();
+                ipv6_packet.set_version(6);
+                ipv6_packet.set_traffic_class(self.tos);
+                ipv6_packet.set_payload_length(packet.buf.len() as u16);
+                ipv6_packet.set_next_header(next_header(self.protocol, true));
+                ipv6_packet.set_hop_limit(ttl);
+                ipv6_packet.set_source(source_ip);
+                ipv6_packet.set_destination(destination_ip);
+                ipv6_packet.set_payload(&packet.buf);
+
+                ipv6_buf
+            }
+            _ => packet.buf.clone(),
+        }
+    }
+
+    /// The transport header and payload of `packet`, without the IP header
+    /// of IPv4 probes.
+    pub fn segment<'a>(&self, packet: &'a ProbePacket) -> &'a [u8] {
+        match self.destination_ip {
+            IpAddr::V4(_) => &packet.buf[ipv4::MutableIpv4Packet::minimum_packet_size()..],
+            IpAddr::V6(_) => &packet.buf,
+        }
+    }
+
+    /// Create a new UDP packet. If `checksum` is set, the payload is chosen so
+    /// that the UDP checksum equals it.
+    fn build_udp_packet(&self, source_port: u16, port: u16, checksum: Option<u16>) -> Vec<u8> {
+        // The payload holds at least the word that sets the checksum.
+        let udp_size = self.segment_size(64, 10);
+
+        let mut udp_buf: Vec<u8> = vec![0; udp_size];
 
         let mut udp_packet = udp::MutableUdpPacket::new(&mut udp_buf[..]).unwrap();
-        udp_packet.set_source(random::<u16>());
+        udp_packet.set_source(source_port);
         udp_packet.set_destination(port);
-        udp_packet.set_length(UDP_SIZE as u16);
-        udp_packet.set_payload(&[0; UDP_SIZE - 8]);
-        let csum = udp::ipv4_checksum(&udp_packet.to_immutable(), &source_ip, &destination_ip);
+        udp_packet.set_length(udp_size as u16);
+        let mut csum = self.udp_checksum(&udp_packet.to_immutable());
+        if let Some(target) = checksum {
+            let word = compensate_checksum(csum, target);
+            udp_packet.payload_mut()[..2].copy_from_slice(&word.to_be_bytes());
+            csum = self.udp_checksum(&udp_packet.to_immutable());
+        }
         udp_packet.set_checksum(csum);
 
-        let mut ipv4_packet = ipv4::MutableIpv4Packet::owned(ipv4_buf).unwrap();
+        udp_buf
+    }
+
+    /// A copy of this builder for probes to another destination that share
+    /// the ICMP identifier and source port of this one.
+    pub fn with_destination(&self, destination_ip: IpAddr) -> Self {
+        PacketBuilder {
+            destination_ip,
+            ..*self
+        }
+    }
+
+    /// Build a probe of a stateless scan. The probe carries its TTL in the IP
+    /// ID and in the data of Echo Requests, and `stamp` in the UDP checksum or
+    /// the ICMP sequence number, so that its replies can be matched without
+    /// keeping state per probe. The high byte of the IP ID is the one of the
+    /// ICMP identifier.
+    pub fn build_stateless_packet(&self, ttl: u8, port: u16, stamp: u16) -> ProbePacket {
+        let segment = match self.protocol {
+            Protocol::Icmp => self.build_icmp_packet(self.ident, stamp, None, &[ttl]),
+            _ => self.build_udp_packet(self.source_port, port, Some(stamp)),
+        };
+
+        let buf = match (self.source_ip, self.destination_ip) {
+            (IpAddr::V4(source_ip), IpAddr::V4(destination_ip)) => self.build_ipv4_packet(
+                source_ip,
+                destination_ip,
+                ttl,
+                self.ident & 0xff00 | u16::from(ttl),
+                next_header(self.protocol, false),
+                &segment,
+            ),
+            _ => segment,
+        };
+
+        ProbePacket { buf }
+    }
+
+    /// Create a new ICMP or ICMPv6 Echo Request packet. If `checksum` is set,
+    /// the payload is chosen so that the ICMP checksum equals it. `data` is
+    /// written to the payload after the two bytes that are used for that.
+    fn build_icmp_packet(
+        &self,
+        ident: u16,
+        seq: u16,
+        checksum: Option<u16>,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let icmp_size = self.segment_size(64, 10 + data.len());
+
+        let mut icmp_buf: Vec<u8> = vec![0; icmp_size];
+
+        // Echo Requests of both versions only differ in their type.
+        let mut icmp_packet =
+            echo_request::MutableEchoRequestPacket::new(&mut icmp_buf[..]).unwrap();
+        icmp_packet.set_identifier(ident);
+        icmp_packet.set_sequence_number(seq);
+        icmp_packet.payload_mut()[2..2 + data.len()].copy_from_slice(data);
+        if self.destination_ip.is_ipv6() {
+            icmp_packet.packet_mut()[0] = Icmpv6Types::EchoRequest.0;
+        } else {
+            icmp_packet.set_icmp_type(IcmpTypes::EchoRequest);
+        }
+        let mut csum = self.icmp_checksum(icmp_packet.packet());
+        if let Some(target) = checksum {
+            let word = compensate_checksum(csum, target);
+            icmp_packet.payload_mut()[..2].copy_from_slice(&word.to_be_bytes());
+            csum = self.icmp_checksum(icmp_packet.packet());
+        }
+        icmp_packet.set_checksum(csum);
+
+        icmp_buf
+    }
+
+    /// Create a new TCP SYN packet
+    fn build_tcp_packet(&self, source_port: u16, port: u16, sequence: u32) -> Vec<u8> {
+        // Probes that are larger than the header carry zeros as data.
+        let tcp_size = self.segment_size(20, 20);
+
+        let mut tcp_buf: Vec<u8> = vec![0; tcp_size];
+
+        let mut tcp_packet = tcp::MutableTcpPacket::new(&mut tcp_buf[..]).unwrap();
+        tcp_packet.set_source(source_port);
+        tcp_packet.set_destination(port);
+        tcp_packet.set_sequence(sequence);
+        tcp_packet.set_data_offset(5);
+        tcp_packet.set_flags(TcpFlags::SYN);
+        tcp_packet.set_window(5840);
+        let csum = match (self.source_ip, self.destination_ip) {
+            (IpAddr::V4(source_ip), IpAddr::V4(destination_ip)) => {
+                tcp::ipv4_checksum(&tcp_packet.to_immutable(), &source_ip, &destination_ip)
+            }
+            (IpAddr::V6(source_ip), IpAddr::V6(destination_ip)) => {
+                tcp::ipv6_checksum(&tcp_packet.to_immutable(), &source_ip, &destination_ip)
+            }
+            _ => 0,
+        };
+        tcp_packet.set_checksum(csum);
+
+        tcp_buf
+    }
+
+    /// Wrap a transport payload into an IPv4 packet with the DF flag set.
+    fn build_ipv4_packet(
+        &self,
+        source_ip: Ipv4Addr,
+        destination_ip: Ipv4Addr,
+        ttl: u8,
+        ip_ident: u16,
+        protocol: IpNextHeaderProtocol,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let ipv4_size = ipv4::MutableIpv4Packet::minimum_packet_size() + payload.len();
+        let mut ipv4_buf: Vec<u8> = vec![0; ipv4_size];
+
+        let mut ipv4_packet = ipv4::MutableIpv4Packet::new(&mut ipv4_buf[..]).unwrap();
         ipv4_packet.set_header_length(5);
-        ipv4_packet.set_fragment_offset(16384);
-        ipv4_packet.set_identification(rand::random::<u16>());
+        ipv4_packet.set_dscp(self.tos >> 2);
+        ipv4_packet.set_ecn(self.tos & 0x03);
+        ipv4_packet.set_flags(Ipv4Flags::DontFragment);
+        ipv4_packet.set_identification(ip_ident);
         ipv4_packet.set_version(4);
         ipv4_packet.set_ttl(ttl);
-        ipv4_packet.set_next_level_protocol(IpNextHeaderProtocols::Udp);
+        ipv4_packet.set_next_level_protocol(protocol);
         ipv4_packet.set_source(source_ip);
         ipv4_packet.set_destination(destination_ip);
-        ipv4_packet.set_total_length(IPV4_SIZE as u16);
-        ipv4_packet.set_payload(&udp_buf);
+        ipv4_packet.set_total_length(ipv4_size as u16);
+        ipv4_packet.set_payload(payload);
 
         let csum = ipv4::checksum(&ipv4_packet.to_immutable());
         ipv4_packet.set_checksum(csum);
 
-        ipv4_packet.consume_to_immutable()
+        ipv4_buf
     }
+
+    /// Calculate the UDP checksum over the pseudo header of the address family
+    /// of this trace.
+    fn udp_checksum(&self, packet: &udp::UdpPacket) -> u16 {
+        match (self.source_ip, self.destination_ip) {
+            (IpAddr::V4(source_ip), IpAddr::V4(destination_ip)) => {
+                udp::ipv4_checksum(packet, &source_ip, &destination_ip)
+            }
+            (IpAddr::V6(source_ip), IpAddr::V6(destination_ip)) => {
+                udp::ipv6_checksum(packet, &source_ip, &destination_ip)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Calculate the ICMP checksum. ICMPv6 checksums include a pseudo header.
+    fn icmp_checksum(&self, packet: &[u8]) -> u16 {
+        match (self.source_ip, self.destination_ip) {
+            (IpAddr::V6(source_ip), IpAddr::V6(destination_ip)) => icmpv6::checksum(
+                &Icmpv6Packet::new(packet).unwrap(),
+                &source_ip,
+                &destination_ip,
+            ),
+            _ => icmp::checksum(&IcmpPacket::new(packet).unwrap()),
+        }
+    }
+}
+
+/// The IP protocol number carried by probes of a protocol, over IPv6 if `ipv6`
+/// is set.
+pub fn next_header(protocol: Protocol, ipv6: bool) -> IpNextHeaderProtocol {
+    match protocol {
+        Protocol::Udp => IpNextHeaderProtocols::Udp,
+        Protocol::Icmp if ipv6 => IpNextHeaderProtocols::Icmpv6,
+        Protocol::Icmp => IpNextHeaderProtocols::Icmp,
+        Protocol::Tcp => IpNextHeaderProtocols::Tcp,
+    }
+}
+
+/// Calculate the 16-bit word that turns a packet with the Internet checksum
+/// `checksum` into one with the checksum `target` when it is added to a
+/// payload word that was zero.
+fn compensate_checksum(checksum: u16, target: u16) -> u16 {
+    let sum = (!target) as u32 + checksum as u32;
+    ((sum & 0xffff) + (sum >> 16)) as u16
 }
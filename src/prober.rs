@@ -0,0 +1,47 @@
+use std::{io, net::IpAddr, sync::mpsc, time::Instant};
+
+use crate::{
+    packet::{PacketBuilder, ProbePacket},
+    receiver::Answer,
+    reply::Matcher,
+    Protocol,
+};
+
+/// A probe of a trace, handed to a prober to send.
+pub struct Probe<'a> {
+    /// Sequence number of the probe within its trace, which the answer to it
+    /// must carry.
+    pub seq: u16,
+    pub ttl: u8,
+    /// The flow of the probe, which per-flow load balancers keep on a single
+    /// path. Classic traces vary it with every probe, except TCP ones.
+    pub flow: u16,
+    pub protocol: Protocol,
+    pub destination: IpAddr,
+    /// Destination port of UDP and TCP probes.
+    pub port: u16,
+    /// Size of the probe including the IP header.
+    pub size: usize,
+    /// When the probe was sent, which round-trip times are measured from.
+    pub sent: Instant,
+    /// Replies that come in later than this are dropped.
+    pub expires: Instant,
+    pub(crate) packet: &'a ProbePacket,
+    pub(crate) builder: &'a PacketBuilder,
+    /// Matches the replies to the probe.
+    pub(crate) matcher: Matcher,
+}
+
+/// Sends the probes of a trace and hands the replies to them to the trace.
+/// Raw sockets, datagram sockets and simulated networks are probers.
+pub trait Prober: Send {
+    /// Send `probe`. Its reply, if any, is sent to `respond_to` once it is
+    /// received. A probe that couldn't be sent fails its query.
+    fn send(&mut self, probe: &Probe<'_>, respond_to: &mpsc::Sender<Answer>) -> io::Result<()>;
+
+    /// The ICMP identifier of Echo Requests, if the prober chooses it instead
+    /// of the trace.
+    fn ident(&self) -> Option<u16> {
+        None
+    }
+}
@@ -0,0 +1,498 @@
+use pnet::{
+    packet::{
+        icmp::IcmpPacket, icmpv6::Icmpv6Packet, ip::IpNextHeaderProtocols, ipv4::Ipv4Packet,
+        tcp::TcpPacket,
+    },
+    transport::{
+        transport_channel,
+        TransportChannelType::Layer4,
+        TransportProtocol::{Ipv4, Ipv6},
+        TransportReceiver,
+    },
+};
+use std::{
+    io, mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    inspect,
+    reply::{Matcher, Reply},
+    Error, Modification, MplsLabel, ReplyKind, Result,
+};
+
+/// How often the receiver thread checks whether it should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Returns the kernel receive timestamp of the last packet read from a
+/// socket as a `timespec`.
+#[cfg(target_os = "linux")]
+const SIOCGSTAMPNS: libc::c_ulong = 0x8907;
+
+/// A reply handed to the trace that sent the probe.
+#[derive(Debug, Clone)]
+pub struct Answer {
+    /// Sequence number of the probe within its trace.
+    pub seq: u16,
+    pub addr: IpAddr,
+    pub reply: ReplyKind,
+    /// The MPLS label stack quoted in the reply.
+    pub mpls: Vec<MplsLabel>,
+    /// The TTL of the reply, if the socket reported it.
+    pub ttl: Option<u8>,
+    /// The TTL of the probe as quoted in an error message.
+    pub quoted_ttl: Option<u8>,
+    /// The header fields of the probe that were changed on the way.
+    pub modifications: Vec<Modification>,
+    /// When the reply was received.
+    pub received: Instant,
+    /// The reply as it was received, if the prober has it. IPv4 replies
+    /// start with the IP header, IPv6 replies with the ICMPv6 or TCP header.
+    pub packet: Vec<u8>,
+}
+
+/// A reply handed as is to a listener.
+#[derive(Debug, Clone)]
+pub(crate) struct Received {
+    pub(crate) reply: Reply,
+    pub(crate) addr: IpAddr,
+    /// The TTL of the reply, if the socket reported it.
+    pub(crate) ttl: Option<u8>,
+    /// When the reply was received.
+    pub(crate) received: Instant,
+}
+
+/// Parses the packets read from a socket.
+type Parse = fn(&[u8]) -> Option<Reply>;
+
+/// Decides whether a listener accepts a reply from an address.
+type Accepts = Box<dyn Fn(&Reply, &IpAddr) -> bool + Send>;
+
+/// Accepts the replies of a whole instance, like a stateless scan, that keeps
+/// no table of outstanding probes.
+struct Listener {
+    accepts: Accepts,
+    respond_to: mpsc::Sender<Received>,
+}
+
+/// The outstanding probes and the listeners replies are handed to.
+#[derive(Default)]
+struct Table {
+    entries: Vec<Entry>,
+    listeners: Vec<Listener>,
+}
+
+/// An outstanding probe waiting for its reply.
+struct Entry {
+    matcher: Matcher,
+    /// The probe as it was sent.
+    probe: Vec<u8>,
+    /// The probe is forgotten if no reply came in until then.
+    expires: Instant,
+    respond_to: mpsc::Sender<Answer>,
+}
+
+/// The raw sockets replies to probes of either address family arrive on.
+/// The IPv6 ones are missing on hosts without IPv6, or where they are denied.
+struct Sockets {
+    icmp: TransportReceiver,
+    tcp: TransportReceiver,
+    icmpv6: Option<TransportReceiver>,
+    tcpv6: Option<TransportReceiver>,
+}
+
+/// Receives the replies to the probes of any number of traces on a single
+/// set of raw sockets. A thread reads every reply once and hands it to the
+/// probe it answers through a table of outstanding probes.
+pub struct Receiver {
+    table: Arc<Mutex<Table>>,
+    /// Why the IPv6 sockets couldn't be opened.
+    ipv6_error: Option<String>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Receiver {
+    /// Open the receiving sockets and start the receiver thread. IPv4
+    /// traces work without the IPv6 sockets, so failing to open those only
+    /// fails IPv6 traces.
+    pub fn new() -> Result<Self> {
+        let ipv6 = open(Layer4(Ipv6(IpNextHeaderProtocols::Icmpv6)))
+            .and_then(|icmpv6| Ok((icmpv6, open(Layer4(Ipv6(IpNextHeaderProtocols::Tcp)))?)));
+        let (icmpv6, tcpv6, ipv6_error) = match ipv6 {
+            Ok((icmpv6, tcpv6)) => (Some(icmpv6), Some(tcpv6), None),
+            Err(e) => (None, None, Some(e.to_string())),
+        };
+        let sockets = Sockets {
+            icmp: open(Layer4(Ipv4(IpNextHeaderProtocols::Icmp)))?,
+            tcp: open(Layer4(Ipv4(IpNextHeaderProtocols::Tcp)))?,
+            icmpv6,
+            tcpv6,
+        };
+        let table = Arc::new(Mutex::new(Table::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let table = Arc::clone(&table);
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || run(sockets, &table, &stop))
+        };
+
+        Ok(Receiver {
+            table,
+            ipv6_error,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Why replies to IPv6 probes can't be received, if the IPv6 sockets
+    /// couldn't be opened.
+    pub fn ipv6_error(&self) -> Option<&str> {
+        self.ipv6_error.as_deref()
+    }
+
+    /// Add a probe to the table of outstanding probes. Its reply is sent to
+    /// `respond_to` if it comes in before `expires`, with the header fields
+    /// that were changed on the way to the replying node. `probe` is the
+    /// packet as it was sent, including its IP header.
+    pub(crate) fn register(
+        &self,
+        matcher: Matcher,
+        probe: Vec<u8>,
+        expires: Instant,
+        respond_to: mpsc::Sender<Answer>,
+    ) {
+        self.table.lock().unwrap().entries.push(Entry {
+            matcher,
+            probe,
+            expires,
+            respond_to,
+        });
+    }
+
+    /// Hand every reply that no outstanding probe matches and that `accepts`
+    /// accepts to `respond_to`, until it is disconnected.
+    pub(crate) fn listen<F>(&self, accepts: F, respond_to: mpsc::Sender<Received>)
+    where
+        F: Fn(&Reply, &IpAddr) -> bool + Send + 'static,
+    {
+        self.table.lock().unwrap().listeners.push(Listener {
+            accepts: Box::new(accepts),
+            respond_to,
+        });
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn open(channel_type: pnet::transport::TransportChannelType) -> Result<TransportReceiver> {
+    let (_, rx) = transport_channel(4096, channel_type)
+        .map_err(|e| Error::socket("layer4: unable to create channel", e))?;
+    enable_timestamps(rx.socket.fd);
+    if matches!(channel_type, Layer4(Ipv6(_))) {
+        enable_hop_limits(rx.socket.fd);
+    }
+
+    Ok(rx)
+}
+
+/// Ask the kernel to timestamp the packets received on `fd`, so RTTs don't
+/// include the time replies wait to be read.
+#[cfg(target_os = "linux")]
+pub(crate) fn enable_timestamps(fd: libc::c_int) {
+    let on: libc::c_int = 1;
+    // Without timestamps the time replies are read at is used instead.
+    unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_TIMESTAMPNS,
+            &on as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        );
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn enable_timestamps(_fd: libc::c_int) {}
+
+/// When the kernel received the last packet read from `fd`, on the monotonic
+/// clock. Falls back to `read`, the time the packet was read, if the kernel
+/// has no timestamp for it.
+#[cfg(target_os = "linux")]
+pub(crate) fn received_at(fd: libc::c_int, read: Instant) -> Instant {
+    let mut stamp = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::ioctl(fd, SIOCGSTAMPNS as _, &mut stamp) } != 0 {
+        return read;
+    }
+
+    timestamp_instant(stamp, read)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn received_at(_fd: libc::c_int, read: Instant) -> Instant {
+    read
+}
+
+/// Convert a kernel receive timestamp of a packet that was read at `read` to
+/// the monotonic clock.
+pub(crate) fn timestamp_instant(stamp: libc::timespec, read: Instant) -> Instant {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    // The timestamp is on the realtime clock, so it is converted through its
+    // age, which is only a few microseconds off when the clock is stepped.
+    let stamp = UNIX_EPOCH + Duration::new(stamp.tv_sec as u64, stamp.tv_nsec as u32);
+    match SystemTime::now().duration_since(stamp) {
+        Ok(age) => Instant::now()
+            .checked_sub(age)
+            .map_or(read, |received| received.min(read)),
+        Err(_) => read,
+    }
+}
+
+/// Ask the kernel to report the hop limit of the packets received on the
+/// IPv6 socket `fd`, which come without their IP header.
+pub(crate) fn enable_hop_limits(fd: libc::c_int) {
+    let on: libc::c_int = 1;
+    // Without it the TTL of IPv6 replies is unknown.
+    unsafe {
+        libc::setsockopt(
+            fd,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_RECVHOPLIMIT,
+            &on as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        );
+    }
+}
+
+/// Read a packet from the raw socket `fd` into `buf`. Returns its length,
+/// the address it came from, and its hop limit if the kernel reported it.
+fn recv(fd: libc::c_int, buf: &mut [u8]) -> io::Result<(usize, IpAddr, Option<u8>)> {
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    // Control messages are aligned like their headers.
+    let mut control = [0u64; 32];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut addr as *mut libc::sockaddr_storage as *mut libc::c_void;
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    let len = unsafe { libc::recvmsg(fd, &mut msg, 0) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut hop_limit = None;
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::IPPROTO_IPV6 && (*cmsg).cmsg_type == libc::IPV6_HOPLIMIT
+            {
+                let data = libc::CMSG_DATA(cmsg) as *const libc::c_int;
+                hop_limit = Some(ptr::read_unaligned(data) as u8);
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    let addr = socket_ip(&addr).ok_or_else(|| io::Error::from(io::ErrorKind::InvalidData))?;
+
+    Ok((len as usize, addr, hop_limit))
+}
+
+/// The IP address of a socket address of either address family.
+pub(crate) fn socket_ip(addr: &libc::sockaddr_storage) -> Option<IpAddr> {
+    match addr.ss_family as libc::c_int {
+        libc::AF_INET => {
+            let addr = unsafe { &*(addr as *const _ as *const libc::sockaddr_in) };
+            Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                addr.sin_addr.s_addr,
+            ))))
+        }
+        libc::AF_INET6 => {
+            let addr = unsafe { &*(addr as *const _ as *const libc::sockaddr_in6) };
+            Some(IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr)))
+        }
+        _ => None,
+    }
+}
+
+/// Read replies until the receiver is dropped.
+fn run(sockets: Sockets, table: &Mutex<Table>, stop: &AtomicBool) {
+    // Whether each socket is an IPv6 one and how its packets are parsed.
+    let sockets: [(Option<&TransportReceiver>, bool, Parse); 4] = [
+        (Some(&sockets.icmp), false, |data| {
+            Reply::from_icmp(&IcmpPacket::new(data)?)
+        }),
+        (sockets.icmpv6.as_ref(), true, |data| {
+            Reply::from_icmpv6(&Icmpv6Packet::new(data)?)
+        }),
+        (Some(&sockets.tcp), false, |data| {
+            Reply::from_tcp(&TcpPacket::new(data)?)
+        }),
+        (sockets.tcpv6.as_ref(), true, |data| {
+            Reply::from_tcp(&TcpPacket::new(data)?)
+        }),
+    ];
+    let (fds, parsers): (Vec<_>, Vec<_>) = sockets
+        .iter()
+        .filter_map(|(rx, ipv6, parse)| Some((rx.as_ref()?.socket.fd, (*ipv6, *parse))))
+        .unzip();
+    let mut buf = [0u8; 4096];
+
+    while !stop.load(Ordering::Relaxed) {
+        let readable = match poll_readable(&fds, POLL_INTERVAL) {
+            Ok(readable) => readable,
+            Err(_) => continue,
+        };
+        let read = Instant::now();
+
+        let mut replies = vec![];
+        for (idx, (ipv6, parse)) in parsers.iter().enumerate() {
+            if !readable[idx] {
+                continue;
+            }
+            let (len, addr, hop_limit) = match recv(fds[idx], &mut buf) {
+                Ok(packet) => packet,
+                Err(_) => continue,
+            };
+            let received = received_at(fds[idx], read);
+
+            // IPv4 sockets deliver the IP header with the packet, IPv6 ones
+            // the hop limit as a control message.
+            let (data, ttl) = if *ipv6 {
+                (&buf[..len], hop_limit)
+            } else {
+                match Ipv4Packet::new(&buf[..len]) {
+                    Some(ip) => {
+                        let offset = (ip.get_header_length() as usize * 4).min(len);
+                        (&buf[offset..len], Some(ip.get_ttl()))
+                    }
+                    None => continue,
+                }
+            };
+            replies.push((parse(data), addr, ttl, received, buf[..len].to_vec()));
+        }
+
+        let mut table = table.lock().unwrap();
+        table.entries.retain(|entry| entry.expires > read);
+
+        // Replies that answer no outstanding probe and that no listener
+        // accepts are unrelated traffic.
+        for (reply, addr, ttl, received, packet) in replies {
+            let reply = match reply {
+                Some(reply) => reply,
+                None => continue,
+            };
+
+            // Replies whose quoted probe was rewritten by a NAT are only
+            // matched on its destination and IP ID if they match no probe
+            // otherwise.
+            let answered = table
+                .entries
+                .iter()
+                .enumerate()
+                .find_map(|(idx, entry)| {
+                    entry
+                        .matcher
+                        .matches(&reply, &addr)
+                        .map(|reply| (idx, reply))
+                })
+                .or_else(|| {
+                    table.entries.iter().enumerate().find_map(|(idx, entry)| {
+                        entry
+                            .matcher
+                            .matches_translated(&reply, &addr)
+                            .map(|reply| (idx, reply))
+                    })
+                });
+
+            if let Some((idx, kind)) = answered {
+                let entry = table.entries.remove(idx);
+                let _ = entry.respond_to.send(Answer {
+                    seq: entry.matcher.seq,
+                    addr,
+                    reply: kind,
+                    mpls: reply.mpls().to_vec(),
+                    ttl,
+                    quoted_ttl: reply.quoted_ttl(),
+                    modifications: inspect::modifications(&entry.probe, &reply),
+                    received,
+                    packet,
+                });
+                continue;
+            }
+
+            if let Some(idx) = table
+                .listeners
+                .iter()
+                .position(|listener| (listener.accepts)(&reply, &addr))
+            {
+                let received = Received {
+                    reply,
+                    addr,
+                    ttl,
+                    received,
+                };
+                if table.listeners[idx].respond_to.send(received).is_err() {
+                    table.listeners.remove(idx);
+                }
+            }
+        }
+    }
+}
+
+/// Wait until any of the sockets in `fds` can be read from or the timeout
+/// expires. Returns which of the sockets are readable.
+fn poll_readable(fds: &[libc::c_int], timeout: Duration) -> io::Result<Vec<bool>> {
+    let mut pollfds: Vec<libc::pollfd> = fds
+        .iter()
+        .map(|&fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    let timeout_ms = timeout.as_millis().clamp(1, libc::c_int::MAX as u128) as libc::c_int;
+
+    let res = unsafe {
+        libc::poll(
+            pollfds.as_mut_ptr(),
+            pollfds.len() as libc::nfds_t,
+            timeout_ms,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(pollfds
+        .iter()
+        .map(|pollfd| pollfd.revents & libc::POLLIN != 0)
+        .collect())
+}
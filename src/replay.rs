@@ -0,0 +1,794 @@
+use pnet::packet::{
+    icmp::{echo_request::EchoRequestPacket, IcmpPacket, IcmpTypes},
+    icmpv6::{Icmpv6Packet, Icmpv6Types},
+    ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
+    ipv4::Ipv4Packet,
+    ipv6::Ipv6Packet,
+    tcp::{TcpFlags, TcpPacket},
+    udp::UdpPacket,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    net::IpAddr,
+    path::Path,
+    time::Duration,
+};
+
+use crate::{
+    capture::{
+        ENHANCED_PACKET, IF_TSRESOL, INTERFACE_DESCRIPTION, LINKTYPE_RAW, OPT_COMMENT, OPT_END,
+        SECTION_HEADER,
+    },
+    inspect,
+    packet::next_header,
+    reply::{Matcher, Reply},
+    Config, Error, Hop, Mode, Protocol, ReplyKind, Response, Result, StopReason, Trace, TraceQuery,
+};
+
+/// Link types of the packets of a capture, besides raw IP packets.
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_LOOP: u32 = 108;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+/// Ethertypes of VLAN tags, which Ethernet frames may carry before the
+/// Ethertype of their payload.
+const VLAN_TAGS: [u16; 2] = [0x8100, 0x88a8];
+
+/// A trace rebuilt from the probes and replies of a capture.
+#[derive(Debug, Clone)]
+pub struct ReplayedTrace {
+    pub trace: Trace,
+    /// The configuration of the trace as far as its probes tell it.
+    pub config: Config,
+    pub hops: Vec<Hop>,
+    /// `Completed` if the destination answered, unknown otherwise.
+    pub stop_reason: Option<StopReason>,
+}
+
+/// Rebuild the traces of a pcap or pcapng capture, like the ones tracer
+/// writes or tcpdump records next to another traceroute. Probes are matched
+/// to their replies by the headers replies quote, like during a trace. The
+/// probes of captures by tracer are grouped by the trace and query in their
+/// comments, the probes of other captures by their source, destination and
+/// protocol. A group starts over once its TTLs do. Groups of other captures
+/// that probed a single TTL and weren't answered by Time Exceeded are
+/// regular traffic and skipped.
+pub fn replay(path: &Path) -> Result<Vec<ReplayedTrace>> {
+    let capture =
+        fs::read(path).map_err(|e| Error::Storage(format!("{}: {}", path.display(), e)))?;
+    let frames = read_frames(&capture).map_err(|e| match e {
+        Error::Parse(message) => Error::Parse(format!("{}: {}", path.display(), message)),
+        e => e,
+    })?;
+
+    Ok(rebuild(frames))
+}
+
+/// An IP packet of a capture.
+struct Frame {
+    /// When the packet was captured, in nanoseconds since the epoch.
+    time: u64,
+    /// The packet from its IP header on.
+    data: Vec<u8>,
+    comment: Option<String>,
+}
+
+/// A probe of a capture and the reply it was matched to.
+struct Sent {
+    time: u64,
+    source: IpAddr,
+    destination: IpAddr,
+    ttl: u8,
+    tos: u8,
+    protocol: Protocol,
+    /// Destination port of UDP and TCP probes.
+    port: u16,
+    /// The header fields load balancers hash on besides the addresses,
+    /// which Paris traces keep fixed.
+    flow: u32,
+    packet: Vec<u8>,
+    matcher: Matcher,
+    tag: Option<Tag>,
+    /// The index of the reply and the kind it was classified as.
+    reply: Option<(usize, ReplyKind)>,
+}
+
+/// A reply of a capture.
+struct Received {
+    time: u64,
+    source: IpAddr,
+    destination: IpAddr,
+    ttl: u8,
+    reply: Reply,
+    late: bool,
+}
+
+/// Where tracer placed a packet of its capture, as told by its comment,
+/// like `probe of trace <id> ttl 3 query 2`.
+struct Tag {
+    trace: String,
+    /// The query of probes of classic and Paris traces. Probes of MDA traces
+    /// name their flow instead.
+    query: Option<u8>,
+    mda: bool,
+    late: bool,
+}
+
+/// The probes of a capture that belong to one trace.
+struct Group {
+    source: IpAddr,
+    destination: IpAddr,
+    protocol: Protocol,
+    tagged: bool,
+    mda: bool,
+    first_ttl: u8,
+    last_ttl: u8,
+    probes: Vec<usize>,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum GroupKey {
+    Trace(String),
+    Path(IpAddr, IpAddr, Protocol),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Endian {
+    Little,
+    Big,
+}
+
+impl Endian {
+    fn u16(self, buf: &[u8], at: usize) -> Result<u16> {
+        let bytes = [byte(buf, at)?, byte(buf, at + 1)?];
+        Ok(match self {
+            Endian::Little => u16::from_le_bytes(bytes),
+            Endian::Big => u16::from_be_bytes(bytes),
+        })
+    }
+
+    fn u32(self, buf: &[u8], at: usize) -> Result<u32> {
+        let bytes = [
+            byte(buf, at)?,
+            byte(buf, at + 1)?,
+            byte(buf, at + 2)?,
+            byte(buf, at + 3)?,
+        ];
+        Ok(match self {
+            Endian::Little => u32::from_le_bytes(bytes),
+            Endian::Big => u32::from_be_bytes(bytes),
+        })
+    }
+}
+
+fn byte(buf: &[u8], at: usize) -> Result<u8> {
+    buf.get(at)
+        .copied()
+        .ok_or_else(|| Error::Parse("the capture is truncated".to_string()))
+}
+
+/// Read the IP packets of a pcap or pcapng capture in the order they were
+/// captured. Packets of other protocols are skipped.
+fn read_frames(capture: &[u8]) -> Result<Vec<Frame>> {
+    // The block type of section headers reads the same in both byte orders.
+    let (mut frames, unsupported) = match Endian::Little.u32(capture, 0)? {
+        SECTION_HEADER => read_pcapng(capture)?,
+        _ => read_pcap(capture)?,
+    };
+
+    match unsupported {
+        Some(linktype) if frames.is_empty() => Err(Error::Unsupported(format!(
+            "packets of link type {} can't be replayed",
+            linktype
+        ))),
+        _ => {
+            frames.sort_by_key(|frame| frame.time);
+            Ok(frames)
+        }
+    }
+}
+
+/// Read a pcap capture. Returns its IP packets and its link type if it isn't
+/// supported.
+fn read_pcap(capture: &[u8]) -> Result<(Vec<Frame>, Option<u32>)> {
+    // The magic tells the byte order and whether timestamps are in
+    // microseconds or nanoseconds.
+    let (endian, scale) = match Endian::Little.u32(capture, 0)? {
+        0xa1b2_c3d4 => (Endian::Little, 1_000),
+        0xa1b2_3c4d => (Endian::Little, 1),
+        0xd4c3_b2a1 => (Endian::Big, 1_000),
+        0x4d3c_b2a1 => (Endian::Big, 1),
+        _ => {
+            return Err(Error::Parse(
+                "the file is neither a pcap nor a pcapng capture".to_string(),
+            ))
+        }
+    };
+    let linktype = endian.u32(capture, 20)?;
+
+    let mut frames = vec![];
+    let mut offset = 24;
+    while offset < capture.len() {
+        let seconds = u64::from(endian.u32(capture, offset)?);
+        let fraction = u64::from(endian.u32(capture, offset + 4)?);
+        let len = endian.u32(capture, offset + 8)? as usize;
+        let data = capture
+            .get(offset + 16..offset + 16 + len)
+            .ok_or_else(|| Error::Parse("the capture is truncated".to_string()))?;
+
+        if let Some(ip) = ip_packet(linktype, data) {
+            frames.push(Frame {
+                time: seconds * 1_000_000_000 + fraction * scale,
+                data: ip.to_vec(),
+                comment: None,
+            });
+        }
+        offset += 16 + len;
+    }
+
+    Ok((
+        frames,
+        Some(linktype).filter(|linktype| !is_supported(*linktype)),
+    ))
+}
+
+/// Read a pcapng capture of any number of sections and interfaces. Returns
+/// its IP packets and a link type of its interfaces that isn't supported, if
+/// any.
+fn read_pcapng(capture: &[u8]) -> Result<(Vec<Frame>, Option<u32>)> {
+    let mut endian = Endian::Little;
+    // The link type of every interface of the section and the units of its
+    // timestamps per second.
+    let mut interfaces: Vec<(u32, u64)> = vec![];
+    let mut unsupported = None;
+
+    let mut frames = vec![];
+    let mut offset = 0;
+    while offset < capture.len() {
+        // Sections start with the magic in their byte order, after the block
+        // type and length.
+        if Endian::Little.u32(capture, offset)? == SECTION_HEADER {
+            endian = match Endian::Little.u32(capture, offset + 8)? {
+                0x1a2b_3c4d => Endian::Little,
+                0x4d3c_2b1a => Endian::Big,
+                _ => {
+                    return Err(Error::Parse(format!(
+                        "invalid section header at offset {}",
+                        offset
+                    )))
+                }
+            };
+            interfaces.clear();
+        }
+        let block_type = endian.u32(capture, offset)?;
+        let len = endian.u32(capture, offset + 4)? as usize;
+        if len < 12 || !len.is_multiple_of(4) {
+            return Err(Error::Parse(format!(
+                "invalid block length {} at offset {}",
+                len, offset
+            )));
+        }
+        let body = capture
+            .get(offset + 8..offset + len - 4)
+            .ok_or_else(|| Error::Parse("the capture is truncated".to_string()))?;
+
+        match block_type {
+            INTERFACE_DESCRIPTION => {
+                let linktype = u32::from(endian.u16(body, 0)?);
+                if !is_supported(linktype) {
+                    unsupported = Some(linktype);
+                }
+                // Timestamps are in microseconds unless the interface tells
+                // a power of 10 or, with the high bit set, of 2.
+                let units = options(endian, body.get(8..).unwrap_or_default())
+                    .into_iter()
+                    .find(|(code, _)| *code == IF_TSRESOL)
+                    .and_then(|(_, value)| value.first().copied())
+                    .and_then(|exponent| match exponent & 0x80 {
+                        0 => 10u64.checked_pow(u32::from(exponent)),
+                        _ => 1u64.checked_shl(u32::from(exponent & 0x7f)),
+                    })
+                    .unwrap_or(1_000_000);
+                interfaces.push((linktype, units));
+            }
+            ENHANCED_PACKET => {
+                let interface = endian.u32(body, 0)? as usize;
+                let (linktype, units) = *interfaces.get(interface).ok_or_else(|| {
+                    Error::Parse(format!(
+                        "packet of unknown interface {} at offset {}",
+                        interface, offset
+                    ))
+                })?;
+                let stamp = u64::from(endian.u32(body, 4)?) << 32 | u64::from(endian.u32(body, 8)?);
+                let len = endian.u32(body, 12)? as usize;
+                let data = body
+                    .get(20..20 + len)
+                    .ok_or_else(|| Error::Parse("the capture is truncated".to_string()))?;
+                let options = options(
+                    endian,
+                    body.get(20 + len.div_ceil(4) * 4..).unwrap_or_default(),
+                );
+
+                if let Some(ip) = ip_packet(linktype, data) {
+                    frames.push(Frame {
+                        time: (u128::from(stamp) * 1_000_000_000 / u128::from(units)) as u64,
+                        data: ip.to_vec(),
+                        comment: options
+                            .into_iter()
+                            .find(|(code, _)| *code == OPT_COMMENT)
+                            .map(|(_, value)| String::from_utf8_lossy(value).into_owned()),
+                    });
+                }
+            }
+            _ => {}
+        }
+        offset += len;
+    }
+
+    Ok((frames, unsupported))
+}
+
+/// The options of a pcapng block by their code, up to the end of options or
+/// the first one that is truncated.
+fn options(endian: Endian, mut options: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut found = vec![];
+    while let (Ok(code), Ok(len)) = (endian.u16(options, 0), endian.u16(options, 2)) {
+        let len = usize::from(len);
+        let value = match options.get(4..4 + len) {
+            Some(value) if code != OPT_END => value,
+            _ => break,
+        };
+        found.push((code, value));
+        options = options.get(4 + len.div_ceil(4) * 4..).unwrap_or_default();
+    }
+
+    found
+}
+
+fn is_supported(linktype: u32) -> bool {
+    matches!(
+        linktype,
+        LINKTYPE_NULL
+            | LINKTYPE_ETHERNET
+            | LINKTYPE_LOOP
+            | LINKTYPE_LINUX_SLL
+            | LINKTYPE_IPV4
+            | LINKTYPE_IPV6
+            | LINKTYPE_LINUX_SLL2
+    ) || linktype == u32::from(LINKTYPE_RAW)
+}
+
+/// The IP packet of a captured frame of `linktype`, without the padding of
+/// the link layer. Returns `None` if the frame carries no IP packet.
+fn ip_packet(linktype: u32, data: &[u8]) -> Option<&[u8]> {
+    let ip = match linktype {
+        // The address family in host byte order.
+        LINKTYPE_NULL | LINKTYPE_LOOP => data.get(4..)?,
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            while VLAN_TAGS.contains(&u16::from_be_bytes([
+                *data.get(offset)?,
+                *data.get(offset + 1)?,
+            ])) {
+                offset += 4;
+            }
+            data.get(offset + 2..)?
+        }
+        LINKTYPE_LINUX_SLL => data.get(16..)?,
+        LINKTYPE_LINUX_SLL2 => data.get(20..)?,
+        LINKTYPE_IPV4 | LINKTYPE_IPV6 => data,
+        _ if linktype == u32::from(LINKTYPE_RAW) => data,
+        _ => return None,
+    };
+
+    let len = match ip.first()? >> 4 {
+        4 => usize::from(u16::from_be_bytes([*ip.get(2)?, *ip.get(3)?])),
+        6 => 40 + usize::from(u16::from_be_bytes([*ip.get(4)?, *ip.get(5)?])),
+        _ => return None,
+    };
+
+    // Packets may be cut short by the snapshot length of the capture.
+    Some(&ip[..len.min(ip.len())])
+}
+
+/// Tell where tracer placed a packet by its comment.
+fn tag(comment: &str) -> Option<Tag> {
+    let (what, rest) = comment.split_once(" of trace ")?;
+    let mut words = rest.split_whitespace();
+    let trace = words.next()?.to_string();
+
+    let (mut query, mut mda) = (None, false);
+    while let Some(word) = words.next() {
+        match word {
+            "query" => query = words.next().and_then(|query| query.parse().ok()),
+            "flow" => mda = true,
+            _ => {}
+        }
+    }
+
+    Some(Tag {
+        trace,
+        query,
+        mda,
+        late: what == "late reply",
+    })
+}
+
+/// A probe or a reply in a captured packet.
+enum Parsed {
+    Probe(Sent),
+    Reply(Received),
+}
+
+/// Classify a packet as a probe or a reply. UDP datagrams, Echo Requests and
+/// SYN segments are probes, ICMP errors, Echo Replies, SYN-ACK and RST
+/// segments replies. Returns `None` for any other packet.
+fn parse(frame: Frame) -> Option<Parsed> {
+    let (source, destination, ttl, tos, ip_ident, protocol, offset) = match frame.data.first()? >> 4
+    {
+        4 => {
+            let ip = Ipv4Packet::new(&frame.data)?;
+            // Only first fragments carry the transport header.
+            if ip.get_fragment_offset() != 0 {
+                return None;
+            }
+            (
+                IpAddr::V4(ip.get_source()),
+                IpAddr::V4(ip.get_destination()),
+                ip.get_ttl(),
+                ip.get_dscp() << 2 | ip.get_ecn(),
+                Some(ip.get_identification()),
+                ip.get_next_level_protocol(),
+                usize::from(ip.get_header_length()) * 4,
+            )
+        }
+        _ => {
+            let ip = Ipv6Packet::new(&frame.data)?;
+            (
+                IpAddr::V6(ip.get_source()),
+                IpAddr::V6(ip.get_destination()),
+                ip.get_hop_limit(),
+                ip.get_traffic_class(),
+                None,
+                ip.get_next_header(),
+                Ipv6Packet::minimum_packet_size(),
+            )
+        }
+    };
+    let segment = frame.data.get(offset..)?;
+    let tag = frame.comment.as_deref().and_then(tag);
+
+    let probe = |protocol, source_port, port, ident, seq, tcp_sequence, flow| {
+        let ipv6 = destination.is_ipv6();
+        Parsed::Probe(Sent {
+            time: frame.time,
+            source,
+            destination,
+            ttl,
+            tos,
+            protocol,
+            port,
+            flow,
+            packet: frame.data.clone(),
+            matcher: Matcher {
+                protocol,
+                next_header: next_header(protocol, ipv6),
+                ipv6,
+                source_port,
+                destination_port: port,
+                ident,
+                seq,
+                tcp_sequence,
+                destination,
+                ip_ident,
+            },
+            tag: None,
+            reply: None,
+        })
+    };
+    let reply = |reply| {
+        Parsed::Reply(Received {
+            time: frame.time,
+            source,
+            destination,
+            ttl,
+            reply,
+            late: false,
+        })
+    };
+
+    // UDP probes carry their sequence in the checksum.
+    let parsed = match protocol {
+        IpNextHeaderProtocols::Udp => {
+            let udp = UdpPacket::new(segment)?;
+            let (source_port, port) = (udp.get_source(), udp.get_destination());
+            let flow = u32::from(source_port) << 16 | u32::from(port);
+            probe(
+                Protocol::Udp,
+                source_port,
+                port,
+                0,
+                udp.get_checksum(),
+                0,
+                flow,
+            )
+        }
+        IpNextHeaderProtocols::Icmp | IpNextHeaderProtocols::Icmpv6
+            if is_echo_request(protocol, segment)? =>
+        {
+            // ICMPv6 Echo Requests share their layout with ICMP ones. Paris
+            // traces keep the checksum fixed.
+            let echo = EchoRequestPacket::new(segment)?;
+            let flow = u32::from(echo.get_checksum());
+            let (ident, seq) = (echo.get_identifier(), echo.get_sequence_number());
+            probe(Protocol::Icmp, 0, 0, ident, seq, 0, flow)
+        }
+        IpNextHeaderProtocols::Icmp => reply(Reply::from_icmp(&IcmpPacket::new(segment)?)?),
+        IpNextHeaderProtocols::Icmpv6 => reply(Reply::from_icmpv6(&Icmpv6Packet::new(segment)?)?),
+        IpNextHeaderProtocols::Tcp => {
+            let tcp = TcpPacket::new(segment)?;
+            if tcp.get_flags() & (TcpFlags::SYN | TcpFlags::ACK) == TcpFlags::SYN {
+                let (source_port, port) = (tcp.get_source(), tcp.get_destination());
+                let flow = u32::from(source_port) << 16 | u32::from(port);
+                probe(
+                    Protocol::Tcp,
+                    source_port,
+                    port,
+                    0,
+                    0,
+                    tcp.get_sequence(),
+                    flow,
+                )
+            } else {
+                reply(Reply::from_tcp(&tcp)?)
+            }
+        }
+        _ => return None,
+    };
+
+    Some(match parsed {
+        Parsed::Probe(probe) => Parsed::Probe(Sent { tag, ..probe }),
+        Parsed::Reply(reply) => Parsed::Reply(Received {
+            late: tag.is_some_and(|tag| tag.late),
+            ..reply
+        }),
+    })
+}
+
+/// Test whether an ICMP or ICMPv6 message is an Echo Request.
+fn is_echo_request(protocol: IpNextHeaderProtocol, segment: &[u8]) -> Option<bool> {
+    let kind = *segment.first()?;
+
+    Some(match protocol {
+        IpNextHeaderProtocols::Icmp => kind == IcmpTypes::EchoRequest.0,
+        _ => kind == Icmpv6Types::EchoRequest.0,
+    })
+}
+
+/// Match every reply to the latest probe on its path sent before it that it
+/// answers and that isn't answered yet. `probes` are in the order they were
+/// sent.
+fn match_replies(probes: &mut [Sent], replies: &[Received]) {
+    let mut paths: HashMap<(IpAddr, IpAddr), Vec<usize>> = HashMap::new();
+    for (idx, probe) in probes.iter().enumerate() {
+        paths
+            .entry((probe.source, probe.destination))
+            .or_default()
+            .push(idx);
+    }
+
+    for (idx, received) in replies.iter().enumerate() {
+        // Error messages come from routers along the path and quote the
+        // destination of the probe.
+        let destination = match &received.reply {
+            Reply::Error { destination, .. } => *destination,
+            _ => received.source,
+        };
+        let path = match paths.get(&(received.destination, destination)) {
+            Some(path) => path,
+            None => continue,
+        };
+
+        let sent = path.partition_point(|probe| probes[*probe].time <= received.time);
+        // Replies whose quoted probe was rewritten by a NAT are only matched
+        // on its destination and IP ID if they match no probe otherwise.
+        let unanswered = || {
+            path[..sent]
+                .iter()
+                .rev()
+                .filter(|probe| probes[**probe].reply.is_none())
+        };
+        let matched = unanswered()
+            .find_map(|probe| {
+                probes[*probe]
+                    .matcher
+                    .matches(&received.reply, &received.source)
+                    .map(|kind| (*probe, kind))
+            })
+            .or_else(|| {
+                unanswered().find_map(|probe| {
+                    probes[*probe]
+                        .matcher
+                        .matches_translated(&received.reply, &received.source)
+                        .map(|kind| (*probe, kind))
+                })
+            });
+        if let Some((probe, kind)) = matched {
+            probes[probe].reply = Some((idx, kind));
+        }
+    }
+}
+
+/// Split the probes of a capture into the groups of their traces.
+fn group(probes: &[Sent]) -> Vec<Group> {
+    let mut groups: Vec<Group> = vec![];
+    let mut open: HashMap<GroupKey, usize> = HashMap::new();
+
+    for (idx, probe) in probes.iter().enumerate() {
+        let key = match &probe.tag {
+            Some(tag) => GroupKey::Trace(tag.trace.clone()),
+            None => GroupKey::Path(probe.source, probe.destination, probe.protocol),
+        };
+        // Traces of other tools start over at their first TTL.
+        let current = open.get(&key).copied().filter(|current| {
+            let group = &groups[*current];
+            group.tagged || probe.ttl >= group.last_ttl || probe.ttl > group.first_ttl
+        });
+
+        let current = match current {
+            Some(current) => current,
+            None => {
+                groups.push(Group {
+                    source: probe.source,
+                    destination: probe.destination,
+                    protocol: probe.protocol,
+                    tagged: probe.tag.is_some(),
+                    mda: probe.tag.as_ref().is_some_and(|tag| tag.mda),
+                    first_ttl: probe.ttl,
+                    last_ttl: probe.ttl,
+                    probes: vec![],
+                });
+                open.insert(key, groups.len() - 1);
+                groups.len() - 1
+            }
+        };
+        groups[current].probes.push(idx);
+        groups[current].last_ttl = probe.ttl;
+    }
+
+    groups
+}
+
+/// Rebuild the traces of the packets of a capture.
+fn rebuild(frames: Vec<Frame>) -> Vec<ReplayedTrace> {
+    let (mut probes, mut replies) = (vec![], vec![]);
+    for frame in frames {
+        match parse(frame) {
+            Some(Parsed::Probe(probe)) => probes.push(probe),
+            Some(Parsed::Reply(reply)) => replies.push(reply),
+            None => {}
+        }
+    }
+    match_replies(&mut probes, &replies);
+
+    group(&probes)
+        .into_iter()
+        .filter_map(|group| rebuild_trace(&group, &probes, &replies))
+        .collect()
+}
+
+/// Rebuild the trace of a group of probes. Returns `None` if the group isn't
+/// a trace.
+fn rebuild_trace(group: &Group, probes: &[Sent], replies: &[Received]) -> Option<ReplayedTrace> {
+    let sent = group
+        .probes
+        .iter()
+        .map(|probe| &probes[*probe])
+        .collect::<Vec<_>>();
+    let ttls = sent.iter().map(|probe| probe.ttl).collect::<BTreeSet<_>>();
+    let time_exceeded = sent
+        .iter()
+        .any(|probe| matches!(probe.reply, Some((_, ReplyKind::TimeExceeded))));
+    if !group.tagged && ttls.len() < 2 && !time_exceeded {
+        return None;
+    }
+
+    let first = sent.first()?;
+    let mode = if group.mda {
+        Mode::Mda
+    } else if group.protocol != Protocol::Tcp
+        && sent.len() > 1
+        && sent.iter().all(|probe| probe.flow == first.flow)
+    {
+        Mode::Paris
+    } else {
+        Mode::Classic
+    };
+    let trace = Trace::new(group.source, group.destination, group.protocol, mode);
+
+    // Queries are in the order of tracer's comments, or the order they were
+    // sent in.
+    let mut queries: BTreeMap<u8, Vec<(Option<u8>, TraceQuery)>> = BTreeMap::new();
+    for probe in &sent {
+        let query = probe.tag.as_ref().and_then(|tag| tag.query);
+        queries
+            .entry(probe.ttl)
+            .or_default()
+            .push((query, query_result(probe, replies)));
+    }
+    let tries = queries.values().map(Vec::len).max().unwrap_or(1);
+    let mut hops = queries
+        .into_iter()
+        .map(|(ttl, mut queries)| {
+            queries.sort_by_key(|(query, _)| *query);
+            Hop {
+                trace: trace.id,
+                ttl,
+                source: group.source,
+                destination: group.destination,
+                queries: queries.into_iter().map(|(_, result)| result).collect(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // Probes beyond the destination, like the ones of a window, aren't hops
+    // of the trace.
+    let stop_reason = match hops
+        .iter()
+        .position(|hop| hop.is_destination(group.destination))
+    {
+        Some(idx) => {
+            hops.truncate(idx + 1);
+            Some(StopReason::Completed)
+        }
+        None => None,
+    };
+
+    let mut config = Config::default()
+        .with_protocol(group.protocol)
+        .with_mode(mode)
+        .with_first_ttl(*ttls.iter().next()?)
+        .with_max_hops(*ttls.iter().next_back()?)
+        .with_tries(tries.min(usize::from(u8::MAX)) as u8)
+        .with_size(first.packet.len().min(usize::from(u16::MAX)) as u16)
+        .with_tos(first.tos);
+    if group.protocol != Protocol::Icmp {
+        config = config.with_port(first.port);
+    }
+
+    Some(ReplayedTrace {
+        trace,
+        config,
+        hops,
+        stop_reason,
+    })
+}
+
+/// The result of the query of a probe.
+fn query_result(probe: &Sent, replies: &[Received]) -> TraceQuery {
+    let (received, kind) = match probe.reply {
+        Some((idx, kind)) => (&replies[idx], kind),
+        None => return TraceQuery::Timeout,
+    };
+
+    let response = Response {
+        rtt: Duration::from_nanos(received.time.saturating_sub(probe.time)),
+        addr: received.source,
+        reply: kind,
+        mpls: received.reply.mpls().to_vec(),
+        reply_ttl: Some(received.ttl),
+        quoted_ttl: received.reply.quoted_ttl(),
+        modifications: inspect::modifications(&probe.packet, &received.reply),
+    };
+
+    if received.late {
+        TraceQuery::Late(response)
+    } else {
+        TraceQuery::Success(response)
+    }
+}
@@ -0,0 +1,404 @@
+use pnet::packet::{
+    icmp::{
+        destination_unreachable::IcmpCodes as DestinationUnreachableCodes,
+        echo_reply::EchoReplyPacket, echo_request::EchoRequestPacket, IcmpPacket, IcmpTypes,
+    },
+    icmpv6::{Icmpv6Packet, Icmpv6Types},
+    ip::IpNextHeaderProtocol,
+    ipv4::Ipv4Packet,
+    ipv6::Ipv6Packet,
+    tcp::{TcpFlags, TcpPacket},
+    util, Packet,
+};
+use std::net::IpAddr;
+
+use crate::{MplsLabel, Protocol, ReplyKind};
+
+/// An ICMP or ICMPv6 message, or a TCP segment, that may answer a probe.
+#[derive(Debug, Clone)]
+pub(crate) enum Reply {
+    /// An Echo Reply with its identifier, sequence number and the data it
+    /// echoes.
+    Echo { ident: u16, seq: u16, data: Vec<u8> },
+    /// An error message about a probe, with the protocol, destination, TTL
+    /// and IP ID of the probe as it arrived, its IP header and the start of
+    /// its transport header, and the MPLS label stack of the router. IPv6
+    /// probes have no IP ID.
+    Error {
+        kind: ReplyKind,
+        protocol: IpNextHeaderProtocol,
+        destination: IpAddr,
+        ttl: u8,
+        ip_ident: Option<u16>,
+        header: Vec<u8>,
+        quoted: Vec<u8>,
+        mpls: Vec<MplsLabel>,
+    },
+    /// A SYN-ACK or RST segment with the ports and acknowledgement number
+    /// that tie it to a SYN probe.
+    Segment {
+        kind: ReplyKind,
+        source: u16,
+        destination: u16,
+        acknowledgement: u32,
+    },
+}
+
+impl Reply {
+    /// Parse an ICMP message.
+    pub(crate) fn from_icmp(packet: &IcmpPacket) -> Option<Self> {
+        let kind = match packet.get_icmp_type() {
+            IcmpTypes::EchoReply => {
+                let echo = EchoReplyPacket::new(packet.packet())?;
+                return Some(Reply::Echo {
+                    ident: echo.get_identifier(),
+                    seq: echo.get_sequence_number(),
+                    data: echo.payload().to_vec(),
+                });
+            }
+            IcmpTypes::TimeExceeded => ReplyKind::TimeExceeded,
+            // Fragmentation Needed carries the next-hop MTU in the last two of
+            // the four bytes before the quoted packet (RFC 1191).
+            IcmpTypes::DestinationUnreachable
+                if packet.get_icmp_code()
+                    == DestinationUnreachableCodes::FragmentationRequiredAndDFFlagSet =>
+            {
+                let mtu = packet.payload().get(2..4)?;
+                ReplyKind::PacketTooBig {
+                    mtu: u16::from_be_bytes([mtu[0], mtu[1]]),
+                }
+            }
+            IcmpTypes::DestinationUnreachable => ReplyKind::Unreachable,
+            _ => return None,
+        };
+
+        // Error messages quote the IP header and at least the first eight
+        // bytes of the probe after four bytes, the second of which is the
+        // length of the quote in 32-bit words if extensions follow it.
+        let length = *packet.payload().get(1)? as usize * 4;
+        let (body, mpls) = extensions(packet.payload().get(4..)?, length);
+        let quoted = Ipv4Packet::new(body)?;
+        let offset = quoted.get_header_length() as usize * 4;
+
+        Self::error(
+            kind,
+            quoted.get_next_level_protocol(),
+            IpAddr::V4(quoted.get_destination()),
+            quoted.get_ttl(),
+            body,
+            offset,
+            mpls,
+        )
+    }
+
+    /// Parse an ICMPv6 message.
+    pub(crate) fn from_icmpv6(packet: &Icmpv6Packet) -> Option<Self> {
+        let kind = match packet.get_icmpv6_type() {
+            // ICMPv6 Echo Replies share their layout with ICMP ones.
+            Icmpv6Types::EchoReply => {
+                let echo = EchoReplyPacket::new(packet.packet())?;
+                return Some(Reply::Echo {
+                    ident: echo.get_identifier(),
+                    seq: echo.get_sequence_number(),
+                    data: echo.payload().to_vec(),
+                });
+            }
+            Icmpv6Types::TimeExceeded => ReplyKind::TimeExceeded,
+            Icmpv6Types::DestinationUnreachable => ReplyKind::Unreachable,
+            // Packet Too Big carries the MTU in the four bytes before the
+            // quoted packet.
+            Icmpv6Types::PacketTooBig => {
+                let mtu = packet.payload().get(..4)?;
+                let mtu = u32::from_be_bytes([mtu[0], mtu[1], mtu[2], mtu[3]]);
+                ReplyKind::PacketTooBig {
+                    mtu: mtu.min(u32::from(u16::MAX)) as u16,
+                }
+            }
+            _ => return None,
+        };
+
+        // The first byte after the header is the length of the quote in
+        // 64-bit words if extensions follow it, except in Packet Too Big
+        // messages, which carry no extensions.
+        let length = match kind {
+            ReplyKind::PacketTooBig { .. } => 0,
+            _ => *packet.payload().first()? as usize * 8,
+        };
+        let (body, mpls) = extensions(packet.payload().get(4..)?, length);
+        let quoted = Ipv6Packet::new(body)?;
+
+        Self::error(
+            kind,
+            quoted.get_next_header(),
+            IpAddr::V6(quoted.get_destination()),
+            quoted.get_hop_limit(),
+            body,
+            Ipv6Packet::minimum_packet_size(),
+            mpls,
+        )
+    }
+
+    /// Parse a TCP segment. Only segments that can answer a SYN probe are
+    /// returned.
+    pub(crate) fn from_tcp(segment: &TcpPacket) -> Option<Self> {
+        let flags = segment.get_flags();
+        let kind = if flags & TcpFlags::RST != 0 {
+            ReplyKind::Reset
+        } else if flags & (TcpFlags::SYN | TcpFlags::ACK) == TcpFlags::SYN | TcpFlags::ACK {
+            ReplyKind::SynAck
+        } else {
+            return None;
+        };
+
+        Some(Reply::Segment {
+            kind,
+            source: segment.get_source(),
+            destination: segment.get_destination(),
+            acknowledgement: segment.get_acknowledgement(),
+        })
+    }
+
+    fn error(
+        kind: ReplyKind,
+        protocol: IpNextHeaderProtocol,
+        destination: IpAddr,
+        ttl: u8,
+        body: &[u8],
+        offset: usize,
+        mpls: Vec<MplsLabel>,
+    ) -> Option<Self> {
+        // The body quotes the IP header of the probe up to the offset and the
+        // start of its transport header after it.
+        let (header, quoted) = (body.get(..offset)?, body.get(offset..)?);
+        if quoted.len() < 8 {
+            return None;
+        }
+        // Only IPv4 headers have an IP ID.
+        let ip_ident = match body[0] >> 4 {
+            4 => Some(u16::from_be_bytes([*header.get(4)?, *header.get(5)?])),
+            _ => None,
+        };
+
+        Some(Reply::Error {
+            kind,
+            protocol,
+            destination,
+            ttl,
+            ip_ident,
+            header: header.to_vec(),
+            quoted: quoted.to_vec(),
+            mpls,
+        })
+    }
+
+    /// The kind of this reply.
+    pub(crate) fn kind(&self) -> ReplyKind {
+        match self {
+            Reply::Echo { .. } => ReplyKind::EchoReply,
+            Reply::Error { kind, .. } | Reply::Segment { kind, .. } => *kind,
+        }
+    }
+
+    /// The TTL of the probe as quoted in this reply, if it is an error
+    /// message.
+    pub(crate) fn quoted_ttl(&self) -> Option<u8> {
+        match self {
+            Reply::Error { ttl, .. } => Some(*ttl),
+            _ => None,
+        }
+    }
+
+    /// The MPLS label stack quoted in this reply, top entry first.
+    pub(crate) fn mpls(&self) -> &[MplsLabel] {
+        match self {
+            Reply::Error { mpls, .. } => mpls,
+            _ => &[],
+        }
+    }
+}
+
+/// Split the ICMP extension structure (RFC 4884) off the body of an error
+/// message and return the quote before it and the MPLS label stack it
+/// carries (RFC 4950). `length` is the length of the quote as announced by
+/// the message, 0 if it announced none.
+fn extensions(body: &[u8], length: usize) -> (&[u8], Vec<MplsLabel>) {
+    // Routers that predate RFC 4884 announce no length and append their
+    // extensions to a quote of 128 bytes.
+    let offset = if length > 0 {
+        length
+    } else {
+        COMPAT_QUOTE_LENGTH
+    };
+
+    match body.get(offset..).and_then(mpls_labels) {
+        Some(mpls) => (&body[..offset], mpls),
+        None => (body, vec![]),
+    }
+}
+
+/// The length of the quote before extensions of routers that don't announce
+/// it.
+const COMPAT_QUOTE_LENGTH: usize = 128;
+
+/// Parse an ICMP extension structure and return the label stack of its MPLS
+/// Label Stack object, if any. Returns `None` if `extensions` isn't a valid
+/// extension structure.
+fn mpls_labels(extensions: &[u8]) -> Option<Vec<MplsLabel>> {
+    // The header holds the version in its high nibble and a checksum over
+    // the whole structure.
+    let header = extensions.get(..4)?;
+    let checksum = u16::from_be_bytes([header[2], header[3]]);
+    if header[0] >> 4 != 2 || (checksum != 0 && util::checksum(extensions, 1) != checksum) {
+        return None;
+    }
+
+    let mut mpls = vec![];
+    let mut objects = &extensions[4..];
+    while objects.len() >= 4 {
+        let length = u16::from_be_bytes([objects[0], objects[1]]) as usize;
+        if length < 4 || length > objects.len() {
+            return None;
+        }
+        let (class, c_type) = (objects[2], objects[3]);
+
+        // Class 1, C-Type 1 is the incoming MPLS label stack.
+        if class == 1 && c_type == 1 {
+            mpls.extend(objects[4..length].chunks_exact(4).map(|entry| {
+                let entry = u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]);
+                MplsLabel {
+                    label: entry >> 12,
+                    tc: (entry >> 9 & 0x7) as u8,
+                    bottom: entry >> 8 & 0x1 == 1,
+                    ttl: entry as u8,
+                }
+            }));
+        }
+        objects = &objects[length..];
+    }
+
+    Some(mpls)
+}
+
+/// The header fields of a sent probe that its replies echo back.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Matcher {
+    pub(crate) protocol: Protocol,
+    /// The IP protocol number of the probe.
+    pub(crate) next_header: IpNextHeaderProtocol,
+    pub(crate) ipv6: bool,
+    pub(crate) source_port: u16,
+    pub(crate) destination_port: u16,
+    /// ICMP identifier of Echo Requests.
+    pub(crate) ident: u16,
+    /// Sequence number of the probe within its trace.
+    pub(crate) seq: u16,
+    /// Sequence number of TCP probes.
+    pub(crate) tcp_sequence: u32,
+    pub(crate) destination: IpAddr,
+    /// IP ID of IPv4 probes.
+    pub(crate) ip_ident: Option<u16>,
+}
+
+impl Matcher {
+    /// Classify a reply from `addr` as the reply to this probe. Returns `None`
+    /// if it doesn't answer this probe.
+    pub(crate) fn matches(&self, reply: &Reply, addr: &IpAddr) -> Option<ReplyKind> {
+        if addr.is_ipv6() != self.ipv6 {
+            return None;
+        }
+
+        let quoted = match (self.protocol, reply) {
+            (Protocol::Icmp, Reply::Echo { ident, seq, .. }) => {
+                return if *ident == self.ident && *seq == self.seq {
+                    Some(ReplyKind::EchoReply)
+                } else {
+                    None
+                };
+            }
+            // Both a SYN-ACK and a RST acknowledge the probe's sequence number.
+            (
+                Protocol::Tcp,
+                Reply::Segment {
+                    source,
+                    destination,
+                    acknowledgement,
+                    ..
+                },
+            ) => {
+                return if *source == self.destination_port
+                    && *destination == self.source_port
+                    && *acknowledgement == self.tcp_sequence.wrapping_add(1)
+                {
+                    Some(reply.kind())
+                } else {
+                    None
+                };
+            }
+            (
+                _,
+                Reply::Error {
+                    protocol, quoted, ..
+                },
+            ) if *protocol == self.next_header => quoted,
+            _ => return None,
+        };
+
+        let matches = match self.protocol {
+            Protocol::Udp => {
+                let source = u16::from_be_bytes([quoted[0], quoted[1]]);
+                let destination = u16::from_be_bytes([quoted[2], quoted[3]]);
+                let checksum = u16::from_be_bytes([quoted[6], quoted[7]]);
+
+                source == self.source_port
+                    && destination == self.destination_port
+                    && checksum == self.seq
+            }
+            Protocol::Icmp => {
+                let echo = EchoRequestPacket::new(quoted)?;
+
+                echo.get_identifier() == self.ident && echo.get_sequence_number() == self.seq
+            }
+            Protocol::Tcp => {
+                // Only the ports and the sequence number are guaranteed to be
+                // quoted, which is too short for a `TcpPacket`.
+                let source = u16::from_be_bytes([quoted[0], quoted[1]]);
+                let destination = u16::from_be_bytes([quoted[2], quoted[3]]);
+                let sequence = u32::from_be_bytes([quoted[4], quoted[5], quoted[6], quoted[7]]);
+
+                source == self.source_port
+                    && destination == self.destination_port
+                    && sequence == self.tcp_sequence
+            }
+        };
+
+        if matches {
+            Some(reply.kind())
+        } else {
+            None
+        }
+    }
+
+    /// Classify an error message from `addr` that quotes this probe with
+    /// other ports, checksums, identifiers or sequence numbers than it was
+    /// sent with, like the ones a NAT rewrites. Only the destination and the
+    /// IP ID of the quoted probe are compared, so IPv6 probes never match
+    /// this way. Replies are only matched this way if they match no probe
+    /// with `matches`.
+    pub(crate) fn matches_translated(&self, reply: &Reply, addr: &IpAddr) -> Option<ReplyKind> {
+        match reply {
+            Reply::Error {
+                protocol,
+                destination,
+                ip_ident: Some(ip_ident),
+                ..
+            } if addr.is_ipv6() == self.ipv6
+                && *protocol == self.next_header
+                && *destination == self.destination
+                && self.ip_ident == Some(*ip_ident) =>
+            {
+                Some(reply.kind())
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,517 @@
+use pnet::{
+    packet::{icmp::echo_request::EchoRequestPacket, ip::IpNextHeaderProtocol},
+    transport::{transport_channel, TransportChannelType, TransportSender},
+};
+use rand::random;
+use std::{
+    collections::{HashMap, VecDeque},
+    net::{IpAddr, Ipv4Addr},
+    ops::Range,
+    sync::{mpsc, Arc},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    datagram::can_open_raw_sockets,
+    inspect,
+    pacer::Pacer,
+    packet::{next_header, PacketBuilder},
+    receiver::{Received, Receiver},
+    reply::Reply,
+    traceroute, Error, Hop, Mode, Protocol, ReplyKind, Response, Result, Trace, TraceQuery,
+};
+
+/// Stamps count the milliseconds since the start of a scan modulo this value,
+/// plus one because a UDP checksum of zero means no checksum.
+const STAMP_MODULUS: u64 = 0xffff;
+
+/// Targets are probed in blocks of this many targets.
+const BLOCK_SIZE: usize = 1024;
+
+/// Stateless scan configurations
+#[derive(Debug)]
+pub struct ScanConfig {
+    protocol: Protocol,
+    port: u16,
+    max_hops: u8,
+    rate: u32,
+    timeout: Duration,
+    interface: Option<String>,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        ScanConfig {
+            protocol: Protocol::Udp,
+            port: 33434,
+            max_hops: 30,
+            rate: 1000,
+            timeout: Duration::from_secs(5),
+            interface: None,
+        }
+    }
+}
+
+impl ScanConfig {
+    /// Builder: Protocol used to send probes, either UDP or ICMP
+    pub fn with_protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Builder: Destination port of UDP probes
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Builder: Maximum number of hops
+    pub fn with_max_hops(mut self, max_hops: u8) -> Self {
+        self.max_hops = max_hops;
+        self
+    }
+
+    /// Builder: Probes sent per second, 0 sends as fast as possible. Unused
+    /// if the scanner is given a pacer
+    pub fn with_rate(mut self, rate: u32) -> Self {
+        self.rate = rate;
+        self
+    }
+
+    /// Builder: Time to wait for replies after the last probe was sent
+    pub fn with_timeout(mut self, timeout: u64) -> Self {
+        self.timeout = Duration::from_millis(timeout);
+        self
+    }
+
+    /// Builder: Network interface probes are sent from
+    pub fn with_interface(mut self, interface: &str) -> Self {
+        self.interface = Some(interface.to_string());
+        self
+    }
+}
+
+/// The state a reply carries back from its probe.
+#[derive(Debug, Clone, Copy)]
+struct Decoded {
+    target: Ipv4Addr,
+    ttl: u8,
+    stamp: u16,
+    reply: ReplyKind,
+}
+
+/// The header fields all probes of a scan share.
+#[derive(Debug, Clone, Copy)]
+struct Instance {
+    protocol: Protocol,
+    next_header: IpNextHeaderProtocol,
+    source_port: u16,
+    port: u16,
+    ident: u16,
+}
+
+impl Instance {
+    /// Rebuild the target, TTL and stamp of the probe a reply answers from
+    /// the headers it quotes. Returns `None` for replies to other probes.
+    fn decode(&self, reply: &Reply, addr: &IpAddr) -> Option<Decoded> {
+        match reply {
+            Reply::Error {
+                kind,
+                protocol,
+                destination: IpAddr::V4(target),
+                ip_ident: Some(ip_ident),
+                quoted,
+                ..
+            } if *protocol == self.next_header && ip_ident & 0xff00 == self.ident & 0xff00 => {
+                let stamp = match self.protocol {
+                    Protocol::Icmp => {
+                        let echo = EchoRequestPacket::new(quoted)?;
+                        if echo.get_identifier() != self.ident {
+                            return None;
+                        }
+                        echo.get_sequence_number()
+                    }
+                    _ => {
+                        let source = u16::from_be_bytes([quoted[0], quoted[1]]);
+                        let destination = u16::from_be_bytes([quoted[2], quoted[3]]);
+                        if source != self.source_port || destination != self.port {
+                            return None;
+                        }
+                        u16::from_be_bytes([quoted[6], quoted[7]])
+                    }
+                };
+
+                Some(Decoded {
+                    target: *target,
+                    ttl: *ip_ident as u8,
+                    stamp,
+                    reply: *kind,
+                })
+            }
+            // Targets echo the TTL that Echo Requests carry in their data.
+            Reply::Echo { ident, seq, data }
+                if self.protocol == Protocol::Icmp && *ident == self.ident =>
+            {
+                let target = match addr {
+                    IpAddr::V4(target) => *target,
+                    IpAddr::V6(_) => return None,
+                };
+
+                Some(Decoded {
+                    target,
+                    ttl: *data.get(2)?,
+                    stamp: *seq,
+                    reply: ReplyKind::EchoReply,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A Yarrp-style stateless scanner. It probes every TTL of a list of targets
+/// in random order at a fixed rate. The probes carry their target, TTL and
+/// send time in their headers, so that each reply can be attributed from the
+/// headers it quotes without keeping state per probe. Only IPv4 is supported,
+/// because IPv6 headers have no IP ID to carry the TTL.
+pub struct Scanner {
+    source: Ipv4Addr,
+    config: ScanConfig,
+    packet_builder: PacketBuilder,
+    tx: TransportSender,
+    receiver: Arc<Receiver>,
+    pacer: Arc<Pacer>,
+}
+
+impl Scanner {
+    /// Creates new instance of a scanner
+    pub fn new(source: IpAddr, config: ScanConfig) -> Result<Self> {
+        if !can_open_raw_sockets() {
+            return Err(Error::Permission(
+                "stateless scans need raw sockets, run as root or grant CAP_NET_RAW".to_string(),
+            ));
+        }
+
+        Self::with_receiver(source, config, Arc::new(Receiver::new()?))
+    }
+
+    /// Creates new instance of a scanner that receives replies on a receiver
+    /// shared with traces.
+    pub fn with_receiver(
+        source: IpAddr,
+        config: ScanConfig,
+        receiver: Arc<Receiver>,
+    ) -> Result<Self> {
+        let source = match source {
+            IpAddr::V4(source) => source,
+            IpAddr::V6(_) => {
+                return Err(Error::Unsupported(
+                    "stateless scans only support IPv4".to_string(),
+                ))
+            }
+        };
+        if config.protocol == Protocol::Tcp {
+            return Err(Error::Unsupported(
+                "stateless scans only support udp and icmp probes".to_string(),
+            ));
+        }
+
+        let packet_builder = PacketBuilder::new(
+            IpAddr::V4(source),
+            IpAddr::V4(source),
+            config.protocol,
+            Mode::Yarrp,
+        );
+        let (tx, _) = transport_channel(
+            4096,
+            TransportChannelType::Layer3(next_header(config.protocol, false)),
+        )
+        .map_err(|e| Error::socket("layer3: unable to create channel", e))?;
+        if let Some(interface) = &config.interface {
+            traceroute::bind_device(tx.socket.fd, interface).map_err(|e| {
+                Error::socket(&format!("unable to bind channel to {}", interface), e)
+            })?;
+        }
+
+        let pacer = Arc::new(Pacer::new(config.rate));
+
+        Ok(Scanner {
+            source,
+            config,
+            packet_builder,
+            tx,
+            receiver,
+            pacer,
+        })
+    }
+
+    /// Paces the probes of this scanner with a pacer shared with other scans
+    /// and traces instead of the rate of its configuration.
+    pub fn with_pacer(mut self, pacer: Arc<Pacer>) -> Self {
+        self.pacer = pacer;
+        self
+    }
+
+    /// Probe every TTL up to the maximum number of hops of every target once
+    /// and hand the trace and hops of each target to `finished`. Targets are
+    /// probed in blocks, and the traces of a block are handed over once the
+    /// replies to its last probes timed out, so that the results of the whole
+    /// scan aren't kept. Probing stops early once the probe budget of the
+    /// pacer is used up.
+    pub fn scan<F>(&mut self, targets: &[Ipv4Addr], mut finished: F) -> Result<()>
+    where
+        F: FnMut(Trace, Vec<Hop>) -> Result<()>,
+    {
+        let ttls = u64::from(self.config.max_hops);
+        let index: HashMap<Ipv4Addr, usize> = targets
+            .iter()
+            .enumerate()
+            .map(|(idx, target)| (*target, idx))
+            .collect();
+        let mut results = Results::default();
+
+        let instance = Instance {
+            protocol: self.config.protocol,
+            next_header: next_header(self.config.protocol, false),
+            source_port: self.packet_builder.source_port(0),
+            port: self.config.port,
+            ident: self.packet_builder.ident(),
+        };
+        let (respond_to, replies) = mpsc::channel();
+        self.receiver.listen(
+            move |reply, addr| instance.decode(reply, addr).is_some(),
+            respond_to,
+        );
+
+        let start = Instant::now();
+
+        'blocks: for offset in (0..targets.len()).step_by(BLOCK_SIZE) {
+            let block = offset..targets.len().min(offset + BLOCK_SIZE);
+            let total = block.len() as u64 * ttls;
+            let permutation = Permutation::new(total);
+
+            for i in 0..total {
+                let probe = permutation.get(i);
+                let target = targets[offset + (probe / ttls) as usize];
+                let ttl = (probe % ttls) as u8 + 1;
+                if !self.pacer.acquire(&IpAddr::V4(target)) {
+                    results.wait(block, self.config.timeout);
+                    break 'blocks;
+                }
+
+                let elapsed = start.elapsed();
+                let packet = self
+                    .packet_builder
+                    .with_destination(IpAddr::V4(target))
+                    .build_stateless_packet(ttl, self.config.port, stamp(elapsed));
+
+                // Probes that can't be sent, like ones to unroutable targets,
+                // are left unanswered.
+                let _ = self.tx.send_to(packet, IpAddr::V4(target));
+
+                while let Ok(received) = replies.try_recv() {
+                    self.record(&instance, start, &index, &mut results, received);
+                }
+                self.store(targets, &mut results, &mut finished)?;
+            }
+
+            results.wait(block, self.config.timeout);
+        }
+
+        // Collect the replies to the last probes of each block.
+        while let Some(deadline) = results.pending.front().map(|(_, deadline)| *deadline) {
+            while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+                match replies.recv_timeout(timeout) {
+                    Ok(received) => self.record(&instance, start, &index, &mut results, received),
+                    Err(_) => break,
+                }
+            }
+            self.store(targets, &mut results, &mut finished)?;
+        }
+
+        Ok(())
+    }
+
+    /// Record the first reply of a (target, TTL) pair. Replies of targets
+    /// whose traces were stored already are dropped.
+    fn record(
+        &self,
+        instance: &Instance,
+        start: Instant,
+        index: &HashMap<Ipv4Addr, usize>,
+        results: &mut Results,
+        received: Received,
+    ) {
+        let decoded = match instance.decode(&received.reply, &received.addr) {
+            Some(decoded) if decoded.ttl >= 1 && decoded.ttl <= self.config.max_hops => decoded,
+            _ => return,
+        };
+        let idx = match index.get(&decoded.target) {
+            Some(idx) if *idx >= results.stored => *idx,
+            _ => return,
+        };
+
+        let elapsed = received.received.saturating_duration_since(start);
+
+        // Stateless probes can be rebuilt from the state their replies carry.
+        let packet_builder = self
+            .packet_builder
+            .with_destination(IpAddr::V4(decoded.target));
+        let probe =
+            packet_builder.build_stateless_packet(decoded.ttl, self.config.port, decoded.stamp);
+        let probe = packet_builder.sent_packet(decoded.ttl, &probe);
+
+        results
+            .answers
+            .entry(idx)
+            .or_default()
+            .entry(decoded.ttl)
+            .or_insert(TraceQuery::Success(Response {
+                rtt: rtt(decoded.stamp, elapsed),
+                addr: received.addr,
+                reply: decoded.reply,
+                mpls: received.reply.mpls().to_vec(),
+                reply_ttl: received.ttl,
+                quoted_ttl: received.reply.quoted_ttl(),
+                modifications: inspect::modifications(&probe, &received.reply),
+            }));
+    }
+
+    /// Hand the traces of the blocks whose replies timed out to `finished`.
+    fn store<F>(&self, targets: &[Ipv4Addr], results: &mut Results, finished: &mut F) -> Result<()>
+    where
+        F: FnMut(Trace, Vec<Hop>) -> Result<()>,
+    {
+        let now = Instant::now();
+        while let Some((block, deadline)) = results.pending.pop_front() {
+            if deadline > now {
+                results.pending.push_front((block, deadline));
+                break;
+            }
+
+            results.stored = block.end;
+            for idx in block {
+                let answers = results.answers.remove(&idx).unwrap_or_default();
+                let (trace, hops) = self.assemble(targets[idx], answers);
+                finished(trace, hops)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Build the trace of a target. Its hops end at the first TTL the target
+    /// answered, or at the last TTL that was answered at all.
+    fn assemble(&self, target: Ipv4Addr, answers: HashMap<u8, TraceQuery>) -> (Trace, Vec<Hop>) {
+        let source = IpAddr::V4(self.source);
+        let destination = IpAddr::V4(target);
+        let trace = Trace::new(source, destination, self.config.protocol, Mode::Yarrp);
+
+        let last = answers
+            .iter()
+            .filter(|(_, query)| match query {
+                TraceQuery::Success(response) => {
+                    response.addr == destination || response.reply.is_final()
+                }
+                _ => false,
+            })
+            .map(|(ttl, _)| *ttl)
+            .min()
+            .or_else(|| answers.keys().max().copied())
+            .unwrap_or(0);
+
+        let hops = (1..=last)
+            .map(|ttl| Hop {
+                trace: trace.id,
+                ttl,
+                source,
+                destination,
+                queries: vec![answers.get(&ttl).cloned().unwrap_or(TraceQuery::Timeout)],
+            })
+            .collect();
+
+        (trace, hops)
+    }
+}
+
+/// The replies of a scan to the targets whose traces weren't stored yet.
+#[derive(Default)]
+struct Results {
+    /// The first reply to each TTL of a target, by the index of the target.
+    answers: HashMap<usize, HashMap<u8, TraceQuery>>,
+    /// The blocks of targets whose probes were all sent, in order, with the
+    /// time until which replies to them are collected.
+    pending: VecDeque<(Range<usize>, Instant)>,
+    /// The index of the first target whose trace wasn't stored yet.
+    stored: usize,
+}
+
+impl Results {
+    /// Collect replies to a block of targets for `timeout` from now.
+    fn wait(&mut self, block: Range<usize>, timeout: Duration) {
+        self.pending.push_back((block, Instant::now() + timeout));
+    }
+}
+
+/// The stamp of a probe sent `elapsed` after the start of a scan.
+fn stamp(elapsed: Duration) -> u16 {
+    (elapsed.as_millis() as u64 % STAMP_MODULUS) as u16 + 1
+}
+
+/// The round-trip time of a probe with `stamp` whose reply was received
+/// `elapsed` after the start of a scan.
+fn rtt(stamp: u16, elapsed: Duration) -> Duration {
+    let sent = u64::from(stamp) - 1;
+    let received = elapsed.as_millis() as u64 % STAMP_MODULUS;
+
+    Duration::from_millis((received + STAMP_MODULUS - sent) % STAMP_MODULUS)
+}
+
+/// A random permutation of `0..len` that is computed on the fly, so that the
+/// random order of all (target, TTL) pairs doesn't have to be stored. It
+/// encrypts indices with a small Feistel network over the smallest power of
+/// two domain that holds `len` and walks the cycle until the result is below
+/// `len`.
+struct Permutation {
+    len: u64,
+    half_bits: u32,
+    keys: [u64; 4],
+}
+
+impl Permutation {
+    fn new(len: u64) -> Self {
+        let bits = 64 - len.saturating_sub(1).leading_zeros();
+
+        Permutation {
+            len,
+            half_bits: bits.div_ceil(2).max(1),
+            keys: random(),
+        }
+    }
+
+    /// The element at position `idx` of the permutation.
+    fn get(&self, idx: u64) -> u64 {
+        let mut value = idx;
+        loop {
+            value = self.encrypt(value);
+            if value < self.len {
+                return value;
+            }
+        }
+    }
+
+    fn encrypt(&self, value: u64) -> u64 {
+        let mask = (1 << self.half_bits) - 1;
+        let (mut left, mut right) = (value >> self.half_bits, value & mask);
+
+        for key in &self.keys {
+            let mut round = (right ^ key).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+            round ^= round >> 29;
+            let next = left ^ (round & mask);
+            left = right;
+            right = next;
+        }
+
+        (left << self.half_bits) | right
+    }
+}
@@ -0,0 +1,326 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    f64::consts::PI,
+    fs, io,
+    net::IpAddr,
+    path::Path,
+    sync::mpsc,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    prober::{Probe, Prober},
+    receiver::Answer,
+    Error, Protocol, ReplyKind, Result,
+};
+
+/// Initial TTL of the replies of routers and of the destination, unless the
+/// topology sets another one.
+const ROUTER_TTL: u8 = 255;
+const DESTINATION_TTL: u8 = 64;
+
+/// A topology of routers between this host and any destination, as read from
+/// a JSON file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Topology {
+    /// Seed of the random latencies and losses, so that runs are repeatable.
+    #[serde(default)]
+    seed: u64,
+    /// The hops before the destination, by TTL starting at 1.
+    hops: Vec<SimulatedHop>,
+    #[serde(default)]
+    destination: Node,
+}
+
+/// The routers that answer the probes of a TTL.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SimulatedHop {
+    /// Probes are spread over the routers of a hop by their flow, like by
+    /// per-flow load balancers.
+    #[serde(default)]
+    routers: Vec<Router>,
+    /// Silent hops answer no probe.
+    #[serde(default)]
+    silent: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Router {
+    address: IpAddr,
+    #[serde(flatten)]
+    node: Node,
+}
+
+/// How a router or the destination answers probes.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+struct Node {
+    #[serde(default)]
+    latency: Latency,
+    /// Probability that a probe or its reply is lost.
+    #[serde(default)]
+    loss: f64,
+    /// Replies sent per second at most, like ICMP rate limits.
+    #[serde(default)]
+    rate_limit: Option<u32>,
+    #[serde(default)]
+    initial_ttl: Option<u8>,
+}
+
+/// The distribution of round-trip times in milliseconds.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(untagged)]
+enum Latency {
+    /// Uniformly distributed between `min` and `max`.
+    Uniform { min: f64, max: f64 },
+    /// Normally distributed, but never negative.
+    Normal {
+        mean: f64,
+        #[serde(default)]
+        stddev: f64,
+    },
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Latency::Normal {
+            mean: 0.0,
+            stddev: 0.0,
+        }
+    }
+}
+
+impl Latency {
+    fn sample(&self, rng: &mut StdRng) -> Duration {
+        let ms = match *self {
+            Latency::Uniform { min, max } if max > min => rng.gen_range(min..max),
+            Latency::Uniform { min, .. } => min,
+            // Box-Muller transform of two uniform samples.
+            Latency::Normal { mean, stddev } => {
+                let u1 = rng.gen::<f64>().max(f64::MIN_POSITIVE);
+                let u2 = rng.gen::<f64>();
+                mean + stddev * (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+            }
+        };
+
+        Duration::from_secs_f64(ms.max(0.0) / 1000.0)
+    }
+}
+
+/// Replies a router may still send under its rate limit.
+struct Limit {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// A reply handed to its trace once it is due.
+struct Delivery {
+    due: Instant,
+    answer: Answer,
+    respond_to: mpsc::Sender<Answer>,
+}
+
+/// Answers probes from a simulated network instead of sending them, so that
+/// traces run without privileges or a network. Every destination is reached
+/// through the hops of the topology, and answers with the reply of its
+/// protocol. Replies are handed to the trace after their round-trip time.
+pub struct Simulation {
+    topology: Topology,
+    rng: StdRng,
+    limits: HashMap<IpAddr, Limit>,
+    deliveries: Option<mpsc::Sender<Delivery>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Simulation {
+    /// Read the topology of a simulated network from a JSON file.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path)
+            .map_err(|e| Error::Parse(format!("{}: {}", path.display(), e)))?;
+
+        Self::parse(&json).map_err(|e| Error::Parse(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Parse the topology of a simulated network from JSON.
+    pub fn parse(json: &str) -> Result<Self> {
+        let topology: Topology =
+            serde_json::from_str(json).map_err(|e| Error::Parse(e.to_string()))?;
+
+        for (idx, hop) in topology.hops.iter().enumerate() {
+            if hop.routers.is_empty() && !hop.silent {
+                return Err(Error::Parse(format!(
+                    "hop {} has no routers and isn't silent",
+                    idx + 1
+                )));
+            }
+        }
+        let mut routers = topology.hops.iter().flat_map(|hop| &hop.routers);
+        if let Some(router) = routers.find(|router| !router.node.is_valid()) {
+            return Err(Error::Parse(format!(
+                "router {} has a loss outside 0 to 1",
+                router.address
+            )));
+        }
+        if !topology.destination.is_valid() {
+            return Err(Error::Parse(
+                "the destination has a loss outside 0 to 1".to_string(),
+            ));
+        }
+
+        let (deliveries, pending) = mpsc::channel();
+        Ok(Simulation {
+            rng: StdRng::seed_from_u64(topology.seed),
+            topology,
+            limits: HashMap::new(),
+            deliveries: Some(deliveries),
+            thread: Some(thread::spawn(move || deliver(pending))),
+        })
+    }
+
+    /// The reply to `probe`, or `None` if it is lost, rate limited or
+    /// reaches a silent hop.
+    fn answer(&mut self, probe: &Probe<'_>) -> Option<Answer> {
+        let idx = usize::from(probe.ttl.max(1) - 1);
+        let (addr, node, reply, quoted_ttl, initial_ttl) = match self.topology.hops.get(idx) {
+            Some(hop) if hop.silent => return None,
+            Some(hop) => {
+                let router =
+                    &hop.routers[flow_route(self.topology.seed, probe) % hop.routers.len()];
+                let reply = ReplyKind::TimeExceeded;
+                (router.address, router.node, reply, Some(1), ROUTER_TTL)
+            }
+            None => {
+                let reply = match probe.protocol {
+                    Protocol::Udp => ReplyKind::Unreachable,
+                    Protocol::Icmp => ReplyKind::EchoReply,
+                    Protocol::Tcp => ReplyKind::SynAck,
+                };
+                let node = self.topology.destination;
+                (probe.destination, node, reply, None, DESTINATION_TTL)
+            }
+        };
+
+        if self.rng.gen::<f64>() < node.loss || !self.admit(addr, node.rate_limit, probe.sent) {
+            return None;
+        }
+
+        // The reply crosses as many routers on its way back as the probe.
+        let hops = idx.min(self.topology.hops.len());
+        Some(Answer {
+            seq: probe.seq,
+            addr,
+            reply,
+            mpls: vec![],
+            ttl: Some(
+                node.initial_ttl
+                    .unwrap_or(initial_ttl)
+                    .saturating_sub(hops as u8),
+            ),
+            quoted_ttl,
+            modifications: vec![],
+            received: probe.sent + node.latency.sample(&mut self.rng),
+            packet: vec![],
+        })
+    }
+
+    /// Test whether `addr` may send a reply at `now` under its rate limit,
+    /// which allows bursts of a second worth of replies.
+    fn admit(&mut self, addr: IpAddr, rate_limit: Option<u32>, now: Instant) -> bool {
+        let rate = match rate_limit {
+            Some(rate) => f64::from(rate),
+            None => return true,
+        };
+        let burst = rate.max(1.0);
+        let limit = self.limits.entry(addr).or_insert(Limit {
+            tokens: burst,
+            updated: now,
+        });
+
+        let elapsed = now.saturating_duration_since(limit.updated).as_secs_f64();
+        limit.tokens = (limit.tokens + elapsed * rate).min(burst);
+        limit.updated = now;
+        if limit.tokens < 1.0 {
+            return false;
+        }
+        limit.tokens -= 1.0;
+
+        true
+    }
+}
+
+impl Node {
+    fn is_valid(&self) -> bool {
+        (0.0..=1.0).contains(&self.loss)
+    }
+}
+
+impl Prober for Simulation {
+    fn send(&mut self, probe: &Probe<'_>, respond_to: &mpsc::Sender<Answer>) -> io::Result<()> {
+        if let (Some(answer), Some(deliveries)) = (self.answer(probe), &self.deliveries) {
+            let delivery = Delivery {
+                due: answer.received,
+                answer,
+                respond_to: respond_to.clone(),
+            };
+            deliveries
+                .send(delivery)
+                .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "simulation stopped"))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        // Replies that aren't due yet are dropped with the trace.
+        self.deliveries.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The index of the router a probe passes at its TTL. Each hop hashes the
+/// flow of the probe on its own, like independent load balancers.
+fn flow_route(seed: u64, probe: &Probe<'_>) -> usize {
+    // The finalizer of SplitMix64.
+    let mut x = seed ^ (u64::from(probe.ttl) << 16 | u64::from(probe.flow));
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+    (x ^ (x >> 31)) as usize
+}
+
+/// Hand replies to their traces once they are due, until the simulation is
+/// dropped.
+fn deliver(pending: mpsc::Receiver<Delivery>) {
+    let mut queue: Vec<Delivery> = vec![];
+
+    loop {
+        let now = Instant::now();
+        let (due, later): (Vec<Delivery>, Vec<Delivery>) =
+            queue.into_iter().partition(|delivery| delivery.due <= now);
+        queue = later;
+        for delivery in due {
+            let _ = delivery.respond_to.send(delivery.answer);
+        }
+
+        let next = queue.iter().map(|delivery| delivery.due).min();
+        let received = match next {
+            Some(next) => pending.recv_timeout(next.saturating_duration_since(now)),
+            None => pending
+                .recv()
+                .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(delivery) => queue.push(delivery),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
@@ -2,13 +2,33 @@ SELECT
   r.source,
   r.destination,
   t.trace,
+  t.protocol,
+  t.mode,
+  t.stop_reason,
+  o.platform,
+  o.vantage_point,
   h.ttl,
   h.query,
   h.query_result,
   h.addr,
-  h.rtt,
-  hs.mean_ms AS hop_mean_ms,
-  hs.median_ms AS hop_median_ms,
+  h.rtt_us / 1000.0 AS rtt,
+  hs.mean_us / 1000.0 AS hop_mean_ms,
+  hs.median_us / 1000.0 AS hop_median_ms,
+  h.reply_ttl,
+  h.quoted_ttl,
+  h.reverse_hops,
+  h.reverse_hops - h.ttl AS asymmetry,
+  h.hidden_hops,
+  (
+    SELECT group_concat(
+      'L=' || m.label || ',TC=' || m.tc || ',S=' || m.s || ',TTL=' || m.ttl,
+      ' '
+    )
+    FROM hop_mpls m
+    WHERE m.hop = h.id
+  ) AS mpls,
+  hm.mtu AS hop_mtu,
+  pm.mtu AS path_mtu,
   hg.city,
   hg.region,
   hg.region_code,
@@ -25,8 +45,11 @@ SELECT
 FROM hop h
   JOIN trace t ON h.trace = t.id
   JOIN route r ON t.route = r.id
+  LEFT JOIN trace_origin o ON t.id = o.trace
   LEFT JOIN hop_stats hs ON h.id = hs.hop
   LEFT JOIN hop_geo hg ON h.id = hg.hop
+  LEFT JOIN hop_mtu hm ON t.id = hm.trace AND h.ttl = hm.ttl
+  LEFT JOIN path_mtu pm ON t.id = pm.trace
 WHERE r.source = ?1
   AND r.destination = ?2
 ORDER BY t.trace, h.ttl;
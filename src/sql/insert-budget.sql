@@ -0,0 +1,8 @@
+INSERT INTO probe_budget (
+  day,
+  budget,
+  used
+) VALUES (COALESCE(date(?3 * 86400, 'unixepoch'), date('now')), ?1, ?2)
+ON CONFLICT (day) DO UPDATE SET
+  budget = ?1,
+  used = used + ?2;
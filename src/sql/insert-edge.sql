@@ -0,0 +1,6 @@
+INSERT INTO edge (
+  trace,
+  source,
+  destination
+) VALUES (?1, ?2, ?3)
+ON CONFLICT DO NOTHING;
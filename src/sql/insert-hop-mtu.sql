@@ -0,0 +1,6 @@
+INSERT INTO hop_mtu (
+  trace,
+  ttl,
+  mtu
+) VALUES (?1, ?2, ?3)
+ON CONFLICT DO NOTHING;
@@ -4,6 +4,10 @@ INSERT INTO hop (
   query,
   query_result,
   addr,
-  rtt
-) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+  rtt_us,
+  reply_ttl,
+  quoted_ttl,
+  reverse_hops,
+  hidden_hops
+) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
 ON CONFLICT DO NOTHING;
@@ -0,0 +1,7 @@
+INSERT INTO hop_modification (
+  hop,
+  field,
+  sent,
+  quoted
+) VALUES (?1, ?2, ?3, ?4)
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,9 @@
+INSERT INTO hop_mpls (
+  hop,
+  position,
+  label,
+  tc,
+  s,
+  ttl
+) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,5 @@
+INSERT INTO path_mtu (
+  trace,
+  mtu
+) VALUES (?1, ?2)
+ON CONFLICT DO NOTHING;
@@ -1,6 +1,6 @@
 INSERT INTO hop_stats (
   hop,
-  mean_ms,
-  median_ms
+  mean_us,
+  median_us
 ) VALUES (?1, ?2, ?3)
 ON CONFLICT DO NOTHING;
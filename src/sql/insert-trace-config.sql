@@ -0,0 +1,16 @@
+INSERT INTO trace_config (
+  trace,
+  port,
+  first_ttl,
+  max_hops,
+  tries,
+  timeout_ms,
+  wait_ms,
+  size,
+  tos,
+  confidence,
+  window_size,
+  gap_limit,
+  interface
+) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,8 @@
+INSERT INTO trace_origin (
+  trace,
+  platform,
+  vantage_point,
+  measurement,
+  started_at
+) VALUES (?1, ?2, ?3, ?4, ?5)
+ON CONFLICT DO NOTHING;
@@ -1,5 +1,7 @@
 INSERT INTO trace (
   trace,
-  route
-) VALUES (?1, ?2)
+  route,
+  protocol,
+  mode
+) VALUES (?1, ?2, ?3, ?4)
 ON CONFLICT DO NOTHING;
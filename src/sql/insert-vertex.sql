@@ -0,0 +1,6 @@
+INSERT INTO vertex (
+  trace,
+  ttl,
+  addr
+) VALUES (?1, ?2, ?3)
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,6 @@
+SELECT
+  budget,
+  CASE WHEN day = date('now') THEN used ELSE 0 END AS used
+FROM probe_budget
+ORDER BY day DESC
+LIMIT 1;
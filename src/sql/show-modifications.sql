@@ -0,0 +1,15 @@
+SELECT
+  t.trace,
+  h.ttl,
+  h.query,
+  h.addr,
+  m.field,
+  m.sent,
+  m.quoted
+FROM hop_modification m
+  JOIN hop h ON m.hop = h.id
+  JOIN trace t ON h.trace = t.id
+  JOIN route r ON t.route = r.id
+WHERE r.source = ?1
+  AND r.destination = ?2
+ORDER BY t.id, h.ttl, h.query, m.id;
@@ -0,0 +1,7 @@
+SELECT
+  id
+FROM
+  vertex
+WHERE trace = ?1
+  AND ttl = ?2
+  AND addr = ?3;
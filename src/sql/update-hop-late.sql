@@ -0,0 +1,11 @@
+UPDATE hop
+SET query_result = 'late',
+  addr = ?4,
+  rtt_us = ?5,
+  reply_ttl = ?6,
+  quoted_ttl = ?7,
+  reverse_hops = ?8,
+  hidden_hops = ?9
+WHERE ttl = ?1
+  AND trace = ?2
+  AND query = ?3;
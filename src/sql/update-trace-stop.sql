@@ -0,0 +1,3 @@
+UPDATE trace
+SET stop_reason = ?2
+WHERE trace = ?1;
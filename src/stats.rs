@@ -10,19 +10,19 @@ pub struct HopStats {
 
 impl HopStats {
     pub(crate) fn from_durations(durations: &[Duration]) -> Self {
-        let mean = time_mean(&durations);
-        let median = time_median(&durations);
+        let mean = time_mean(durations);
+        let median = time_median(durations);
 
         Self { mean, median }
     }
 }
 
 fn time_mean(list: &[Duration]) -> Option<Duration> {
-    if list.len() == 0 {
+    if list.is_empty() {
         return None;
     };
     let sum: Duration = Iterator::sum(list.iter());
-    Some(Duration::from(sum) / (list.len() as u32))
+    Some(sum / (list.len() as u32))
 }
 
 fn time_median(list: &[Duration]) -> Option<Duration> {
@@ -34,9 +34,9 @@ fn time_median(list: &[Duration]) -> Option<Duration> {
 
     let mid = len / 2;
 
-    if len % 2 == 0 {
+    if len.is_multiple_of(2) {
         time_mean(&list[(mid - 1)..(mid + 1)])
     } else {
-        Some(Duration::from(list[mid]))
+        Some(list[mid])
     }
 }
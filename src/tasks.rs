@@ -1,9 +1,9 @@
-use anyhow::Result;
 use std::{net::IpAddr, time::Duration};
 
 use crate::{
     data::DbHandle,
-    geoip, stats, {Hop, TraceQuery},
+    geoip, stats, Result,
+    {ExportModification, Hop, LateReply, MplsLabel, Multipath, PathMtu, Response, TraceQuery},
 };
 
 pub enum Task {
@@ -13,22 +13,152 @@ pub enum Task {
 }
 
 pub fn hop_log(hop: Hop) -> Result<()> {
-    let queries = hop
-        .queries
+    let msg = format!("{}: {}", hop.ttl, queries_log(&hop));
+
+    println!("{}", msg);
+
+    Ok(())
+}
+
+/// Print the hops of a path MTU discovery with the MTU of the path up to
+/// each of them, and the MTU of the whole path.
+pub fn pmtu_log(hops: &[Hop], path: &PathMtu) -> Result<()> {
+    for (hop, mtu) in hops.iter().zip(&path.hops) {
+        println!("{}: {}  mtu {}", hop.ttl, queries_log(hop), mtu.mtu);
+    }
+    println!("path mtu {}", path.mtu);
+
+    Ok(())
+}
+
+fn queries_log(hop: &Hop) -> String {
+    hop.queries
         .iter()
         .map(|q| match q {
-            TraceQuery::Success { addr, rtt } => {
-                format!("{} ({}ms)", addr, rtt.as_millis().to_string())
+            TraceQuery::Success(response) => {
+                format!(
+                    "{} ({:.3}ms){}{}",
+                    response.addr,
+                    millis(&response.rtt),
+                    mpls_log(&response.mpls),
+                    ttl_log(q, hop.ttl)
+                )
+            }
+            TraceQuery::Late(response) => {
+                format!(
+                    "{} ({:.3}ms, late){}{}",
+                    response.addr,
+                    millis(&response.rtt),
+                    mpls_log(&response.mpls),
+                    ttl_log(q, hop.ttl)
+                )
             }
-            TraceQuery::Timeout => format!("*"),
-            TraceQuery::Failure(_) => format!("X"),
+            TraceQuery::Timeout => "*".to_string(),
+            TraceQuery::Failure(_) => "X".to_string(),
         })
         .collect::<Vec<String>>()
-        .join("  ");
+        .join("  ")
+}
 
-    let msg = format!("{}: {}", hop.ttl, queries);
+/// What the TTLs of a reply to a probe with `ttl` tell about the path: the
+/// length of the reverse path if it differs from the forward one, and the
+/// number of hops hidden before the replying router.
+fn ttl_log(query: &TraceQuery, ttl: u8) -> String {
+    let mut log = String::new();
+    if let Some(reverse_hops) = query.reverse_hops().filter(|hops| *hops != ttl) {
+        log.push_str(&format!(" <reverse path {}>", reverse_hops));
+    }
+    if let Some(hidden_hops) = query.hidden_hops().filter(|hops| *hops > 0) {
+        log.push_str(&format!(" <hidden {}>", hidden_hops));
+    }
 
-    println!("{}", msg);
+    log
+}
+
+/// The MPLS label stack of a reply, top entry first, or nothing if the reply
+/// quoted none.
+fn mpls_log(mpls: &[MplsLabel]) -> String {
+    if mpls.is_empty() {
+        return String::new();
+    }
+
+    let labels = mpls
+        .iter()
+        .map(|label| label.to_string())
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    format!(" <MPLS {}>", labels)
+}
+
+/// A round-trip time in fractional milliseconds.
+fn millis(rtt: &Duration) -> f64 {
+    rtt.as_secs_f64() * 1000.0
+}
+
+pub fn late_log(late: &LateReply) -> Result<()> {
+    if let TraceQuery::Late(response) = &late.result {
+        println!(
+            "{}: {} ({:.3}ms, late reply to query {}){}",
+            late.ttl,
+            response.addr,
+            millis(&response.rtt),
+            late.query,
+            mpls_log(&response.mpls)
+        );
+    }
+
+    Ok(())
+}
+
+/// Print the header modifications of a route, one line per query that found
+/// any, grouped by trace.
+pub fn modifications_log(modifications: &[ExportModification]) -> Result<()> {
+    if modifications.is_empty() {
+        println!("no modifications");
+        return Ok(());
+    }
+
+    let mut last = None;
+    for (idx, modification) in modifications.iter().enumerate() {
+        let key = (modification.trace, modification.ttl, modification.query);
+        if last.map(|(trace, _, _)| trace) != Some(modification.trace) {
+            println!("trace {}", modification.trace);
+        }
+        if last != Some(key) {
+            let changes = modifications[idx..]
+                .iter()
+                .take_while(|m| (m.trace, m.ttl, m.query) == key)
+                .map(|m| format!("{} {} -> {}", m.field, m.sent, m.quoted))
+                .collect::<Vec<String>>()
+                .join(", ");
+            println!("{}: {} {}", modification.ttl, modification.addr, changes);
+        }
+        last = Some(key);
+    }
+
+    Ok(())
+}
+
+pub fn multipath_log(multipath: &Multipath) -> Result<()> {
+    let mut ttls = multipath
+        .vertices
+        .iter()
+        .map(|vertex| vertex.ttl)
+        .collect::<Vec<u8>>();
+    ttls.dedup();
+
+    for ttl in ttls {
+        let addrs = multipath
+            .vertices
+            .iter()
+            .filter(|vertex| vertex.ttl == ttl)
+            .map(|vertex| vertex.addr.to_string())
+            .collect::<Vec<String>>()
+            .join("  ");
+
+        println!("{}: {}", ttl, addrs);
+    }
 
     Ok(())
 }
@@ -38,7 +168,7 @@ pub fn hop_stats(db: &DbHandle, hop: Hop) -> Result<()> {
         .queries
         .iter()
         .filter_map(|q| match q {
-            &TraceQuery::Success { rtt, .. } => Some(rtt),
+            TraceQuery::Success(response) => Some(response.rtt),
             _ => None,
         })
         .collect::<Vec<Duration>>();
@@ -46,33 +176,36 @@ pub fn hop_stats(db: &DbHandle, hop: Hop) -> Result<()> {
 
     let stats = stats::HopStats::from_durations(&durations);
 
-    db.insert_stats(hop, stats);
-
-    Ok(())
+    db.insert_stats(hop, stats)
 }
 
+/// Store the geolocation of the addresses of a hop, looking up the ones that
+/// weren't looked up before. Stops at the first lookup that fails.
 pub fn hop_geoip(db: &DbHandle, hop: Hop) -> Result<()> {
-    let mut idx = 1;
-    for query in &hop.queries {
-        match query {
-            TraceQuery::Success { addr, .. } => match addr {
-                IpAddr::V4(ipv4) => {
-                    if !ipv4.is_private() {
-                        let lookup_data = db
-                            .show_geoip(&ipv4)
-                            .or_else(|| geoip::fetch_ip_api(&addr).ok());
-
-                        if let Some(ip_api_resp) = lookup_data {
-                            db.insert_geoip(hop.clone(), idx, ip_api_resp);
-                        }
-                    }
-                }
-                _ => {}
-            },
-            _ => {}
+    for (idx, query) in (1..).zip(&hop.queries) {
+        if let Some(Response { addr, .. }) = query.response() {
+            if is_global(addr) {
+                let ip_api_resp = match db.show_geoip(addr)? {
+                    Some(ip_api_resp) => ip_api_resp,
+                    None => geoip::fetch_ip_api(addr)?,
+                };
+
+                db.insert_geoip(hop.clone(), idx, ip_api_resp)?;
+            }
         };
-        idx += 1;
     }
 
     Ok(())
 }
+
+/// Test whether an address can be looked up in a geolocation database. Private,
+/// unique local and link-local addresses aren't.
+fn is_global(addr: &IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(ip) => !ip.is_private() && !ip.is_link_local(),
+        IpAddr::V6(ip) => {
+            let prefix = ip.segments()[0];
+            prefix & 0xfe00 != 0xfc00 && prefix & 0xffc0 != 0xfe80
+        }
+    }
+}
@@ -1,27 +1,48 @@
-use anyhow::Result;
 use pnet::{
     datalink::{self, MacAddr, NetworkInterface},
-    packet::{icmp::IcmpTypes, ip::IpNextHeaderProtocols, Packet},
+    packet::Packet,
     transport::{
-        TransportProtocol::Ipv4,
-        TransportSender, {icmp_packet_iter, transport_channel, TransportChannelType},
+        TransportProtocol::Ipv6,
+        TransportSender, {transport_channel, TransportChannelType},
     },
 };
 use std::{
-    net::{IpAddr, Ipv4Addr},
-    str::FromStr,
-    time::{Duration, SystemTime},
+    collections::VecDeque,
+    fs, io, mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, UdpSocket},
+    ops::RangeInclusive,
+    sync::{mpsc, Arc},
+    thread,
+    time::{Duration, Instant},
 };
 
-use crate::{packet::PacketBuilder, Hop, Trace, TraceQuery};
+use crate::{
+    capture::{ipv6_reply, Capture},
+    datagram::{can_open_raw_sockets, Datagram},
+    mda,
+    pacer::Pacer,
+    packet::{next_header, PacketBuilder},
+    prober::{Probe, Prober},
+    receiver::{Answer, Receiver},
+    Error, Hop, HopMtu, LateReply, Mode, Multipath, PathMtu, Protocol, ReplyKind, Response, Result,
+    StopReason, Trace, TraceQuery,
+};
+
+/// Replies to probes that timed out are still matched for this many timeouts
+/// after the probe was sent.
+const LATE_TIMEOUTS: u32 = 4;
+
+/// MTUs probed after a router that doesn't report the MTU of its next hop
+/// answered Packet Too Big, from RFC 1191.
+const MTU_PLATEAUS: [u16; 11] = [
+    65535, 32000, 17914, 8166, 4352, 2002, 1492, 1006, 508, 296, 68,
+];
 
 /// List all available interfaces on this machine.
 pub fn available_interfaces() -> Vec<NetworkInterface> {
     let all_interfaces = datalink::interfaces();
 
-    let available_interfaces: Vec<NetworkInterface>;
-
-    available_interfaces = if cfg!(target_family = "windows") {
+    let available_interfaces: Vec<NetworkInterface> = if cfg!(target_family = "windows") {
         all_interfaces
             .into_iter()
             .filter(|e| {
@@ -33,69 +54,156 @@ pub fn available_interfaces() -> Vec<NetworkInterface> {
     } else {
         all_interfaces
             .into_iter()
-            .filter(|e| {
-                e.is_up()
-                    && !e.is_loopback()
-                    && e.ips.iter().any(|ip| ip.is_ipv4())
-                    && e.mac.is_some()
-                // && e.mac.unwrap() != MacAddr::zero()
-            })
+            // Tunnels of VPNs have no MAC address.
+            .filter(|e| e.is_up() && !e.is_loopback() && !e.ips.is_empty())
             .collect()
     };
 
     available_interfaces
 }
 
-/// Return the default network interface.
-pub fn default_interface() -> NetworkInterface {
-    let available_interfaces = available_interfaces();
+/// Return the network interface the kernel routes packets to `destination`
+/// through. Falls back to the first interface of the address family of
+/// `destination` if the routing table has no route to it.
+pub fn default_interface(destination: &IpAddr) -> Result<NetworkInterface> {
+    if let Some(interface) = route_source(destination).and_then(|ip| address_interface(&ip)) {
+        return Ok(interface);
+    }
 
-    available_interfaces
-        .get(0)
-        .expect("no interfaces available")
-        .clone()
+    available_interfaces()
+        .into_iter()
+        .find(|e| e.ips.iter().any(|ip| is_usable(&ip.ip(), destination)))
+        .ok_or_else(|| Error::Interface("no interfaces available".to_string()))
 }
 
-/// Extract the IP address of the network interface.
-pub fn interface_ip(interface: NetworkInterface) -> Result<Ipv4Addr> {
-    let ip = interface
+/// Find a network interface by its name.
+pub fn find_interface(name: &str) -> Result<NetworkInterface> {
+    datalink::interfaces()
+        .into_iter()
+        .find(|e| e.name == name)
+        .ok_or_else(|| Error::Interface(format!("{:?} is an unknown interface", name)))
+}
+
+/// Find the network interface that has the address `ip`.
+pub fn address_interface(ip: &IpAddr) -> Option<NetworkInterface> {
+    datalink::interfaces()
+        .into_iter()
+        .find(|e| e.ips.iter().any(|net| net.ip() == *ip))
+}
+
+/// Ask the kernel for the source address of packets to `destination`, as
+/// chosen by its routing table. Connecting a UDP socket sends nothing.
+pub fn route_source(destination: &IpAddr) -> Option<IpAddr> {
+    let unspecified = match destination {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = UdpSocket::bind((unspecified, 0)).ok()?;
+    socket.connect((*destination, 33434)).ok()?;
+
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+/// Extract the IP address of the network interface that has the address
+/// family of `destination`.
+pub fn interface_ip(interface: NetworkInterface, destination: &IpAddr) -> Result<IpAddr> {
+    interface
         .ips
         .iter()
-        .find(|i| i.is_ipv4())
-        .expect("couldn't get interface IP")
-        .ip()
-        .to_string();
+        .map(|ip| ip.ip())
+        .find(|ip| is_usable(ip, destination))
+        .ok_or_else(|| {
+            Error::Interface(format!(
+                "interface {} has no address to reach {}",
+                interface.name, destination
+            ))
+        })
+}
 
-    Ok(Ipv4Addr::from_str(ip.as_str())?)
+/// Read the MTU of a network interface from sysfs. Returns `None` where it
+/// isn't available.
+pub fn interface_mtu(interface: &NetworkInterface) -> Option<u16> {
+    let path = format!("/sys/class/net/{}/mtu", interface.name);
+
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Test whether an interface address can be the source of a trace to
+/// `destination`. IPv6 link-local addresses can't reach beyond the link.
+fn is_usable(ip: &IpAddr, destination: &IpAddr) -> bool {
+    match (ip, destination) {
+        (IpAddr::V4(_), IpAddr::V4(_)) => true,
+        (IpAddr::V6(ip), IpAddr::V6(_)) => ip.segments()[0] & 0xffc0 != 0xfe80,
+        _ => false,
+    }
 }
 
 /// Traceroute configurations
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Config {
-    port: u16,
-    max_hops: u8,
-    tries: u8,
-    timeout: Duration,
+    pub(crate) protocol: Protocol,
+    pub(crate) mode: Mode,
+    pub(crate) port: u16,
+    pub(crate) first_ttl: u8,
+    pub(crate) max_hops: u8,
+    pub(crate) tries: u8,
+    pub(crate) timeout: Duration,
+    pub(crate) wait: Duration,
+    pub(crate) size: Option<u16>,
+    pub(crate) tos: u8,
+    pub(crate) confidence: f64,
+    pub(crate) window: Option<u8>,
+    pub(crate) gap_limit: Option<u8>,
+    pub(crate) interface: Option<String>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
+            protocol: Protocol::Udp,
+            mode: Mode::Classic,
             port: 33434,
+            first_ttl: 1,
             max_hops: 30,
             tries: 3,
             timeout: Duration::from_secs(5),
+            wait: Duration::from_millis(0),
+            size: None,
+            tos: 0,
+            confidence: 0.95,
+            window: None,
+            gap_limit: None,
+            interface: None,
         }
     }
 }
 
 impl Config {
-    /// Builder: Port for traceroute. Will be incremented on every query (except for TCP-based traceroute)
+    /// Builder: Protocol used to send probes
+    pub fn with_protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Builder: How probes are varied, either per query or Paris-style
+    pub fn with_mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Builder: Port for traceroute. Will be incremented on every query (except for TCP-based and Paris traceroute).
+    /// TCP traces send their SYN probes to this port.
     pub fn with_port(mut self, port: u16) -> Self {
         self.port = port;
         self
     }
 
+    /// Builder: TTL of the first hop that is probed
+    pub fn with_first_ttl(mut self, first_ttl: u8) -> Self {
+        self.first_ttl = first_ttl;
+        self
+    }
+
     /// Builder: Maximum number of hops
     pub fn with_max_hops(mut self, max_hops: u8) -> Self {
         self.max_hops = max_hops;
@@ -108,11 +216,99 @@ impl Config {
         self
     }
 
+    /// Builder: Confidence with which MDA traces find all next hops of a hop,
+    /// between 0 and 1 exclusive
+    pub fn with_confidence(mut self, confidence: f64) -> Self {
+        self.confidence = confidence;
+        self
+    }
+
+    /// Builder: Number of TTLs probed at once instead of one after the other,
+    /// 0 probes all TTLs up to the maximum number of hops at once
+    pub fn with_window(mut self, window: u8) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    /// Builder: Number of consecutive hops that answer no probe after which a
+    /// trace gives up
+    pub fn with_gap_limit(mut self, gap_limit: u8) -> Self {
+        self.gap_limit = Some(gap_limit);
+        self
+    }
+
     /// Builder: Timeout per query
     pub fn with_timeout(mut self, timeout: u64) -> Self {
         self.timeout = Duration::from_millis(timeout);
         self
     }
+
+    /// Builder: Minimum time between two probes in milliseconds
+    pub fn with_wait(mut self, wait: u64) -> Self {
+        self.wait = Duration::from_millis(wait);
+        self
+    }
+
+    /// Builder: Size of probes including the IP header, as far as their
+    /// headers fit
+    pub fn with_size(mut self, size: u16) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    /// Builder: TOS byte of IPv4 probes and traffic class of IPv6 probes,
+    /// the DSCP in the upper six bits and the ECN bits below
+    pub fn with_tos(mut self, tos: u8) -> Self {
+        self.tos = tos;
+        self
+    }
+
+    /// Builder: Network interface probes are sent from, regardless of the
+    /// routing table
+    pub fn with_interface(mut self, interface: &str) -> Self {
+        self.interface = Some(interface.to_string());
+        self
+    }
+}
+
+/// A probe that was sent, kept to match a reply that comes in late once it
+/// timed out.
+#[derive(Debug, Clone, Copy)]
+struct Outstanding {
+    /// Sequence number of the probe within the trace.
+    seq: u16,
+    ttl: u8,
+    query: u8,
+    flow: u16,
+    sent: Instant,
+}
+
+/// Sends probes on raw sockets, which take root or CAP_NET_RAW. Replies are
+/// received by a receiver, which may be shared with other traces.
+struct Raw {
+    tx: TransportSender,
+    receiver: Arc<Receiver>,
+}
+
+impl Prober for Raw {
+    fn send(&mut self, probe: &Probe<'_>, respond_to: &mpsc::Sender<Answer>) -> io::Result<()> {
+        // IPv6 raw sockets can't include the IP header, so the hop limit is
+        // set on the socket.
+        if probe.destination.is_ipv6() {
+            self.tx.set_ttl(probe.ttl)?;
+        }
+
+        // The probe is registered before it is sent so that no reply can
+        // arrive before the receiver knows about it.
+        self.receiver.register(
+            probe.matcher,
+            probe.builder.sent_packet(probe.ttl, probe.packet),
+            probe.expires,
+            respond_to.clone(),
+        );
+
+        self.tx.send_to(probe.packet, probe.destination).map(drop)
+    }
 }
 
 /// Traceroute instance containing destination address and configurations. A
@@ -121,37 +317,185 @@ pub struct TraceRoute {
     pub trace: Trace,
     config: Config,
     packet_builder: PacketBuilder,
-    tx: TransportSender,
+    prober: Box<dyn Prober>,
+    answers: mpsc::Receiver<Answer>,
+    respond_to: mpsc::Sender<Answer>,
+    seq: u16,
+    /// When the last probe was sent.
+    last_sent: Option<Instant>,
+    /// Paces probes with those of other traces sharing it.
+    pacer: Arc<Pacer>,
+    /// Whether a probe wasn't sent because the probe budget is used up.
+    exhausted: bool,
+    /// Probes that timed out and may still be answered.
+    outstanding: Vec<Outstanding>,
+    /// Replies that came in after their probe timed out.
+    late: Vec<LateReply>,
+    /// Records every probe and reply, if set.
+    capture: Option<Arc<Capture>>,
 }
 
 impl TraceRoute {
-    /// Creates new instance of Traceroute
-    pub fn new(source: Ipv4Addr, destination: Ipv4Addr, config: Config) -> Self {
-        let trace = Trace::new(source, destination);
-        let packet_builder = PacketBuilder::new(source, destination);
-        let transport = transport_channel(
-            4096,
-            TransportChannelType::Layer3(IpNextHeaderProtocols::Udp),
-        );
+    /// Creates new instance of Traceroute. Probes are sent on raw sockets if
+    /// this process may open them, and on datagram sockets otherwise.
+    pub fn new(source: IpAddr, destination: IpAddr, config: Config) -> Result<Self> {
+        if can_open_raw_sockets() {
+            Self::with_receiver(source, destination, config, Arc::new(Receiver::new()?))
+        } else {
+            Self::unprivileged(source, destination, config)
+        }
+    }
 
-        let (tx, _) = match transport {
-            Ok((tx, rx)) => (tx, rx),
-            Err(e) => panic!("layer3: unable to create channel: {}", e),
+    /// Creates new instance of Traceroute that receives replies on a receiver
+    /// shared with other traces.
+    pub fn with_receiver(
+        source: IpAddr,
+        destination: IpAddr,
+        config: Config,
+        receiver: Arc<Receiver>,
+    ) -> Result<Self> {
+        if let (IpAddr::V6(_), Some(e)) = (destination, receiver.ipv6_error()) {
+            return Err(Error::Socket(format!(
+                "IPv6 replies can't be received: {}",
+                e
+            )));
+        }
+
+        // IPv6 raw sockets can't include the IP header, so the kernel builds
+        // it from the bound source address and the hop limit of the socket.
+        let tx = match source {
+            IpAddr::V4(_) => {
+                let protocol = TransportChannelType::Layer3(next_header(config.protocol, false));
+                transport_channel(4096, protocol)
+                    .map_err(|e| Error::socket("layer3: unable to create channel", e))?
+                    .0
+            }
+            IpAddr::V6(source) => {
+                let protocol =
+                    TransportChannelType::Layer4(Ipv6(next_header(config.protocol, true)));
+                let (tx, _) = transport_channel(4096, protocol)
+                    .map_err(|e| Error::socket("layer4: unable to create channel", e))?;
+                bind_ipv6(tx.socket.fd, source)
+                    .map_err(|e| Error::socket("layer4: unable to bind channel", e))?;
+                set_dont_fragment(tx.socket.fd)
+                    .and_then(|_| set_traffic_class(tx.socket.fd, config.tos))
+                    .map_err(|e| Error::socket("layer4: unable to set channel options", e))?;
+                tx
+            }
         };
 
+        if let Some(interface) = &config.interface {
+            bind_device(tx.socket.fd, interface).map_err(|e| {
+                Error::socket(&format!("unable to bind channel to {}", interface), e)
+            })?;
+        }
+
+        let (respond_to, answers) = mpsc::channel();
+        Ok(Self::with_backend(
+            source,
+            destination,
+            config,
+            Box::new(Raw { tx, receiver }),
+            respond_to,
+            answers,
+        ))
+    }
+
+    /// Creates new instance of Traceroute that sends its probes on datagram
+    /// sockets, which need neither root nor CAP_NET_RAW. Only ICMP probes
+    /// and UDP probes of classic traces can be sent on them.
+    pub fn unprivileged(source: IpAddr, destination: IpAddr, config: Config) -> Result<Self> {
+        let (respond_to, answers) = mpsc::channel();
+        let ident = PacketBuilder::new(source, destination, config.protocol, config.mode).ident();
+        let datagram = Datagram::new(source, destination, &config, ident, respond_to.clone())?;
+
+        Ok(Self::with_backend(
+            source,
+            destination,
+            config,
+            Box::new(datagram),
+            respond_to,
+            answers,
+        ))
+    }
+
+    /// Creates new instance of Traceroute that sends its probes with a
+    /// prober of its own, like a simulated network.
+    pub fn with_prober(
+        source: IpAddr,
+        destination: IpAddr,
+        config: Config,
+        prober: Box<dyn Prober>,
+    ) -> Self {
+        let (respond_to, answers) = mpsc::channel();
+
+        Self::with_backend(source, destination, config, prober, respond_to, answers)
+    }
+
+    fn with_backend(
+        source: IpAddr,
+        destination: IpAddr,
+        config: Config,
+        prober: Box<dyn Prober>,
+        respond_to: mpsc::Sender<Answer>,
+        answers: mpsc::Receiver<Answer>,
+    ) -> Self {
+        let trace = Trace::new(source, destination, config.protocol, config.mode);
+        let mut packet_builder =
+            PacketBuilder::new(source, destination, config.protocol, config.mode)
+                .with_tos(config.tos);
+        if let Some(size) = config.size {
+            packet_builder = packet_builder.with_size(size);
+        }
+        // The kernel puts the identifier of a ping socket into the Echo
+        // Requests sent on it.
+        if let Some(ident) = prober.ident() {
+            packet_builder = packet_builder.with_ident(ident);
+        }
+
         TraceRoute {
             trace,
             config,
             packet_builder,
-            tx,
+            prober,
+            answers,
+            respond_to,
+            seq: 0,
+            last_sent: None,
+            pacer: Arc::new(Pacer::new(0)),
+            exhausted: false,
+            outstanding: vec![],
+            late: vec![],
+            capture: None,
         }
     }
 
+    /// Paces the probes of this trace with a pacer shared with other traces
+    /// and scans. Probes aren't paced otherwise.
+    pub fn with_pacer(mut self, pacer: Arc<Pacer>) -> Self {
+        self.pacer = pacer;
+        self
+    }
+
+    /// Records the probes and replies of this trace in a capture, which may be
+    /// shared with other traces.
+    pub fn with_capture(mut self, capture: Arc<Capture>) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// The configuration of this trace.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
     /// Return an iterator of hops over this trace.
     pub fn iter(&mut self) -> TraceRouteIter<'_> {
         TraceRouteIter {
-            done: false,
-            ttl: 1,
+            stop_reason: None,
+            gap: 0,
+            ttl: u16::from(self.config.first_ttl),
+            hops: VecDeque::new(),
             traceroute: self,
         }
     }
@@ -168,14 +512,201 @@ impl TraceRoute {
         hops
     }
 
+    /// Run the Multipath Detection Algorithm and return the graph of all paths
+    /// to the destination of this trace. It stops for the same reasons as
+    /// other traces.
+    pub fn multipath(&mut self) -> Multipath {
+        let first_ttl = self.config.first_ttl;
+        let max_hops = self.config.max_hops;
+        let confidence = self.config.confidence;
+        let gap_limit = self.config.gap_limit;
+        let destination = self.trace.route.destination;
+
+        let (vertices, edges, stop_reason) = mda::discover(
+            |ttl, flow| {
+                let query = self.probe(ttl, 1, flow);
+                (!self.exhausted).then_some(query)
+            },
+            first_ttl,
+            max_hops,
+            confidence,
+            gap_limit,
+            destination,
+        );
+
+        Multipath {
+            trace: self.trace.id,
+            vertices,
+            edges,
+            stop_reason,
+        }
+    }
+
+    /// Discover the MTU of the path up to each hop. Each TTL is probed with
+    /// probes of the MTU found so far, starting at `mtu`. A router that can't
+    /// forward them answers Packet Too Big, and the TTL is probed again with
+    /// the MTU it reported. Like other traces, it stops at the destination,
+    /// once the budget is exhausted or at the gap limit. Returns the hops and
+    /// the MTUs found.
+    pub fn pmtu(&mut self, mtu: u16) -> (Vec<Hop>, PathMtu) {
+        let ipv6 = self.trace.route.destination.is_ipv6();
+        let mut mtu = mtu;
+        let mut hops = vec![];
+        let mut mtus = vec![];
+        let mut stop_reason = StopReason::MaxHops;
+        let mut gap = 0;
+
+        for ttl in self.config.first_ttl..=self.config.max_hops {
+            self.packet_builder = self.packet_builder.with_size(mtu);
+            let mut queries = vec![];
+
+            while queries.len() < self.config.tries as usize {
+                let query = self.probe(ttl, queries.len() as u8 + 1, 0);
+                if let TraceQuery::Success(Response {
+                    reply: ReplyKind::PacketTooBig { mtu: reported },
+                    ..
+                }) = query
+                {
+                    if let Some(next) = next_mtu(mtu, reported, ipv6) {
+                        mtu = next;
+                        self.packet_builder = self.packet_builder.with_size(mtu);
+                        queries.clear();
+                        continue;
+                    }
+                }
+                queries.push(query);
+            }
+
+            let hop = self.assemble_hop(ttl, queries);
+            let done = hop.is_destination(self.trace.route.destination);
+            let unresponsive = hop.is_unresponsive();
+            hops.push(hop);
+            mtus.push(HopMtu { ttl, mtu });
+
+            if done {
+                stop_reason = StopReason::Completed;
+                break;
+            } else if self.exhausted {
+                stop_reason = StopReason::Budget;
+                break;
+            } else if unresponsive {
+                gap += 1;
+                if Some(gap) == self.config.gap_limit {
+                    stop_reason = StopReason::GapLimit;
+                    break;
+                }
+            } else {
+                gap = 0;
+            }
+        }
+
+        let path = PathMtu {
+            trace: self.trace.id,
+            hops: mtus,
+            mtu,
+            stop_reason,
+        };
+
+        (hops, path)
+    }
+
+    /// Take the replies that came in late for probes of hops that were
+    /// already yielded.
+    pub fn take_late(&mut self) -> Vec<LateReply> {
+        mem::take(&mut self.late)
+    }
+
     /// Yield the next hop of this trace.
     fn hop(&mut self, ttl: u8) -> Hop {
         let mut queries: Vec<TraceQuery> = vec![];
 
-        for _ in 0..self.config.tries {
-            let packet = self.packet_builder.build_packet(ttl, self.config.port);
-            let query_result = self.query(packet);
-            queries.push(query_result);
+        self.expire_outstanding();
+
+        for query in 1..=self.config.tries {
+            let result = match self.send(ttl, query, 0) {
+                Ok(probe) => {
+                    let result = self.wait(probe);
+                    if let TraceQuery::Timeout = result {
+                        self.outstanding.push(probe);
+                    }
+                    result
+                }
+                Err(failure) => failure,
+            };
+            queries.push(result);
+        }
+
+        self.assemble_hop(ttl, queries)
+    }
+
+    /// Probe all TTLs in `ttls` at once and yield their hops once every probe
+    /// was answered or timed out.
+    fn hops(&mut self, ttls: RangeInclusive<u8>) -> Vec<Hop> {
+        let tries = self.config.tries as usize;
+        let mut queries: Vec<Vec<TraceQuery>> = ttls
+            .clone()
+            .map(|_| vec![TraceQuery::Timeout; tries])
+            .collect();
+        let mut pending: Vec<Outstanding> = vec![];
+
+        self.expire_outstanding();
+
+        for query in 1..=self.config.tries {
+            for ttl in ttls.clone() {
+                match self.send(ttl, query, 0) {
+                    Ok(probe) => pending.push(probe),
+                    Err(failure) => {
+                        queries[(ttl - ttls.start()) as usize][query as usize - 1] = failure
+                    }
+                }
+            }
+        }
+
+        // All probes share the deadline of the last one that was sent.
+        let deadline = pending.last().map(|p| p.sent + self.config.timeout);
+        while let (false, Some(deadline)) = (pending.is_empty(), deadline) {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout == Duration::from_millis(0) {
+                break;
+            }
+
+            match self.answers.recv_timeout(timeout) {
+                Ok(answer) => match pending.iter().position(|p| p.seq == answer.seq) {
+                    Some(idx) => {
+                        let probe = pending.remove(idx);
+                        self.capture_reply(&answer, Some(&probe), "reply");
+                        queries[(probe.ttl - ttls.start()) as usize][probe.query as usize - 1] =
+                            TraceQuery::Success(response(answer, probe.sent));
+                    }
+                    None => self.record_late(answer),
+                },
+                Err(_) => break,
+            }
+        }
+
+        // Probes that weren't answered in time may still be answered late.
+        self.outstanding.append(&mut pending);
+
+        ttls.zip(queries)
+            .map(|(ttl, queries)| self.assemble_hop(ttl, queries))
+            .collect()
+    }
+
+    /// Forget the probes that timed out too long ago to expect a reply.
+    fn expire_outstanding(&mut self) {
+        let window = self.config.timeout * LATE_TIMEOUTS;
+        self.outstanding.retain(|o| o.sent.elapsed() < window);
+    }
+
+    /// Build the hop of a TTL from its query results.
+    fn assemble_hop(&mut self, ttl: u8, mut queries: Vec<TraceQuery>) -> Hop {
+        // Late replies to probes of this hop replace their timeouts, the others
+        // belong to hops that were already yielded.
+        let (own, late): (Vec<LateReply>, Vec<LateReply>) =
+            self.late.drain(..).partition(|late| late.ttl == ttl);
+        self.late = late;
+        for late in own {
+            queries[late.query as usize - 1] = late.result;
         }
 
         Hop {
@@ -187,55 +718,308 @@ impl TraceRoute {
         }
     }
 
-    /// Runs a query to the destination and returns RTT and IP of the router where
-    /// time-to-live-exceeded. Doesn't increase TTL
-    fn query(&mut self, packet: impl Packet) -> TraceQuery {
-        let now = SystemTime::now();
-        let protocol = TransportChannelType::Layer4(Ipv4(IpNextHeaderProtocols::Icmp));
+    /// Send a single probe of a flow with a TTL and wait for its reply.
+    fn probe(&mut self, ttl: u8, query: u8, flow: u16) -> TraceQuery {
+        match self.send(ttl, query, flow) {
+            Ok(probe) => self.wait(probe),
+            Err(failure) => failure,
+        }
+    }
 
-        let (_, mut receiver) = match transport_channel(4096, protocol) {
-            Ok((tx, rx)) => (tx, rx),
-            Err(e) => panic!("layer4: unable to create channel: {}", e),
+    /// Send the probe of a query of a flow with a TTL.
+    fn send(
+        &mut self,
+        ttl: u8,
+        query: u8,
+        flow: u16,
+    ) -> std::result::Result<Outstanding, TraceQuery> {
+        // UDP probes carry the sequence in the UDP checksum, where zero means
+        // no checksum. A checksum of 0xffff is sent as zero as well, so the
+        // sequence skips both.
+        self.seq = match self.seq.wrapping_add(1) {
+            0 | 0xffff => 1,
+            seq => seq,
         };
+        let seq = self.seq;
+        let packet = self
+            .packet_builder
+            .build_packet(ttl, self.config.port, seq, flow);
 
-        let mut iter = icmp_packet_iter(&mut receiver);
-
-        match self
-            .tx
-            .send_to(packet, IpAddr::V4(self.trace.route.destination))
-        {
-            Ok(_) => {}
-            Err(e) => {
-                panic!(
-                    "Could not send packet, make sure this program has needed privilages, Error<{}>",
-                    e.to_string()
-                );
-            }
+        if !self.pacer.acquire(&self.trace.route.destination) {
+            self.exhausted = true;
+            return Err(TraceQuery::Failure("probe budget exhausted".to_string()));
         }
 
-        let next = iter.next_with_timeout(self.config.timeout);
+        // Probes are spaced by the configured wait.
+        if let Some(last_sent) = self.last_sent {
+            let next = last_sent + self.config.wait;
+            let now = Instant::now();
+            if next > now {
+                thread::sleep(next - now);
+            }
+        }
 
-        match next {
-            Ok(Some((header, addr))) => match header.get_icmp_type() {
-                IcmpTypes::TimeExceeded
-                | IcmpTypes::EchoReply
-                | IcmpTypes::DestinationUnreachable => TraceQuery::Success {
-                    rtt: now.elapsed().unwrap_or_else(|_| Duration::from_millis(0)),
-                    addr,
-                },
-                _ => TraceQuery::Failure("wrong packet".to_string()),
+        let sent = Instant::now();
+        self.last_sent = Some(sent);
+        let ipv6 = self.trace.route.destination.is_ipv6();
+        let probe = Probe {
+            seq,
+            ttl,
+            flow: match (self.config.mode, self.config.protocol) {
+                (Mode::Classic, Protocol::Udp | Protocol::Icmp) => seq,
+                _ => flow,
             },
-            Ok(None) => TraceQuery::Timeout,
-            Err(e) => TraceQuery::Failure(e.to_string()),
+            protocol: self.config.protocol,
+            destination: self.trace.route.destination,
+            port: self.config.port,
+            size: packet.packet().len() + if ipv6 { 40 } else { 0 },
+            sent,
+            expires: sent + self.config.timeout * LATE_TIMEOUTS,
+            packet: &packet,
+            builder: &self.packet_builder,
+            matcher: self.packet_builder.matcher(self.config.port, seq, flow),
+        };
+        if let Err(e) = self.prober.send(&probe, &self.respond_to) {
+            return Err(TraceQuery::Failure(e.to_string()));
+        }
+
+        let probe = Outstanding {
+            seq,
+            ttl,
+            query,
+            flow,
+            sent,
+        };
+        if let Some(capture) = &self.capture {
+            let packet = self.packet_builder.sent_packet(ttl, &packet);
+            capture.probe(&packet, sent, &self.comment(&probe, "probe"));
+        }
+
+        Ok(probe)
+    }
+
+    /// Wait for the reply to `probe`. Replies to earlier probes that come in
+    /// meanwhile are recorded as late.
+    fn wait(&mut self, probe: Outstanding) -> TraceQuery {
+        loop {
+            let timeout = match self.config.timeout.checked_sub(probe.sent.elapsed()) {
+                Some(timeout) if timeout > Duration::from_millis(0) => timeout,
+                _ => return TraceQuery::Timeout,
+            };
+
+            match self.answers.recv_timeout(timeout) {
+                Ok(answer) if answer.seq == probe.seq => {
+                    self.capture_reply(&answer, Some(&probe), "reply");
+                    return TraceQuery::Success(response(answer, probe.sent));
+                }
+                Ok(answer) => self.record_late(answer),
+                Err(mpsc::RecvTimeoutError::Timeout) => return TraceQuery::Timeout,
+                Err(e) => return TraceQuery::Failure(e.to_string()),
+            }
+        }
+    }
+
+    /// Record a reply to a probe of a hop that already timed out. Replies to
+    /// other probes, like the ones of MDA traces, are dropped.
+    fn record_late(&mut self, answer: Answer) {
+        let idx = match self.outstanding.iter().position(|o| o.seq == answer.seq) {
+            Some(idx) => idx,
+            None => {
+                self.capture_reply(&answer, None, "reply");
+                return;
+            }
+        };
+
+        let outstanding = self.outstanding.remove(idx);
+        self.capture_reply(&answer, Some(&outstanding), "late reply");
+        self.late.push(LateReply {
+            trace: self.trace.id,
+            ttl: outstanding.ttl,
+            query: outstanding.query,
+            result: TraceQuery::Late(response(answer, outstanding.sent)),
+        });
+    }
+
+    /// Describe `what` of a probe in the capture, like `probe` or `reply`.
+    fn comment(&self, probe: &Outstanding, what: &str) -> String {
+        let id = self.trace.id;
+        match self.config.mode {
+            Mode::Mda => format!(
+                "{} of trace {} ttl {} flow {}",
+                what, id, probe.ttl, probe.flow
+            ),
+            _ => format!(
+                "{} of trace {} ttl {} query {}",
+                what, id, probe.ttl, probe.query
+            ),
         }
     }
+
+    /// Record a reply to `probe` in the capture of this trace. Replies the
+    /// prober has no packet of aren't recorded.
+    fn capture_reply(&self, answer: &Answer, probe: Option<&Outstanding>, what: &str) {
+        let capture = match &self.capture {
+            Some(capture) if !answer.packet.is_empty() => capture,
+            _ => return,
+        };
+
+        let comment = match probe {
+            Some(probe) => self.comment(probe, what),
+            None => format!(
+                "{} of trace {} to forgotten probe {}",
+                what, self.trace.id, answer.seq
+            ),
+        };
+        match (answer.addr, self.trace.route.source) {
+            (IpAddr::V6(addr), IpAddr::V6(source)) => {
+                let packet = ipv6_reply(addr, source, answer.reply, answer.ttl, &answer.packet);
+                capture.reply(&packet, answer.received, &comment);
+            }
+            _ => capture.reply(&answer.packet, answer.received, &comment),
+        }
+    }
+}
+
+/// The MTU to probe with after a probe of `mtu` bytes was answered with
+/// Packet Too Big reporting `reported`. Routers that report no MTU, or one
+/// that isn't smaller, are probed with the next smaller plateau. Returns
+/// `None` if `mtu` is the minimum MTU of the address family already.
+fn next_mtu(mtu: u16, reported: u16, ipv6: bool) -> Option<u16> {
+    let min = if ipv6 { 1280 } else { 68 };
+    let next = if reported >= min && reported < mtu {
+        reported
+    } else {
+        MTU_PLATEAUS
+            .iter()
+            .copied()
+            .find(|plateau| *plateau < mtu)
+            .unwrap_or(min)
+    };
+
+    Some(next.max(min)).filter(|next| *next < mtu)
+}
+
+/// Set the DF flag on the probes sent on an IPv6 socket, so that they are
+/// fragmented neither on the way nor by the MTU the kernel learned for the
+/// path, like IPv4 probes that carry the flag in their header.
+#[cfg(target_os = "linux")]
+fn set_dont_fragment(fd: libc::c_int) -> io::Result<()> {
+    for (option, value) in [
+        (libc::IPV6_MTU_DISCOVER, libc::IPV6_PMTUDISC_PROBE),
+        (libc::IPV6_DONTFRAG, 1),
+    ] {
+        let res = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::IPPROTO_IPV6,
+                option,
+                &value as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_dont_fragment(_fd: libc::c_int) -> io::Result<()> {
+    Ok(())
+}
+
+/// Set the traffic class of the probes sent on an IPv6 socket, since the
+/// kernel builds their header.
+fn set_traffic_class(fd: libc::c_int, tos: u8) -> io::Result<()> {
+    let value = libc::c_int::from(tos);
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_TCLASS,
+            &value as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Bind a socket to a network interface, so that its packets leave through
+/// it whatever the routing table says.
+#[cfg(target_os = "linux")]
+pub(crate) fn bind_device(fd: libc::c_int, interface: &str) -> io::Result<()> {
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            interface.as_ptr() as *const libc::c_void,
+            interface.len() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn bind_device(_fd: libc::c_int, _interface: &str) -> io::Result<()> {
+    Ok(())
+}
+
+/// Bind an IPv6 socket to a source address.
+fn bind_ipv6(fd: libc::c_int, source: Ipv6Addr) -> io::Result<()> {
+    let mut addr: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+    addr.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+    addr.sin6_addr.s6_addr = source.octets();
+
+    let res = unsafe {
+        libc::bind(
+            fd,
+            &addr as *const libc::sockaddr_in6 as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// The response of `answer` to a probe sent at `sent`.
+fn response(answer: Answer, sent: Instant) -> Response {
+    Response {
+        rtt: answer.received.saturating_duration_since(sent),
+        addr: answer.addr,
+        reply: answer.reply,
+        mpls: answer.mpls,
+        reply_ttl: answer.ttl,
+        quoted_ttl: answer.quoted_ttl,
+        modifications: answer.modifications,
+    }
 }
 
 /// An iterator over a trace. Returns individual hops of a trace as it's
 /// elements.
 pub struct TraceRouteIter<'a> {
-    ttl: u8,
-    done: bool,
+    /// The next TTL to probe, one above the maximum number of hops once they
+    /// were all probed.
+    ttl: u16,
+    /// Why the trace stopped, once it did.
+    stop_reason: Option<StopReason>,
+    /// Number of consecutive hops that answered no probe.
+    gap: u8,
+    /// Hops of the current window that weren't yielded yet.
+    hops: VecDeque<Hop>,
     traceroute: &'a mut TraceRoute,
 }
 
@@ -245,9 +1029,31 @@ impl<'a> TraceRouteIter<'a> {
         self.ttl += 1;
     }
 
+    /// Take the replies that came in late for probes of hops that were
+    /// already yielded.
+    pub fn take_late(&mut self) -> Vec<LateReply> {
+        self.traceroute.take_late()
+    }
+
+    /// Why the trace stopped, or `None` while it can be continued.
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.stop_reason
+    }
+
     /// Test whether the trace has been completed or can be continued.
-    fn is_finished(&self) -> bool {
-        self.done || self.ttl >= self.traceroute.config.max_hops
+    fn is_finished(&mut self) -> bool {
+        if self.stop_reason.is_none() && self.ttl > u16::from(self.traceroute.config.max_hops) {
+            self.stop_reason = Some(StopReason::MaxHops);
+        }
+
+        self.stop_reason.is_some()
+    }
+
+    /// Stop the trace, dropping the hops of a window beyond the hop that
+    /// stopped it.
+    fn stop(&mut self, reason: StopReason) {
+        self.stop_reason = Some(reason);
+        self.hops.clear();
     }
 }
 
@@ -255,17 +1061,44 @@ impl<'a> Iterator for TraceRouteIter<'a> {
     type Item = Hop;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.is_finished() {
-            return None;
-        }
+        if self.hops.is_empty() {
+            if self.is_finished() {
+                return None;
+            }
 
-        let hop = self.traceroute.hop(self.ttl);
+            let max_hops = self.traceroute.config.max_hops;
+            match self.traceroute.config.window {
+                Some(window) => {
+                    let last = match window {
+                        0 => max_hops,
+                        window => (self.ttl + u16::from(window) - 1).min(u16::from(max_hops)) as u8,
+                    };
+                    let hops = self.traceroute.hops(self.ttl as u8..=last);
+                    self.hops.extend(hops);
+                    self.ttl = u16::from(last) + 1;
+                }
+                None => {
+                    let hop = self.traceroute.hop(self.ttl as u8);
+                    self.hops.push_back(hop);
+                    self.increment_ttl();
+                }
+            }
+        }
 
-        self.increment_ttl();
+        let hop = self.hops.pop_front()?;
 
-        if hop.is_ip(self.traceroute.trace.route.destination) {
-            self.done = true;
-        };
+        if hop.is_destination(self.traceroute.trace.route.destination) {
+            self.stop(StopReason::Completed);
+        } else if self.traceroute.exhausted {
+            self.stop(StopReason::Budget);
+        } else if hop.is_unresponsive() {
+            self.gap += 1;
+            if Some(self.gap) == self.traceroute.config.gap_limit {
+                self.stop(StopReason::GapLimit);
+            }
+        } else {
+            self.gap = 0;
+        }
 
         Some(hop)
     }
@@ -0,0 +1,358 @@
+use std::{net::IpAddr, path::Path, time::Duration};
+
+use tracer::{
+    Hop, ImportedTrace, Mode, MplsLabel, Protocol, ReplyKind, Response, StopReason, TraceQuery,
+};
+
+fn import(name: &str) -> Vec<ImportedTrace> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name);
+
+    tracer::import(&path, None).unwrap()
+}
+
+fn addr(addr: &str) -> IpAddr {
+    addr.parse().unwrap()
+}
+
+/// The address and round-trip time of each answered query of a hop, `None`
+/// for timeouts. Late replies are left out.
+fn replies(hop: &Hop) -> Vec<Option<(IpAddr, Duration)>> {
+    hop.queries
+        .iter()
+        .filter(|query| !matches!(query, TraceQuery::Late(_)))
+        .map(|query| match query {
+            TraceQuery::Success(response) => Some((response.addr, response.rtt)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn mpls(query: &TraceQuery) -> &[MplsLabel] {
+    query
+        .response()
+        .map_or(&[], |response| response.mpls.as_slice())
+}
+
+fn micros(us: u64) -> Duration {
+    Duration::from_micros(us)
+}
+
+#[test]
+fn warts_trace() {
+    let traces = import("trace.warts");
+    assert_eq!(traces.len(), 1);
+    let trace = &traces[0];
+
+    assert_eq!(trace.trace.route.source, addr("192.0.2.2"));
+    assert_eq!(trace.trace.route.destination, addr("198.51.100.1"));
+    assert_eq!(trace.trace.protocol, Protocol::Udp);
+    assert_eq!(trace.trace.mode, Mode::Paris);
+    assert_eq!(trace.origin.vantage_point, "ams-nl");
+    assert_eq!(trace.stop_reason, Some(StopReason::Completed));
+
+    assert_eq!(trace.hops.len(), 3);
+    assert_eq!(
+        replies(&trace.hops[0]),
+        vec![
+            Some((addr("192.0.2.1"), micros(812))),
+            Some((addr("192.0.2.1"), micros(790))),
+        ]
+    );
+    assert_eq!(
+        replies(&trace.hops[1]),
+        vec![Some((addr("10.0.0.1"), micros(5000))), None]
+    );
+    assert_eq!(
+        replies(&trace.hops[2]),
+        vec![None, Some((addr("198.51.100.1"), micros(9000)))]
+    );
+
+    // The router at hop 2 quotes its label stack in an ICMP extension.
+    assert_eq!(
+        mpls(&trace.hops[1].queries[0]),
+        &[MplsLabel {
+            label: 16001,
+            tc: 0,
+            bottom: true,
+            ttl: 254,
+        }]
+    );
+    assert!(mpls(&trace.hops[0].queries[0]).is_empty());
+}
+
+#[test]
+fn atlas_traces() {
+    let traces = import("atlas.json");
+    // The ping result is skipped.
+    assert_eq!(traces.len(), 2);
+
+    let trace = &traces[0];
+    assert_eq!(trace.trace.route.source, addr("192.168.1.10"));
+    assert_eq!(trace.trace.route.destination, addr("193.0.14.129"));
+    assert_eq!(trace.trace.protocol, Protocol::Icmp);
+    assert_eq!(trace.trace.mode, Mode::Paris);
+    assert_eq!(trace.origin.vantage_point, "6012");
+    assert_eq!(trace.origin.measurement.as_deref(), Some("5001"));
+    assert_eq!(trace.stop_reason, Some(StopReason::Completed));
+
+    assert_eq!(trace.hops.len(), 3);
+    assert_eq!(
+        replies(&trace.hops[0]),
+        vec![
+            Some((addr("192.168.1.1"), micros(800))),
+            Some((addr("192.168.1.1"), micros(700))),
+            None,
+        ]
+    );
+    // Duplicates are dropped and the late reply is kept as such.
+    assert_eq!(
+        replies(&trace.hops[1]),
+        vec![Some((addr("10.1.1.1"), micros(5100))), None]
+    );
+    match &trace.hops[1].queries[2] {
+        TraceQuery::Late(late) => {
+            assert_eq!(late.addr, addr("10.1.1.1"));
+            assert_eq!(late.rtt, micros(1_500_200));
+        }
+        query => panic!("expected a late reply, got {:?}", query),
+    }
+    assert_eq!(
+        mpls(&trace.hops[1].queries[0]),
+        &[MplsLabel {
+            label: 24001,
+            tc: 0,
+            bottom: true,
+            ttl: 1,
+        }]
+    );
+    assert_eq!(
+        replies(&trace.hops[2]),
+        vec![
+            Some((addr("193.0.14.129"), micros(9900))),
+            Some((addr("193.0.14.129"), micros(9800))),
+            Some((addr("193.0.14.129"), micros(9700))),
+        ]
+    );
+
+    // Atlas gave up after hop 2, and the hop 255 it ends with is dropped.
+    let trace = &traces[1];
+    assert_eq!(trace.trace.protocol, Protocol::Udp);
+    assert_eq!(trace.trace.mode, Mode::Classic);
+    assert_eq!(trace.stop_reason, Some(StopReason::GapLimit));
+    assert_eq!(
+        trace.hops.iter().map(|hop| hop.ttl).collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+    assert_eq!(
+        replies(&trace.hops[0]),
+        vec![Some((addr("203.0.113.1"), micros(1000)))]
+    );
+    assert!(matches!(
+        trace.hops[1].queries.as_slice(),
+        [TraceQuery::Failure(_)]
+    ));
+}
+
+/// The number of replies from each address of a hop and of timeouts.
+fn counts(hop: &Hop) -> Vec<(Option<IpAddr>, usize)> {
+    let mut counts: Vec<(Option<IpAddr>, usize)> = vec![];
+    for reply in replies(hop) {
+        let addr = reply.map(|(addr, _)| addr);
+        match counts.iter_mut().find(|(known, _)| *known == addr) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((addr, 1)),
+        }
+    }
+
+    counts
+}
+
+#[test]
+fn hop_log() {
+    let traces = import("198.51.100.9.trace");
+    assert_eq!(traces.len(), 2);
+
+    // Logs don't name their destination, the file does.
+    let trace = &traces[0];
+    assert_eq!(trace.trace.route.destination, addr("198.51.100.9"));
+    assert!(trace.trace.route.source.is_unspecified());
+    assert_eq!(trace.trace.protocol, Protocol::Udp);
+    assert_eq!(trace.stop_reason, Some(StopReason::Completed));
+
+    assert_eq!(trace.hops.len(), 4);
+    assert_eq!(
+        replies(&trace.hops[0]),
+        vec![
+            Some((addr("192.0.2.1"), micros(512))),
+            Some((addr("192.0.2.1"), micros(498))),
+            None,
+        ]
+    );
+    match trace.hops[1].queries.as_slice() {
+        [TraceQuery::Success(first), TraceQuery::Failure(_), TraceQuery::Late(late)] => {
+            assert_eq!(first.addr, addr("10.0.0.1"));
+            assert_eq!(
+                first.mpls,
+                vec![MplsLabel {
+                    label: 24001,
+                    tc: 0,
+                    bottom: true,
+                    ttl: 1,
+                }]
+            );
+            // Two hidden hops are a quoted TTL of 3.
+            assert_eq!(first.quoted_ttl, Some(3));
+            assert_eq!(late.addr, addr("10.0.0.1"));
+            assert_eq!(late.rtt, micros(900_100));
+        }
+        queries => panic!("unexpected queries {:?}", queries),
+    }
+    assert_eq!(
+        replies(&trace.hops[2]),
+        vec![
+            Some((addr("10.0.1.1"), micros(5100))),
+            Some((addr("10.0.1.2"), micros(5300))),
+            None,
+        ]
+    );
+    assert_eq!(
+        counts(&trace.hops[3]),
+        vec![(Some(addr("198.51.100.9")), 3)]
+    );
+
+    let trace = &traces[1];
+    assert_eq!(trace.stop_reason, None);
+    assert_eq!(counts(&trace.hops[1]), vec![(None, 3)]);
+}
+
+#[test]
+fn traceroute() {
+    let traces = import("traceroute.txt");
+    assert_eq!(traces.len(), 1);
+    let trace = &traces[0];
+
+    assert_eq!(trace.trace.route.destination, addr("8.8.4.4"));
+    assert_eq!(trace.trace.protocol, Protocol::Udp);
+    assert_eq!(trace.stop_reason, Some(StopReason::Completed));
+
+    assert_eq!(trace.hops.len(), 4);
+    assert_eq!(
+        replies(&trace.hops[0]),
+        vec![
+            Some((addr("192.0.2.1"), micros(512))),
+            Some((addr("192.0.2.1"), micros(468))),
+            Some((addr("192.0.2.1"), micros(440))),
+        ]
+    );
+    assert_eq!(counts(&trace.hops[1]), vec![(None, 3)]);
+    assert_eq!(
+        replies(&trace.hops[2]),
+        vec![
+            Some((addr("10.0.0.1"), micros(5123))),
+            Some((addr("10.0.0.2"), micros(5456))),
+            Some((addr("10.0.0.2"), micros(5789))),
+        ]
+    );
+    assert_eq!(
+        mpls(&trace.hops[2].queries[0]),
+        &[MplsLabel {
+            label: 24001,
+            tc: 0,
+            bottom: true,
+            ttl: 1,
+        }]
+    );
+    assert!(mpls(&trace.hops[2].queries[1]).is_empty());
+    assert_eq!(
+        replies(&trace.hops[3]),
+        vec![
+            Some((addr("8.8.4.4"), micros(9100))),
+            Some((addr("8.8.4.4"), micros(9200))),
+            None,
+        ]
+    );
+    assert!(matches!(
+        trace.hops[3].queries[0],
+        TraceQuery::Success(Response {
+            reply: ReplyKind::Unreachable,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn mtr_report() {
+    let traces = import("mtr-report.txt");
+    assert_eq!(traces.len(), 1);
+    let trace = &traces[0];
+
+    // The destination is the last hop that answered.
+    assert_eq!(trace.trace.route.destination, addr("1.0.0.1"));
+    assert_eq!(trace.trace.protocol, Protocol::Icmp);
+    assert_eq!(trace.origin.vantage_point, "radar");
+    assert_eq!(trace.origin.started, Some(1_683_187_200));
+    assert_eq!(trace.stop_reason, Some(StopReason::Completed));
+
+    assert_eq!(trace.hops.len(), 5);
+    assert_eq!(counts(&trace.hops[0]), vec![(Some(addr("192.0.2.1")), 10)]);
+    assert_eq!(
+        replies(&trace.hops[0])[0],
+        Some((addr("192.0.2.1"), micros(600)))
+    );
+    // Other addresses of a hop and their labels are skipped.
+    assert_eq!(
+        counts(&trace.hops[2]),
+        vec![(Some(addr("10.0.1.1")), 8), (None, 2)]
+    );
+    for (hop, label) in &[(1, 24001), (2, 24002)] {
+        assert_eq!(
+            mpls(&trace.hops[*hop].queries[0]),
+            &[MplsLabel {
+                label: *label,
+                tc: 0,
+                bottom: true,
+                ttl: 1,
+            }]
+        );
+    }
+    assert_eq!(counts(&trace.hops[3]), vec![(None, 10)]);
+    assert_eq!(counts(&trace.hops[4]), vec![(Some(addr("1.0.0.1")), 10)]);
+}
+
+#[test]
+fn mtr_json() {
+    let traces = import("mtr.json");
+    assert_eq!(traces.len(), 1);
+    let trace = &traces[0];
+
+    assert_eq!(trace.trace.route.destination, addr("9.9.9.9"));
+    assert_eq!(trace.trace.protocol, Protocol::Icmp);
+    assert_eq!(trace.origin.vantage_point, "radar");
+    assert_eq!(trace.stop_reason, Some(StopReason::Completed));
+
+    assert_eq!(trace.hops.len(), 4);
+    assert_eq!(counts(&trace.hops[0]), vec![(Some(addr("192.0.2.1")), 5)]);
+    assert_eq!(counts(&trace.hops[1]), vec![(None, 5)]);
+    // Hubs of the same TTL are other addresses of the hop.
+    assert_eq!(
+        counts(&trace.hops[2]),
+        vec![
+            (Some(addr("10.0.1.1")), 5),
+            (Some(addr("10.0.1.2")), 2),
+            (None, 3),
+        ]
+    );
+    assert_eq!(
+        replies(&trace.hops[3]),
+        vec![
+            Some((addr("9.9.9.9"), micros(12_500))),
+            Some((addr("9.9.9.9"), micros(12_500))),
+            Some((addr("9.9.9.9"), micros(12_500))),
+            None,
+            None,
+        ]
+    );
+}
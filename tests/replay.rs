@@ -0,0 +1,373 @@
+use std::{
+    env, fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tracer::{Mode, MplsLabel, Protocol, ReplayedTrace, ReplyKind, StopReason, TraceQuery};
+
+const SOURCE: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 2);
+const DESTINATION: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 7);
+const SOURCE6: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2);
+const DESTINATION6: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 7);
+
+/// The link type of raw IP packets.
+const LINKTYPE_RAW: u32 = 101;
+
+/// Replay a pcap capture of raw IP `packets`, each captured at its time in
+/// microseconds.
+fn replay(name: &str, packets: &[(u32, Vec<u8>)]) -> Vec<ReplayedTrace> {
+    let mut capture = vec![];
+    for field in &[0xa1b2_c3d4u32, 0x0004_0002, 0, 0, 0xffff, LINKTYPE_RAW] {
+        capture.extend_from_slice(&field.to_le_bytes());
+    }
+    for (time, packet) in packets {
+        let len = packet.len() as u32;
+        for field in &[0, *time, len, len] {
+            capture.extend_from_slice(&field.to_le_bytes());
+        }
+        capture.extend_from_slice(packet);
+    }
+
+    let path = capture_path(name);
+    fs::write(&path, capture).unwrap();
+    let traces = tracer::replay(&path);
+    let _ = fs::remove_file(&path);
+
+    traces.unwrap()
+}
+
+fn capture_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("tracer-{}-{}.pcap", name, std::process::id()))
+}
+
+fn fixture(name: &str) -> Vec<ReplayedTrace> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name);
+
+    tracer::replay(&path).unwrap()
+}
+
+fn addr(addr: &str) -> IpAddr {
+    addr.parse().unwrap()
+}
+
+/// The address, round-trip time and kind of each answered query of a hop,
+/// `None` for timeouts.
+fn replies(trace: &ReplayedTrace, ttl: u8) -> Vec<Option<(IpAddr, Duration, ReplyKind)>> {
+    let hop = trace.hops.iter().find(|hop| hop.ttl == ttl).unwrap();
+
+    hop.queries
+        .iter()
+        .map(|query| {
+            query
+                .response()
+                .map(|response| (response.addr, response.rtt, response.reply))
+        })
+        .collect()
+}
+
+/// The Internet checksum of `data`.
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|word| u32::from(word[0]) << 8 | u32::from(*word.get(1).unwrap_or(&0)))
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+fn ipv4(source: Ipv4Addr, destination: Ipv4Addr, ttl: u8, protocol: u8, payload: &[u8]) -> Vec<u8> {
+    let len = (20 + payload.len()) as u16;
+    let mut packet = vec![0x45, 0];
+    packet.extend_from_slice(&len.to_be_bytes());
+    packet.extend_from_slice(&[0x12, 0x34, 0, 0, ttl, protocol, 0, 0]);
+    packet.extend_from_slice(&source.octets());
+    packet.extend_from_slice(&destination.octets());
+    let sum = checksum(&packet);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet.extend_from_slice(payload);
+
+    packet
+}
+
+fn ipv6(
+    source: Ipv6Addr,
+    destination: Ipv6Addr,
+    hop_limit: u8,
+    next: u8,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut packet = vec![0x60, 0, 0, 0];
+    packet.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    packet.extend_from_slice(&[next, hop_limit]);
+    packet.extend_from_slice(&source.octets());
+    packet.extend_from_slice(&destination.octets());
+    packet.extend_from_slice(payload);
+
+    packet
+}
+
+/// A UDP probe to port 33434 that carries `seq` in its checksum, like the
+/// probes of tracer.
+fn udp(seq: u16) -> Vec<u8> {
+    let mut segment = vec![0x80, 0x00, 0x82, 0x9a, 0, 12];
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&[0; 4]);
+
+    segment
+}
+
+/// An MPLS Label Stack object (RFC 4950) of `labels`, each a label, traffic
+/// class, bottom of stack flag and TTL.
+fn mpls_object(labels: &[(u32, u8, bool, u8)]) -> Vec<u8> {
+    let mut object = vec![];
+    object.extend_from_slice(&(4 + 4 * labels.len() as u16).to_be_bytes());
+    object.extend_from_slice(&[1, 1]);
+    for (label, tc, bottom, ttl) in labels {
+        let entry = label << 12 | u32::from(*tc) << 9 | u32::from(*bottom) << 8 | u32::from(*ttl);
+        object.extend_from_slice(&entry.to_be_bytes());
+    }
+
+    object
+}
+
+/// An ICMP extension structure (RFC 4884) of version 2 with `objects`.
+fn extensions(objects: &[u8], valid: bool) -> Vec<u8> {
+    let mut extensions = vec![0x20, 0, 0, 0];
+    extensions.extend_from_slice(objects);
+    let sum = checksum(&extensions) ^ if valid { 0 } else { 0xffff };
+    extensions[2..4].copy_from_slice(&sum.to_be_bytes());
+
+    extensions
+}
+
+/// A Time Exceeded message quoting `probe`, padded to `length` bytes if it
+/// has extensions. ICMP messages announce the length in units of 4 bytes,
+/// ICMPv6 messages in units of 8, unless `announce` is false, like routers
+/// that predate RFC 4884.
+fn time_exceeded(
+    probe: &[u8],
+    extensions: Option<Vec<u8>>,
+    length: usize,
+    unit: usize,
+    announce: bool,
+) -> Vec<u8> {
+    let mut quote = probe.to_vec();
+    let mut announced = 0;
+    if let Some(extensions) = extensions {
+        quote.resize(length, 0);
+        quote.extend_from_slice(&extensions);
+        if announce {
+            announced = (length / unit) as u8;
+        }
+    }
+
+    let mut message = if unit == 4 {
+        vec![11, 0, 0, 0, 0, announced, 0, 0]
+    } else {
+        vec![3, 0, 0, 0, announced, 0, 0, 0]
+    };
+    message.extend_from_slice(&quote);
+
+    message
+}
+
+fn mpls(query: &TraceQuery) -> &[MplsLabel] {
+    query
+        .response()
+        .map_or(&[], |response| response.mpls.as_slice())
+}
+
+fn label(label: u32, tc: u8, bottom: bool, ttl: u8) -> MplsLabel {
+    MplsLabel {
+        label,
+        tc,
+        bottom,
+        ttl,
+    }
+}
+
+#[test]
+fn icmp_extensions() {
+    let router = |ttl| Ipv4Addr::new(10, 0, ttl, 1);
+    let labels = mpls_object(&[(24001, 5, true, 1)]);
+    let stack = mpls_object(&[(16000, 0, false, 254), (24002, 7, true, 1)]);
+
+    let mut packets = vec![];
+    for ttl in 1..=4u8 {
+        let probe = ipv4(SOURCE, DESTINATION, ttl, 17, &udp(u16::from(ttl)));
+        // The probe as the router quotes it, after it decreased the TTL.
+        let quote = ipv4(SOURCE, DESTINATION, 1, 17, &udp(u16::from(ttl)));
+        let message = match ttl {
+            // No extensions.
+            1 => time_exceeded(&quote, None, 0, 4, true),
+            // The length of the quote is announced in 32-bit words.
+            2 => time_exceeded(&quote, Some(extensions(&labels, true)), 32, 4, true),
+            // Routers that don't announce it pad the quote to 128 bytes.
+            3 => time_exceeded(&quote, Some(extensions(&stack, true)), 128, 4, false),
+            // Extensions with a bad checksum are ignored.
+            _ => time_exceeded(&quote, Some(extensions(&labels, false)), 32, 4, true),
+        };
+        let time = u32::from(ttl) * 10_000;
+        packets.push((time, probe));
+        packets.push((time + 1_000, ipv4(router(ttl), SOURCE, 250, 1, &message)));
+    }
+
+    let traces = replay("icmp-extensions", &packets);
+    assert_eq!(traces.len(), 1);
+    let hops = &traces[0].hops;
+    assert_eq!(hops.len(), 4);
+    for hop in hops {
+        let response = hop.queries[0].response().unwrap();
+        assert_eq!(response.addr, IpAddr::V4(router(hop.ttl)));
+        assert_eq!(response.reply, ReplyKind::TimeExceeded);
+        assert_eq!(response.quoted_ttl, Some(1));
+    }
+
+    assert!(mpls(&hops[0].queries[0]).is_empty());
+    assert_eq!(mpls(&hops[1].queries[0]), &[label(24001, 5, true, 1)]);
+    assert_eq!(
+        mpls(&hops[2].queries[0]),
+        &[label(16000, 0, false, 254), label(24002, 7, true, 1)]
+    );
+    assert!(mpls(&hops[3].queries[0]).is_empty());
+}
+
+#[test]
+fn icmpv6_extensions() {
+    let router = |ttl| Ipv6Addr::new(0x2001, 0xdb8, 1, u16::from(ttl), 0, 0, 0, 1);
+    let labels = mpls_object(&[(24001, 0, true, 1)]);
+
+    let mut packets = vec![];
+    for ttl in 1..=2u8 {
+        let probe = ipv6(SOURCE6, DESTINATION6, ttl, 17, &udp(u16::from(ttl)));
+        let quote = ipv6(SOURCE6, DESTINATION6, 1, 17, &udp(u16::from(ttl)));
+        // The length of the quote is announced in 64-bit words, and a quote
+        // that isn't announced is padded to 128 bytes.
+        let message = time_exceeded(
+            &quote,
+            Some(extensions(&labels, true)),
+            if ttl == 1 { 56 } else { 128 },
+            8,
+            ttl == 1,
+        );
+        let time = u32::from(ttl) * 10_000;
+        packets.push((time, probe));
+        packets.push((time + 1_000, ipv6(router(ttl), SOURCE6, 250, 58, &message)));
+    }
+
+    let traces = replay("icmpv6-extensions", &packets);
+    assert_eq!(traces.len(), 1);
+    let hops = &traces[0].hops;
+    assert_eq!(hops.len(), 2);
+    for hop in hops {
+        let response = hop.queries[0].response().unwrap();
+        assert_eq!(response.addr, IpAddr::V6(router(hop.ttl)));
+        assert_eq!(response.reply, ReplyKind::TimeExceeded);
+        assert_eq!(mpls(&hop.queries[0]), &[label(24001, 0, true, 1)]);
+    }
+}
+
+/// Two runs of Linux traceroute in a big-endian pcap with timestamps in
+/// nanoseconds, captured on Ethernet with a VLAN tag, and a DNS query and an
+/// ARP request in between.
+#[test]
+fn pcap_traces() {
+    let traces = fixture("traceroute.pcap");
+    assert_eq!(traces.len(), 2);
+
+    // The second run starts over at TTL 1.
+    let (first, second) = (&traces[0], &traces[1]);
+    for trace in &traces {
+        assert_eq!(trace.trace.route.source, addr("192.0.2.2"));
+        assert_eq!(trace.trace.route.destination, addr("198.51.100.7"));
+        assert_eq!(trace.trace.protocol, Protocol::Udp);
+        // The destination port changes with every probe.
+        assert_eq!(trace.trace.mode, Mode::Classic);
+    }
+
+    assert_eq!(first.stop_reason, Some(StopReason::Completed));
+    assert_eq!(first.hops.len(), 3);
+    let router = |hop, us| {
+        Some((
+            addr(hop),
+            Duration::from_micros(us),
+            ReplyKind::TimeExceeded,
+        ))
+    };
+    assert_eq!(
+        replies(first, 1),
+        vec![router("192.0.2.1", 512), router("192.0.2.1", 498)]
+    );
+    assert_eq!(replies(first, 2), vec![router("10.0.0.1", 5123), None]);
+    assert_eq!(
+        replies(first, 3),
+        vec![
+            Some((
+                addr("198.51.100.7"),
+                Duration::from_micros(9100),
+                ReplyKind::Unreachable
+            )),
+            Some((
+                addr("198.51.100.7"),
+                Duration::from_micros(9200),
+                ReplyKind::Unreachable
+            )),
+        ]
+    );
+
+    assert_eq!(second.stop_reason, None);
+    assert_eq!(second.hops.len(), 2);
+    assert_eq!(replies(second, 1), vec![router("192.0.2.1", 600)]);
+    assert_eq!(replies(second, 2), vec![router("10.0.0.2", 5500)]);
+}
+
+/// A Paris traceroute with ICMP probes in a pcapng of two sections. The
+/// first is little-endian, captured on Linux cooked with timestamps in
+/// nanoseconds, the second big-endian, captured on Linux cooked v2 with
+/// timestamps in 1/1024 seconds.
+#[test]
+fn pcapng_trace() {
+    let traces = fixture("traceroute.pcapng");
+    assert_eq!(traces.len(), 1);
+    let trace = &traces[0];
+
+    assert_eq!(trace.trace.route.source, addr("192.0.2.5"));
+    assert_eq!(trace.trace.route.destination, addr("203.0.113.9"));
+    assert_eq!(trace.trace.protocol, Protocol::Icmp);
+    // The probes keep their checksum.
+    assert_eq!(trace.trace.mode, Mode::Paris);
+    assert_eq!(trace.stop_reason, Some(StopReason::Completed));
+
+    assert_eq!(
+        replies(trace, 1),
+        vec![Some((
+            addr("192.0.2.1"),
+            Duration::from_nanos(1_234_567),
+            ReplyKind::TimeExceeded
+        ))]
+    );
+    assert_eq!(
+        replies(trace, 2),
+        vec![Some((
+            addr("10.1.0.1"),
+            Duration::from_nanos(4_500_250),
+            ReplyKind::TimeExceeded
+        ))]
+    );
+    assert_eq!(
+        replies(trace, 3),
+        vec![Some((
+            addr("203.0.113.9"),
+            Duration::from_nanos(9_765_625),
+            ReplyKind::EchoReply
+        ))]
+    );
+}
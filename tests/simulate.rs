@@ -0,0 +1,238 @@
+use std::net::IpAddr;
+
+use tracer::{Config, Hop, Mode, Simulation, StopReason, TraceQuery, TraceRoute, Vertex};
+
+const SOURCE: &str = "192.0.2.2";
+const DESTINATION: &str = "198.51.100.7";
+
+/// Trace the destination through the simulated network of `topology` and
+/// return the hops and why the trace stopped.
+fn trace(topology: &str, config: Config) -> (Vec<Hop>, Option<StopReason>) {
+    let mut traceroute = traceroute(topology, config);
+    let mut iter = traceroute.iter();
+    let hops = iter.by_ref().collect();
+
+    (hops, iter.stop_reason())
+}
+
+fn traceroute(topology: &str, config: Config) -> TraceRoute {
+    let simulation = Simulation::parse(topology).unwrap();
+
+    TraceRoute::with_prober(
+        addr(SOURCE),
+        addr(DESTINATION),
+        config.with_timeout(200),
+        Box::new(simulation),
+    )
+}
+
+fn addr(addr: &str) -> IpAddr {
+    addr.parse().unwrap()
+}
+
+/// The addresses that answered the queries of a hop, `None` for timeouts.
+fn addrs(hop: &Hop) -> Vec<Option<IpAddr>> {
+    hop.queries
+        .iter()
+        .map(|query| match query {
+            TraceQuery::Success(response) => Some(response.addr),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn destination_stops_trace() {
+    let topology = r#"{
+        "hops": [
+            { "routers": [{ "address": "10.0.0.1" }] },
+            { "routers": [{ "address": "10.0.1.1" }] }
+        ]
+    }"#;
+
+    let (hops, stop_reason) = trace(topology, Config::default());
+
+    assert_eq!(stop_reason, Some(StopReason::Completed));
+    assert_eq!(hops.len(), 3);
+    assert_eq!(addrs(&hops[0]), vec![Some(addr("10.0.0.1")); 3]);
+    assert_eq!(addrs(&hops[1]), vec![Some(addr("10.0.1.1")); 3]);
+    assert_eq!(addrs(&hops[2]), vec![Some(addr(DESTINATION)); 3]);
+}
+
+#[test]
+fn gap_limit_stops_trace() {
+    let topology = r#"{
+        "hops": [
+            { "routers": [{ "address": "10.0.0.1" }] },
+            { "silent": true },
+            { "silent": true },
+            { "silent": true },
+            { "silent": true }
+        ]
+    }"#;
+
+    let (hops, stop_reason) = trace(topology, Config::default().with_gap_limit(2));
+
+    assert_eq!(stop_reason, Some(StopReason::GapLimit));
+    assert_eq!(hops.len(), 3);
+    assert_eq!(addrs(&hops[0]), vec![Some(addr("10.0.0.1")); 3]);
+    assert!(hops[1..]
+        .iter()
+        .all(|hop| addrs(hop).iter().all(Option::is_none)));
+}
+
+#[test]
+fn max_hops_stops_trace() {
+    let topology = r#"{
+        "hops": [
+            { "routers": [{ "address": "10.0.0.1" }] },
+            { "routers": [{ "address": "10.0.1.1" }] },
+            { "routers": [{ "address": "10.0.2.1" }] },
+            { "routers": [{ "address": "10.0.3.1" }] }
+        ]
+    }"#;
+
+    let (hops, stop_reason) = trace(topology, Config::default().with_max_hops(2));
+
+    assert_eq!(stop_reason, Some(StopReason::MaxHops));
+    assert_eq!(
+        hops.iter().map(|hop| hop.ttl).collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+    assert_eq!(addrs(&hops[1]), vec![Some(addr("10.0.1.1")); 3]);
+}
+
+#[test]
+fn load_balancers_yield_multipath_vertices() {
+    let topology = r#"{
+        "seed": 7,
+        "hops": [
+            { "routers": [{ "address": "10.0.0.1" }] },
+            { "routers": [{ "address": "10.0.1.1" }, { "address": "10.0.1.2" }] },
+            { "routers": [{ "address": "10.0.2.1" }] }
+        ]
+    }"#;
+
+    let multipath = traceroute(topology, Config::default().with_mode(Mode::Mda)).multipath();
+
+    let vertices = |ttl| {
+        let mut addrs = multipath
+            .vertices
+            .iter()
+            .filter(|vertex| vertex.ttl == ttl)
+            .map(|vertex| vertex.addr)
+            .collect::<Vec<_>>();
+        addrs.sort();
+        addrs
+    };
+    assert_eq!(vertices(1), vec![addr("10.0.0.1")]);
+    assert_eq!(vertices(2), vec![addr("10.0.1.1"), addr("10.0.1.2")]);
+    assert_eq!(vertices(3), vec![addr("10.0.2.1")]);
+    assert!(multipath.vertices.contains(&Vertex {
+        ttl: 4,
+        addr: addr(DESTINATION),
+    }));
+    assert_eq!(
+        multipath.edges.iter().filter(|edge| edge.ttl == 1).count(),
+        2
+    );
+    assert_eq!(multipath.stop_reason, StopReason::Completed);
+}
+
+#[test]
+fn gap_limit_stops_mda_and_pmtu() {
+    let topology = r#"{
+        "hops": [
+            { "routers": [{ "address": "10.0.0.1" }] },
+            { "silent": true },
+            { "silent": true },
+            { "silent": true }
+        ]
+    }"#;
+    let config = Config::default().with_gap_limit(2);
+
+    let multipath = traceroute(topology, config.clone().with_mode(Mode::Mda)).multipath();
+    assert_eq!(multipath.stop_reason, StopReason::GapLimit);
+    assert_eq!(
+        multipath.vertices,
+        vec![Vertex {
+            ttl: 1,
+            addr: addr("10.0.0.1"),
+        }]
+    );
+
+    let (hops, path) = traceroute(topology, config).pmtu(1500);
+    assert_eq!(path.stop_reason, StopReason::GapLimit);
+    assert_eq!(hops.len(), 3);
+}
+
+#[test]
+fn vertices_reached_by_new_flows_are_searched() {
+    // The search of the second hop stops at a low confidence before it has
+    // found all of its routers. The flows sent to search the third hop reach
+    // the others.
+    let topology = r#"{
+        "seed": 3,
+        "hops": [
+            { "routers": [{ "address": "10.0.0.1" }] },
+            { "routers": [
+                { "address": "10.0.1.1" }, { "address": "10.0.1.2" },
+                { "address": "10.0.1.3" }, { "address": "10.0.1.4" },
+                { "address": "10.0.1.5" }, { "address": "10.0.1.6" },
+                { "address": "10.0.1.7" }, { "address": "10.0.1.8" },
+                { "address": "10.0.1.9" }, { "address": "10.0.1.10" },
+                { "address": "10.0.1.11" }, { "address": "10.0.1.12" },
+                { "address": "10.0.1.13" }, { "address": "10.0.1.14" },
+                { "address": "10.0.1.15" }, { "address": "10.0.1.16" }
+            ] },
+            { "routers": [{ "address": "10.0.2.1" }, { "address": "10.0.2.2" }] }
+        ]
+    }"#;
+
+    let multipath = traceroute(
+        topology,
+        Config::default().with_mode(Mode::Mda).with_confidence(0.05),
+    )
+    .multipath();
+
+    let second: Vec<IpAddr> = multipath
+        .vertices
+        .iter()
+        .filter(|vertex| vertex.ttl == 2)
+        .map(|vertex| vertex.addr)
+        .collect();
+    assert_eq!(second.len(), 16);
+    for edge in &multipath.edges {
+        assert!(multipath.vertices.contains(&Vertex {
+            ttl: edge.ttl,
+            addr: edge.from,
+        }));
+    }
+    // The next hops of every router of the second hop were searched.
+    for addr in second {
+        assert!(multipath
+            .edges
+            .iter()
+            .any(|edge| edge.ttl == 2 && edge.from == addr));
+    }
+}
+
+#[test]
+fn rate_limits_and_loss_yield_timeouts() {
+    let topology = r#"{
+        "hops": [
+            { "routers": [{ "address": "10.0.0.1", "rate_limit": 1 }] },
+            { "routers": [{ "address": "10.0.1.1", "loss": 1 }] }
+        ]
+    }"#;
+
+    let (hops, stop_reason) = trace(topology, Config::default());
+
+    assert_eq!(stop_reason, Some(StopReason::Completed));
+    assert_eq!(addrs(&hops[0]), vec![Some(addr("10.0.0.1")), None, None]);
+    assert_eq!(addrs(&hops[1]), vec![None; 3]);
+    assert!(hops[1]
+        .queries
+        .iter()
+        .all(|query| matches!(query, TraceQuery::Timeout)));
+}
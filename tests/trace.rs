@@ -0,0 +1,156 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// A directory of its own for a test, removed when the test ends.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("tracer-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        TempDir(path)
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn tracer(dir: &TempDir, args: &[&str]) {
+    let output = Command::new(env!("CARGO_BIN_EXE_tracer"))
+        .args(args)
+        .current_dir(dir.path())
+        .env_remove("TRACER_IPAPI_KEY")
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "tracer {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn simulated_trace_is_stored() {
+    let dir = TempDir::new("simulated-trace");
+    let topology = dir.path().join("topology.json");
+    fs::write(
+        &topology,
+        r#"{
+            "hops": [
+                { "routers": [{ "address": "10.0.0.1", "latency": { "mean": 1 } }] },
+                { "silent": true },
+                { "routers": [{ "address": "10.0.2.1", "latency": { "mean": 3 } }] }
+            ],
+            "destination": { "latency": { "mean": 4 } }
+        }"#,
+    )
+    .unwrap();
+    let db = dir.path().join("tracer.db");
+    let (topology, db) = (topology.to_str().unwrap(), db.to_str().unwrap());
+
+    tracer(&dir, &["init", "198.51.100.7", "-D", db]);
+    tracer(
+        &dir,
+        &[
+            "trace",
+            "198.51.100.7",
+            "-D",
+            db,
+            "-s",
+            "127.0.0.1",
+            "-P",
+            "icmp",
+            "--simulate",
+            topology,
+            "-W",
+            "200",
+        ],
+    );
+
+    let connection = rusqlite::Connection::open(db).unwrap();
+    let (source, destination, protocol, mode, stop_reason): (
+        String,
+        String,
+        String,
+        String,
+        String,
+    ) = connection
+        .query_row(
+            "SELECT r.source, r.destination, t.protocol, t.mode, t.stop_reason
+             FROM trace t JOIN route r ON r.id = t.route",
+            rusqlite::params![],
+            |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        (source.as_str(), destination.as_str()),
+        ("127.0.0.1", "198.51.100.7")
+    );
+    assert_eq!((protocol.as_str(), mode.as_str()), ("icmp", "classic"));
+    assert_eq!(stop_reason, "completed");
+
+    let mut stmt = connection
+        .prepare("SELECT ttl, query, query_result, addr, rtt_us FROM hop ORDER BY ttl, query")
+        .unwrap();
+    let hops = stmt
+        .query_map(rusqlite::params![], |row| {
+            Ok((
+                row.get::<_, u8>(0)?,
+                row.get::<_, u8>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, Option<i64>>(4)?,
+            ))
+        })
+        .unwrap()
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .unwrap();
+
+    assert_eq!(hops.len(), 12);
+    for (ttl, query, result, addr, rtt_us) in hops {
+        assert!((1..=3).contains(&query));
+        let (expected, rtt) = match ttl {
+            1 => (Some("10.0.0.1"), 1000),
+            2 => (None, 0),
+            3 => (Some("10.0.2.1"), 3000),
+            4 => (Some("198.51.100.7"), 4000),
+            _ => panic!("unexpected hop {}", ttl),
+        };
+        assert_eq!(addr.as_deref(), expected, "hop {}", ttl);
+        match expected {
+            Some(_) => {
+                assert_eq!(result, "success");
+                // Replies are handed over at their round-trip time at the
+                // earliest.
+                let rtt_us = rtt_us.unwrap();
+                assert!(rtt_us >= rtt && rtt_us < rtt + 150_000, "hop {}", ttl);
+            }
+            None => {
+                assert_eq!(result, "timeout");
+                assert_eq!(rtt_us, None);
+            }
+        }
+    }
+}